@@ -0,0 +1,124 @@
+use std::fmt;
+
+/// A MAC address, shown as `00:1a:2b:3c:4d:5e` and stored in that order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mac(pub [u8; 6]);
+
+impl Mac {
+    /// Parses six hex byte pairs separated by `:` or `-`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let separator = if text.contains('-') { '-' } else { ':' };
+        let mut bytes = [0; 6];
+        let mut parts = text.split(separator);
+        for byte in &mut bytes {
+            *byte = hex_byte(parts.next()?)?;
+        }
+        parts.next().is_none().then_some(Mac(bytes))
+    }
+}
+
+impl fmt::Display for Mac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// A UUID, shown as `123e4567-e89b-12d3-a456-426614174000` and stored in
+/// that order, as RFC 9562 lays it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    /// Parses 32 hex digits, hyphenated in the usual groups or not at all,
+    /// optionally in braces or after `urn:uuid:`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.strip_prefix("urn:uuid:").unwrap_or(text);
+        let text = text
+            .strip_prefix('{')
+            .and_then(|text| text.strip_suffix('}'))
+            .unwrap_or(text);
+        let digits = match text.len() {
+            32 => text.to_string(),
+            36 => {
+                let groups = text.split('-').map(str::len).collect::<Vec<_>>();
+                if groups != [8, 4, 4, 4, 12] {
+                    return None;
+                }
+                text.replace('-', "")
+            }
+            _ => return None,
+        };
+        let mut bytes = [0; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = hex_byte(digits.get(2 * i..2 * i + 2)?)?;
+        }
+        Some(Uuid(bytes))
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a byte written as exactly two hex digits.
+fn hex_byte(text: &str) -> Option<u8> {
+    if text.len() != 2 || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u8::from_str_radix(text, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epxr::{Expr, Type};
+    use crate::value::Value;
+
+    #[test]
+    fn macs() {
+        let mac = Mac([0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E]);
+        assert_eq!(Mac::parse("00:1a:2b:3c:4d:5e"), Some(mac));
+        assert_eq!(Mac::parse("00-1A-2B-3C-4D-5E"), Some(mac));
+        assert_eq!(mac.to_string(), "00:1a:2b:3c:4d:5e");
+        assert_eq!(Mac::parse("00:1a:2b:3c:4d"), None);
+        assert_eq!(Mac::parse("00:1a:2b:3c:4d:5e:6f"), None);
+        assert_eq!(Mac::parse("00:1a:2b:3c:4d:+e"), None);
+        assert_eq!(Mac::parse("00:1a-2b:3c:4d:5e"), None);
+    }
+
+    #[test]
+    fn uuids() {
+        let text = "123e4567-e89b-12d3-a456-426614174000";
+        let uuid = Uuid::parse(text).unwrap();
+        assert_eq!(uuid.0[..4], [0x12, 0x3E, 0x45, 0x67]);
+        assert_eq!(uuid.to_string(), text);
+        assert_eq!(Uuid::parse("123E4567E89B12D3A456426614174000"), Some(uuid));
+        assert_eq!(Uuid::parse(&format!("{{{text}}}")), Some(uuid));
+        assert_eq!(Uuid::parse(&format!("urn:uuid:{text}")), Some(uuid));
+        assert_eq!(Uuid::parse("123e4567e-89b-12d3-a456-426614174000"), None);
+        assert_eq!(Uuid::parse("123e4567-e89b-12d3-a456-42661417400"), None);
+    }
+
+    #[test]
+    fn addresses_are_stored_in_network_order() {
+        let expr = Expr::parse("struct A { ip: ipv4, mac: mac }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let ip = Value::from_text(&expr, &Type::Ipv4, "192.168.0.1").unwrap();
+        let mac = Value::Mac(Mac([0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E]));
+        let value = Value::Struct {
+            fields: vec![("ip".into(), ip), ("mac".into(), mac)],
+        };
+        let bytes = [192, 168, 0, 1, 0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E];
+        assert_eq!(value.encode_value(&expr, id).unwrap(), bytes);
+        assert_eq!(expr.read_value(&bytes, id).unwrap(), value);
+    }
+}
@@ -0,0 +1,166 @@
+/// The order the bits of a `#[bits]` layout are packed in, `#[bits]` or
+/// `#[bits("msb")]` for most significant bit first and `#[bits("lsb")]` for
+/// least significant bit first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Fields fill each byte from its top bit down, and are themselves
+    /// written top bit first, as in most network and MIL-STD formats.
+    Msb,
+    /// Fields fill each byte from its bottom bit up, and are themselves
+    /// written bottom bit first.
+    Lsb,
+}
+
+impl BitOrder {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "msb" => Some(BitOrder::Msb),
+            "lsb" => Some(BitOrder::Lsb),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BitOrder::Msb => "msb",
+            BitOrder::Lsb => "lsb",
+        }
+    }
+
+    /// The bit of its byte that bit `position` of a buffer is.
+    fn mask(self, position: usize) -> u8 {
+        match self {
+            BitOrder::Msb => 0x80 >> (position % 8),
+            BitOrder::Lsb => 1 << (position % 8),
+        }
+    }
+}
+
+/// Reads fields of any width from a buffer, from bit `position` on.
+#[derive(Debug, Clone, Copy)]
+pub struct BitReader {
+    pub order: BitOrder,
+    /// The next bit to read, counted from the start of the buffer.
+    pub position: usize,
+}
+
+impl BitReader {
+    /// Reads a `width` bit unsigned number, `None` if the buffer ends first.
+    pub fn read(&mut self, buf: &[u8], width: u32) -> Option<u64> {
+        if (self.position + width as usize).div_ceil(8) > buf.len() {
+            return None;
+        }
+        let mut value = 0;
+        for i in 0..width {
+            let set = buf[self.position / 8] & self.order.mask(self.position) != 0;
+            match self.order {
+                BitOrder::Msb => value = (value << 1) | set as u64,
+                BitOrder::Lsb => value |= (set as u64) << i,
+            }
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Writes fields of any width to a buffer, from bit `position` on, growing
+/// it to the byte the last bit is in.
+#[derive(Debug, Clone, Copy)]
+pub struct BitWriter {
+    pub order: BitOrder,
+    /// The next bit to write, counted from the start of the buffer.
+    pub position: usize,
+}
+
+impl BitWriter {
+    /// Writes the low `width` bits of `value`.
+    pub fn write(&mut self, out: &mut Vec<u8>, width: u32, value: u64) {
+        let end = (self.position + width as usize).div_ceil(8);
+        if out.len() < end {
+            out.resize(end, 0);
+        }
+        for i in 0..width {
+            let bit = match self.order {
+                BitOrder::Msb => width - 1 - i,
+                BitOrder::Lsb => i,
+            };
+            let mask = self.order.mask(self.position);
+            if value >> bit & 1 != 0 {
+                out[self.position / 8] |= mask;
+            } else {
+                out[self.position / 8] &= !mask;
+            }
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epxr::{Expr, Size};
+    use crate::value::{EncodeError, Value};
+
+    /// A `u5`, an `i12` of -3 and a `u7`, as their low bits.
+    const FIELDS: [(u32, u64); 3] = [(5, 0b10110), (12, 0xFFD), (7, 0x55)];
+
+    /// Writes [`FIELDS`] in `order` and checks they read back the same.
+    fn round_trip(order: BitOrder) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut writer = BitWriter { order, position: 0 };
+        for (width, value) in FIELDS {
+            writer.write(&mut out, width, value);
+        }
+        assert_eq!(writer.position, 24);
+        let mut reader = BitReader { order, position: 0 };
+        for (width, value) in FIELDS {
+            assert_eq!(reader.read(&out, width), Some(value));
+        }
+        assert_eq!(reader.read(&out, 1), None);
+        out
+    }
+
+    #[test]
+    fn msb_first() {
+        assert_eq!(round_trip(BitOrder::Msb), [0xB7, 0xFE, 0xD5]);
+    }
+
+    #[test]
+    fn lsb_first() {
+        assert_eq!(round_trip(BitOrder::Lsb), [0xB6, 0xFF, 0xAB]);
+    }
+
+    #[test]
+    fn writes_over_what_is_there() {
+        let mut out = vec![0xFF];
+        let mut writer = BitWriter {
+            order: BitOrder::Msb,
+            position: 2,
+        };
+        writer.write(&mut out, 3, 0b010);
+        assert_eq!(out, [0b1101_0111]);
+    }
+
+    #[test]
+    fn bit_layouts_sign_extend_and_round_up_to_bytes() {
+        let expr = Expr::parse("#[bits] struct A { a: u5, b: i12, c: u3 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        assert_eq!(expr.bit_size_of(id), Some(Size::Fixed(20)));
+        assert_eq!(expr.size_of(id), Some(Size::Fixed(3)));
+        let value = expr.read_value(&[0xB7, 0xFE, 0xA0], id).unwrap();
+        assert_eq!(value.field("b"), Some(&Value::I16(-3)));
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [0xB7, 0xFE, 0xA0]);
+
+        let value = Value::Struct {
+            fields: vec![
+                ("a".into(), Value::U8(32)),
+                ("b".into(), Value::I16(0)),
+                ("c".into(), Value::U8(0)),
+            ],
+        };
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(
+            matches!(error, EncodeError::OutOfRange { path, ty, .. } if path == "a" && ty == "u5")
+        );
+    }
+}
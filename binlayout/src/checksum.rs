@@ -0,0 +1,373 @@
+use std::{fmt, ops::Range};
+
+use thiserror::Error;
+
+use crate::epxr::Field;
+use crate::parser::Literal;
+
+/// A field filled with a checksum of other fields of its struct when
+/// encoding, and checked against them when decoding, declared as
+/// `crc: u16 = crc16_ccitt(version..crc)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    /// Width of the checksum in bits, that of the field's type.
+    pub width: u32,
+    /// Indices of the fields the checksum covers, in the same struct.
+    pub fields: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Crc(Crc),
+    /// The bytes added up, wrapping at the checksum's width.
+    Sum,
+    /// Fletcher's checksum, Fletcher-16 over bytes and Fletcher-32 and -64
+    /// over little-endian 16 and 32 bit words.
+    Fletcher,
+}
+
+/// A CRC in the parameters of the Rocksoft model, with `poly` in normal,
+/// unreflected form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc {
+    pub poly: u64,
+    pub init: u64,
+    pub xorout: u64,
+    pub refin: bool,
+    pub refout: bool,
+    /// The catalogue name the parameters came from.
+    pub name: Option<&'static str>,
+}
+
+/// Named CRCs, by width and parameters: poly, init, xorout, reflected.
+const CATALOGUE: &[(&str, u32, u64, u64, u64, bool)] = &[
+    ("crc8", 8, 0x07, 0x00, 0x00, false),
+    ("crc8_maxim", 8, 0x31, 0x00, 0x00, true),
+    ("crc16_ccitt", 16, 0x1021, 0xFFFF, 0x0000, false),
+    ("crc16_xmodem", 16, 0x1021, 0x0000, 0x0000, false),
+    ("crc16_kermit", 16, 0x1021, 0x0000, 0x0000, true),
+    ("crc16_modbus", 16, 0x8005, 0xFFFF, 0x0000, true),
+    ("crc16_arc", 16, 0x8005, 0x0000, 0x0000, true),
+    ("crc32", 32, 0x04C11DB7, 0xFFFFFFFF, 0xFFFFFFFF, true),
+    ("crc32c", 32, 0x1EDC6F41, 0xFFFFFFFF, 0xFFFFFFFF, true),
+    ("crc32_mpeg2", 32, 0x04C11DB7, 0xFFFFFFFF, 0x00000000, false),
+];
+
+#[derive(Debug, Error)]
+pub enum ChecksumError {
+    #[error("unknown checksum {0}")]
+    UnknownAlgorithm(String),
+    #[error("{algorithm} computes {expected} bits, the field has {found}")]
+    Width {
+        algorithm: String,
+        expected: u32,
+        found: u32,
+    },
+    #[error("{algorithm} does not take {found} bit fields")]
+    UnsupportedWidth { algorithm: String, found: u32 },
+    #[error("{algorithm} has no parameter {name}")]
+    UnknownParameter { algorithm: String, name: String },
+    #[error("invalid value {value} for {name}")]
+    InvalidParameter { name: String, value: Literal },
+    #[error("crc needs a poly")]
+    MissingPoly,
+    #[error("a checksum has to be stored in an unsigned integer")]
+    InvalidType,
+    #[error("the checksum is part of its own range")]
+    CoversItself,
+    #[error("the checksum covers {0}, which depends on it in turn")]
+    Cycle(String),
+    #[error("{0} refers to it, but checksums are only computed once their struct is written")]
+    Referenced(String),
+}
+
+impl Algorithm {
+    /// Looks up the algorithm `name` for a `width` bit field. `crc` takes the
+    /// `poly`, `init`, `xorout`, `refin` and `refout` parameters, the rest
+    /// take none.
+    pub fn new(
+        name: &str,
+        width: u32,
+        params: &[(String, Literal)],
+    ) -> Result<Self, ChecksumError> {
+        let unknown_parameter = |param: &String| ChecksumError::UnknownParameter {
+            algorithm: name.to_string(),
+            name: param.clone(),
+        };
+        let algorithm = match name {
+            "crc" => {
+                let mask = mask(width);
+                let mut crc = Crc {
+                    poly: 0,
+                    init: 0,
+                    xorout: 0,
+                    refin: false,
+                    refout: false,
+                    name: None,
+                };
+                let mut poly = None;
+                for (param, value) in params {
+                    let invalid = || ChecksumError::InvalidParameter {
+                        name: param.clone(),
+                        value: *value,
+                    };
+                    let Literal::Int(int) = *value else {
+                        return Err(invalid());
+                    };
+                    let bits = u64::try_from(int)
+                        .ok()
+                        .filter(|bits| bits & !mask == 0)
+                        .ok_or_else(invalid);
+                    let flag = match int {
+                        0 | 1 => Ok(int == 1),
+                        _ => Err(invalid()),
+                    };
+                    match param.as_str() {
+                        "poly" => poly = Some(bits?),
+                        "init" => crc.init = bits?,
+                        "xorout" => crc.xorout = bits?,
+                        "refin" => crc.refin = flag?,
+                        "refout" => crc.refout = flag?,
+                        _ => return Err(unknown_parameter(param)),
+                    }
+                }
+                crc.poly = poly.ok_or(ChecksumError::MissingPoly)?;
+                return Ok(Algorithm::Crc(crc));
+            }
+            "sum" => Algorithm::Sum,
+            "fletcher" => {
+                if !matches!(width, 16 | 32 | 64) {
+                    return Err(ChecksumError::UnsupportedWidth {
+                        algorithm: name.to_string(),
+                        found: width,
+                    });
+                }
+                Algorithm::Fletcher
+            }
+            _ => {
+                let &(name, expected, poly, init, xorout, reflected) = CATALOGUE
+                    .iter()
+                    .find(|(entry, ..)| *entry == name)
+                    .ok_or_else(|| ChecksumError::UnknownAlgorithm(name.to_string()))?;
+                if expected != width {
+                    return Err(ChecksumError::Width {
+                        algorithm: name.to_string(),
+                        expected,
+                        found: width,
+                    });
+                }
+                Algorithm::Crc(Crc {
+                    poly,
+                    init,
+                    xorout,
+                    refin: reflected,
+                    refout: reflected,
+                    name: Some(name),
+                })
+            }
+        };
+        match params.first() {
+            Some((param, _)) => Err(unknown_parameter(param)),
+            None => Ok(algorithm),
+        }
+    }
+
+    /// Computes the `width` bit checksum of `data`.
+    pub fn compute(&self, width: u32, data: &[u8]) -> u64 {
+        match self {
+            Algorithm::Crc(crc) => crc.compute(width, data),
+            Algorithm::Sum => {
+                data.iter()
+                    .fold(0u64, |sum, byte| sum.wrapping_add(*byte as u64))
+                    & mask(width)
+            }
+            Algorithm::Fletcher => {
+                // Fletcher-N adds up N/2 bit words modulo 2^(N/2) - 1.
+                let half = width / 2;
+                let modulus = mask(half);
+                let word_len = (half / 8).max(1) as usize;
+                let (mut a, mut b) = (0u64, 0u64);
+                for word in data.chunks(word_len) {
+                    let word = word
+                        .iter()
+                        .rev()
+                        .fold(0u64, |word, byte| (word << 8) | *byte as u64);
+                    a = (a + word) % modulus;
+                    b = (b + a) % modulus;
+                }
+                (b << half) | a
+            }
+        }
+    }
+}
+
+impl Crc {
+    fn compute(&self, width: u32, data: &[u8]) -> u64 {
+        let mask = mask(width);
+        let top = 1u64 << (width - 1);
+        let mut crc = self.init;
+        for byte in data {
+            let byte = if self.refin {
+                byte.reverse_bits()
+            } else {
+                *byte
+            };
+            crc ^= (byte as u64) << (width - 8);
+            for _ in 0..8 {
+                crc = if crc & top != 0 {
+                    (crc << 1) ^ self.poly
+                } else {
+                    crc << 1
+                };
+            }
+            crc &= mask;
+        }
+        if self.refout {
+            crc = crc.reverse_bits() >> (64 - width);
+        }
+        (crc ^ self.xorout) & mask
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Crc(crc) => write!(f, "{}", crc.name.unwrap_or("crc")),
+            Algorithm::Sum => write!(f, "sum"),
+            Algorithm::Fletcher => write!(f, "fletcher"),
+        }
+    }
+}
+
+impl Checksum {
+    /// Describes the checksum with the names of the fields it covers, e.g.
+    /// `crc16_ccitt(version..=payload)`.
+    pub fn describe(&self, fields: &[Field]) -> String {
+        let first = &fields[self.fields.start].name;
+        let last = &fields[self.fields.end - 1].name;
+        let params = match &self.algorithm {
+            Algorithm::Crc(crc) if crc.name.is_none() => format!(
+                ", poly = {:#X}, init = {:#X}, xorout = {:#X}, refin = {}, refout = {}",
+                crc.poly, crc.init, crc.xorout, crc.refin as u8, crc.refout as u8
+            ),
+            _ => String::new(),
+        };
+        format!("{}({first}..={last}{params})", self.algorithm)
+    }
+}
+
+fn mask(width: u32) -> u64 {
+    u64::MAX >> (64 - width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epxr::{Expr, ReadError};
+    use crate::value::Value;
+
+    const CHECK: &[u8] = b"123456789";
+
+    fn compute(name: &str, width: u32, data: &[u8]) -> u64 {
+        Algorithm::new(name, width, &[])
+            .unwrap()
+            .compute(width, data)
+    }
+
+    #[test]
+    fn catalogue_check_values() {
+        let expected = [
+            ("crc8", 8, 0xF4),
+            ("crc8_maxim", 8, 0xA1),
+            ("crc16_ccitt", 16, 0x29B1),
+            ("crc16_xmodem", 16, 0x31C3),
+            ("crc16_kermit", 16, 0x2189),
+            ("crc16_modbus", 16, 0x4B37),
+            ("crc16_arc", 16, 0xBB3D),
+            ("crc32", 32, 0xCBF43926),
+            ("crc32c", 32, 0xE3069283),
+            ("crc32_mpeg2", 32, 0x0376E6E7),
+        ];
+        for (name, width, check) in expected {
+            assert_eq!(compute(name, width, CHECK), check, "{name}");
+        }
+    }
+
+    #[test]
+    fn crc_with_parameters() {
+        let params = [
+            ("poly".to_string(), Literal::Int(0x1021)),
+            ("init".to_string(), Literal::Int(0xFFFF)),
+        ];
+        let crc = Algorithm::new("crc", 16, &params).unwrap();
+        assert_eq!(crc.compute(16, CHECK), 0x29B1);
+
+        let error = Algorithm::new("crc", 16, &[]).unwrap_err();
+        assert!(matches!(error, ChecksumError::MissingPoly));
+        let params = [("poly".to_string(), Literal::Int(0x1_0000))];
+        let error = Algorithm::new("crc", 16, &params).unwrap_err();
+        assert!(matches!(error, ChecksumError::InvalidParameter { .. }));
+        let params = [("poly".to_string(), Literal::Int(7))];
+        let error = Algorithm::new("crc8", 8, &params).unwrap_err();
+        assert!(matches!(error, ChecksumError::UnknownParameter { .. }));
+    }
+
+    #[test]
+    fn fletcher_and_sum() {
+        assert_eq!(compute("fletcher", 16, b"abcde"), 0xC8F0);
+        assert_eq!(compute("fletcher", 32, b"abcde"), 0xF04FC729);
+        assert_eq!(compute("fletcher", 64, b"abcde"), 0xC8C6C527646362C6);
+        let error = Algorithm::new("fletcher", 8, &[]).unwrap_err();
+        assert!(matches!(
+            error,
+            ChecksumError::UnsupportedWidth { found: 8, .. }
+        ));
+        // Sums wrap at their width.
+        assert_eq!(compute("sum", 8, &[0xFF, 0x02]), 0x01);
+        assert_eq!(compute("sum", 16, &[0xFF, 0x02]), 0x101);
+    }
+
+    #[test]
+    fn width_has_to_match_the_catalogue() {
+        let error = Algorithm::new("crc32", 16, &[]).unwrap_err();
+        assert!(matches!(
+            error,
+            ChecksumError::Width {
+                expected: 32,
+                found: 16,
+                ..
+            }
+        ));
+        let error = Algorithm::new("md5", 16, &[]).unwrap_err();
+        assert!(matches!(error, ChecksumError::UnknownAlgorithm(name) if name == "md5"));
+    }
+
+    #[test]
+    fn mismatches_are_reported_when_decoding() {
+        let expr = Expr::parse("struct A { a: u8, b: u8, crc: u8 = crc8(a..=b) }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = Value::Struct {
+            fields: vec![("a".into(), Value::U8(1)), ("b".into(), Value::U8(2))],
+        };
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [1, 2, 0x1B]);
+        assert!(expr.read_value(&[1, 2, 0x1B], id).is_ok());
+
+        let error = expr.read_value(&[1, 3, 0x1B], id).unwrap_err();
+        assert!(matches!(
+            error,
+            ReadError::ChecksumMismatch { path, expected: 0x1C, found: 0x1B, .. } if path == "crc"
+        ));
+        assert!(expr.read_value(&[1, 3, 0x1C], id).is_ok());
+
+        // A stale checksum is replaced when encoding.
+        let value = Value::Struct {
+            fields: vec![
+                ("a".into(), Value::U8(1)),
+                ("b".into(), Value::U8(3)),
+                ("crc".into(), Value::U8(0x1B)),
+            ],
+        };
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [1, 3, 0x1C]);
+    }
+}
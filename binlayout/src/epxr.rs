@@ -1,5 +1,29 @@
-use std::{collections::HashMap, hash::Hash, io};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    iter::Sum,
+    mem,
+    net::{Ipv4Addr, Ipv6Addr},
+    ops::Add,
+};
 
+use half::{bf16, f16};
+use thiserror::Error;
+
+use crate::address::{Mac, Uuid};
+use crate::bits::{BitOrder, BitReader};
+use crate::checksum::Checksum;
+use crate::expression::{EvalError, Expression, Scalar};
+use crate::fixed::Fixed;
+use crate::length::Length;
+use crate::parser::Literal;
+use crate::pointer::{Base, Location, Pointer, Regions};
+use crate::scaling::Scaling;
+use crate::time::Timestamp;
+use crate::utils::field_path;
+use crate::validate::Constraint;
 use crate::value::Value;
 
 #[repr(transparent)]
@@ -12,30 +36,195 @@ impl LayoutId {
     }
 }
 
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct EnumId(u32);
+
+impl EnumId {
+    pub fn new(id: u32) -> Self {
+        EnumId(id)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Expr {
     pub layouts: HashMap<LayoutId, Struct>,
     pub layout_ids: HashMap<String, LayoutId>,
+    /// Type aliases by qualified name, resolved to the type they stand for.
+    pub aliases: HashMap<String, Type>,
+    pub enums: HashMap<EnumId, Enumeration>,
+    pub enum_ids: HashMap<String, EnumId>,
+}
+
+/// The encoded size of a layout or type, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Fixed(usize),
+    /// Depends on the data, e.g. because of a `varint`. Every instance takes
+    /// at least `min` bytes.
+    Variable {
+        min: usize,
+    },
+}
+
+impl Size {
+    /// The size if it does not depend on the data.
+    pub fn fixed(self) -> Option<usize> {
+        match self {
+            Size::Fixed(size) => Some(size),
+            Size::Variable { .. } => None,
+        }
+    }
+
+    pub fn min(self) -> usize {
+        match self {
+            Size::Fixed(size) | Size::Variable { min: size } => size,
+        }
+    }
+
+    fn times(self, n: usize) -> Size {
+        match self {
+            Size::Fixed(size) => Size::Fixed(size * n),
+            Size::Variable { min } => Size::Variable { min: min * n },
+        }
+    }
+
+    /// A size in bits, rounded up to whole bytes.
+    fn bytes(self) -> Size {
+        match self {
+            Size::Fixed(bits) => Size::Fixed(bits.div_ceil(8)),
+            Size::Variable { min } => Size::Variable {
+                min: min.div_ceil(8),
+            },
+        }
+    }
+}
+
+impl Add for Size {
+    type Output = Size;
+
+    fn add(self, other: Size) -> Size {
+        match (self, other) {
+            (Size::Fixed(a), Size::Fixed(b)) => Size::Fixed(a + b),
+            (a, b) => Size::Variable {
+                min: a.min() + b.min(),
+            },
+        }
+    }
+}
+
+impl Sum for Size {
+    fn sum<I: Iterator<Item = Size>>(iter: I) -> Size {
+        iter.fold(Size::Fixed(0), Add::add)
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Size::Fixed(size) => write!(f, "{size} bytes"),
+            Size::Variable { min } => write!(f, "at least {min} bytes"),
+        }
+    }
 }
 
 impl Expr {
-    pub fn size_of(&self, id: LayoutId) -> Option<usize> {
-        self.layouts
-            .get(&id)?
+    pub fn size_of(&self, id: LayoutId) -> Option<Size> {
+        let layout = self.layouts.get(&id)?;
+        if layout.bits.is_some() {
+            // Starts on a byte and fills the last one up.
+            return self.bit_size_of(id).map(Size::bytes);
+        }
+        if layout.union {
+            // Members are fixed-size, the union is as large as the largest.
+            return layout
+                .fields
+                .iter()
+                .try_fold(0, |size, field| {
+                    let member = self.size_of_type(&field.ty)?.fixed()?;
+                    Some(size.max(member))
+                })
+                .map(Size::Fixed);
+        }
+        layout
             .fields
             .iter()
-            .map(|(_, ty)| match ty {
-                Type::Struct(layout_id) => self.size_of(*layout_id),
-                Type::I8 => Some(1),
-                Type::I16 => Some(2),
-                Type::I32 => Some(4),
-                Type::I64 => Some(8),
-                Type::F32 => Some(4),
-                Type::F64 => Some(8),
+            .map(|field| match (&field.location, &field.condition) {
+                // Located fields are not part of the layout's own bytes.
+                (Some(_), _) => self.size_of_type(&field.ty).map(|_| Size::Fixed(0)),
+                // Conditional fields may take no space at all.
+                (None, Some(_)) => self
+                    .size_of_type(&field.ty)
+                    .map(|_| Size::Variable { min: 0 }),
+                (None, None) => self.size_of_type(&field.ty),
             })
             .sum()
     }
 
+    /// The size of the layout `id` in bits. `#[bits]` layouts may end part
+    /// way through a byte, which [`Expr::size_of`] rounds up.
+    pub fn bit_size_of(&self, id: LayoutId) -> Option<Size> {
+        let layout = self.layouts.get(&id)?;
+        if layout.bits.is_none() {
+            return self.size_of(id).map(|size| size.times(8));
+        }
+        layout
+            .fields
+            .iter()
+            .map(|field| match field.condition {
+                Some(_) => self
+                    .bit_size_of_type(&field.ty)
+                    .map(|_| Size::Variable { min: 0 }),
+                None => self.bit_size_of_type(&field.ty),
+            })
+            .sum()
+    }
+
+    /// The size of a value of type `ty` in bits.
+    pub fn bit_size_of_type(&self, ty: &Type) -> Option<Size> {
+        match ty {
+            Type::Struct(id) => self.bit_size_of(*id),
+            Type::Bool(storage) => self.bit_size_of_type(storage),
+            Type::Enum(id) => self.bit_size_of_type(&self.enums.get(id)?.storage),
+            Type::Array(element, Some(len)) => Some(self.bit_size_of_type(element)?.times(*len)),
+            Type::Array(_, None) => Some(Size::Variable { min: 0 }),
+            Type::Bits { width, .. } => Some(Size::Fixed(*width as usize)),
+            Type::Timestamp(timestamp) => self.bit_size_of_type(&timestamp.storage),
+            _ => Some(self.size_of_type(ty)?.times(8)),
+        }
+    }
+
+    pub fn size_of_type(&self, ty: &Type) -> Option<Size> {
+        let size = match ty {
+            Type::Struct(layout_id) => return self.size_of(*layout_id),
+            Type::Bool(storage) => return self.size_of_type(storage),
+            Type::Enum(id) => return self.size_of_type(&self.enums.get(id)?.storage),
+            Type::Array(element, Some(len)) => {
+                return Some(self.size_of_type(element)?.times(*len));
+            }
+            Type::Array(_, None) => return Some(Size::Variable { min: 0 }),
+            // Just the offset, the target is elsewhere.
+            Type::Pointer(pointer) => return self.size_of_type(&pointer.storage),
+            Type::Timestamp(timestamp) => return self.size_of_type(&timestamp.storage),
+            Type::VarInt | Type::UVarInt | Type::ZigZag => return Some(Size::Variable { min: 1 }),
+            Type::Fixed(fixed) => fixed.bits() as usize / 8,
+            Type::Bits { width, .. } => (*width as usize).div_ceil(8),
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 => 4,
+            Type::I64 | Type::U64 => 8,
+            Type::F16 | Type::BF16 => 2,
+            Type::F32 => 4,
+            Type::F64 => 8,
+            Type::Ipv4 => 4,
+            Type::Mac => 6,
+            Type::Ipv6 | Type::Uuid => 16,
+            Type::Bytes(Some(len)) => *len,
+            Type::Bytes(None) => return Some(Size::Variable { min: 0 }),
+        };
+        Some(Size::Fixed(size))
+    }
+
     pub fn get_id(&self, name: &str) -> Option<LayoutId> {
         self.layout_ids.get(name).copied()
     }
@@ -44,94 +233,1389 @@ impl Expr {
         self.layouts.get(&id)
     }
 
+    /// Looks up a layout by its fully qualified name, e.g. `nav::Header`.
     pub fn get(&self, name: &str) -> Option<&Struct> {
         self.get_id(name).and_then(|id| self.get_type(id))
     }
 
-    pub fn read_value(&self, buf: &[u8], layout_id: LayoutId) -> Option<Value> {
-        self.read_value_helper(buf, layout_id).map(|v| v.0)
+    /// Looks up `name` as written inside the namespace `scope`. The innermost
+    /// enclosing namespace that declares the name wins, so inside `nav::gps`
+    /// the name `Header` may refer to `nav::gps::Header`, `nav::Header` or
+    /// `Header`.
+    pub fn resolve(&self, scope: &str, name: &str) -> Option<LayoutId> {
+        lookup_scoped(&self.layout_ids, "", scope, name).map(|(_, id)| *id)
+    }
+
+    /// Builds an instance of the layout `id` from the declared constants and
+    /// defaults, zero-initializing every other field. Conditional fields are
+    /// left out when their condition does not hold for the defaults, and
+    /// padding is left out altogether. Pointers that would lead back to a
+    /// struct being built are null.
+    pub fn default_value(&self, id: LayoutId) -> Option<Value> {
+        self.default_struct(id, &mut Vec::new())
     }
-    /// Reads a `Value` from a byte buffer.
-    fn read_value_helper(&self, buf: &[u8], layout_id: LayoutId) -> Option<(Value, usize)> {
-        let mut offset = 0;
-        let layout = self.get_type(layout_id)?;
 
+    /// Like [`Expr::default_value`], inside the structs `building`.
+    fn default_struct(&self, id: LayoutId, building: &mut Vec<LayoutId>) -> Option<Value> {
+        let layout = self.get_type(id)?;
+        building.push(id);
         let mut fields = Vec::with_capacity(layout.fields.len());
-        for (name, ty) in &layout.fields {
-            let val = match ty {
-                Type::I8 => {
-                    if buf.len() < offset + 1 {
-                        return None;
+        for field in &layout.fields {
+            if field.padding {
+                continue;
+            }
+            if let Some(condition) = &field.condition
+                && !condition.check_fields(&fields).unwrap_or(false)
+            {
+                continue;
+            }
+            // Computed ones as the defaults before them make them.
+            let derived = field
+                .derived
+                .as_ref()
+                .and_then(|derived| self.derive(&field.ty, derived, &fields).ok());
+            let given = derived
+                .as_ref()
+                .or(field.constant.as_ref())
+                .or(field.default.as_ref());
+            let value = match given {
+                Some(value) => value.clone(),
+                None => {
+                    // As long as the defaults before it make it.
+                    let value = match &field.size {
+                        Some(size) => {
+                            let len = match size.eval_fields(&fields) {
+                                Ok(Scalar::Int(len)) => usize::try_from(len).unwrap_or(0),
+                                _ => 0,
+                            };
+                            self.default_in(&field.ty.sized(len), building)?
+                        }
+                        None => self.default_in(&field.ty, building)?,
+                    };
+                    match &field.scaling {
+                        Some(scaling) => scaling.apply(value),
+                        None => value,
                     }
-                    let val = i8::from_le_bytes([buf[offset]]);
-                    offset += 1;
-                    Value::I8(val as i64)
                 }
-                Type::I16 => {
-                    if buf.len() < offset + 2 {
-                        return None;
-                    }
-                    let val = i16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap());
-                    offset += 2;
-                    Value::I16(val as i64)
+            };
+            fields.push((field.name.clone(), value));
+            // Unions are encoded from a single member.
+            if layout.union {
+                break;
+            }
+        }
+        building.pop();
+        Some(Value::Struct { fields })
+    }
+
+    /// The value of a field of type `ty` that is computed by `derived` from
+    /// the fields before it.
+    pub fn derive(
+        &self,
+        ty: &Type,
+        derived: &Expression,
+        scope: &[(String, Value)],
+    ) -> Result<Value, EvalError> {
+        let scalar = derived.eval_fields(scope)?;
+        let value = match (ty, scalar) {
+            (Type::Bool(_), Scalar::Bool(v)) => Some(Value::Bool(v)),
+            (_, Scalar::Int(v)) => Literal::Int(v).to_value(self, ty),
+            (_, Scalar::Float(v)) => Literal::Float(v).to_value(self, ty).map(|v| v.rounded()),
+            (_, Scalar::Bool(_)) => None,
+        };
+        value.ok_or_else(|| EvalError::OutOfRange {
+            value: scalar.to_string(),
+            ty: ty.name(self).into_owned(),
+        })
+    }
+
+    /// The value `raw` of the enum `id`, named if it is declared. `None` if
+    /// `raw` does not fit the enum's storage type.
+    pub fn enum_value(&self, id: EnumId, raw: i128) -> Option<Value> {
+        let enumeration = self.enums.get(&id)?;
+        Some(Value::Enum {
+            raw: Box::new(Literal::Int(raw).to_value(self, &enumeration.storage)?),
+            name: enumeration.name_of(raw).map(str::to_string),
+        })
+    }
+
+    /// Whether a value of `ty` holds an instance of the layout `id` in place,
+    /// rather than through a pointer.
+    fn holds(&self, ty: &Type, id: LayoutId) -> bool {
+        match ty {
+            Type::Struct(inner) => {
+                *inner == id
+                    || self.get_type(*inner).is_some_and(|layout| {
+                        layout.fields.iter().any(|field| self.holds(&field.ty, id))
+                    })
+            }
+            Type::Array(element, _) => self.holds(element, id),
+            _ => false,
+        }
+    }
+
+    /// The zero value of `ty`, or the default instance for structs.
+    pub fn default_for_type(&self, ty: &Type) -> Option<Value> {
+        self.default_in(ty, &mut Vec::new())
+    }
+
+    fn default_in(&self, ty: &Type, building: &mut Vec<LayoutId>) -> Option<Value> {
+        Some(match ty {
+            Type::Bool(_) => Value::Bool(false),
+            Type::Fixed(fixed) => Value::Fixed {
+                raw: 0,
+                frac_bits: fixed.frac_bits,
+            },
+            Type::Timestamp(timestamp) => Value::Timestamp {
+                raw: 0,
+                epoch: timestamp.epoch,
+                unit: timestamp.unit,
+            },
+            Type::VarInt | Type::ZigZag => Value::I64(0),
+            Type::UVarInt => Value::U64(0),
+            Type::Bits { .. } => Literal::Int(0).to_value(self, ty)?,
+            Type::I8 => Value::I8(0),
+            Type::I16 => Value::I16(0),
+            Type::I32 => Value::I32(0),
+            Type::I64 => Value::I64(0),
+            Type::U8 => Value::U8(0),
+            Type::U16 => Value::U16(0),
+            Type::U32 => Value::U32(0),
+            Type::U64 => Value::U64(0),
+            Type::F16 => Value::F16(0.0),
+            Type::BF16 => Value::BF16(0.0),
+            Type::F32 => Value::F32(0.0),
+            Type::F64 => Value::F64(0.0),
+            Type::Ipv4 => Value::Ipv4(Ipv4Addr::UNSPECIFIED),
+            Type::Ipv6 => Value::Ipv6(Ipv6Addr::UNSPECIFIED),
+            Type::Mac => Value::Mac(Mac([0; 6])),
+            Type::Uuid => Value::Uuid(Uuid([0; 16])),
+            Type::Bytes(len) => Value::Bytes(vec![0; len.unwrap_or(0)]),
+            Type::Struct(id) => self.default_struct(*id, building)?,
+            Type::Enum(id) => {
+                let enumeration = self.enums.get(id)?;
+                // Zero if it has a name, the first declared value otherwise.
+                let (_, value) = enumeration
+                    .values
+                    .iter()
+                    .find(|(_, value)| *value == 0)
+                    .or(enumeration.values.first())?;
+                self.enum_value(*id, *value)?
+            }
+            Type::Array(element, len) => {
+                Value::Array(vec![self.default_in(element, building)?; len.unwrap_or(0)])
+            }
+            Type::Pointer(pointer) => {
+                let recursive = building.iter().any(|id| self.holds(&pointer.target, *id));
+                let target = if recursive {
+                    None
+                } else {
+                    Some(Box::new(self.default_in(&pointer.target, building)?))
+                };
+                Value::Pointer {
+                    offset: None,
+                    target,
                 }
-                Type::I32 => {
-                    if buf.len() < offset + 4 {
-                        return None;
-                    }
-                    let val = i32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
-                    offset += 4;
-                    Value::I32(val as i64)
+            }
+        })
+    }
+
+    pub fn read_value(&self, buf: &[u8], layout_id: LayoutId) -> Result<Value, ReadError> {
+        self.read_value_with(buf, layout_id, ReadOptions::default())
+    }
+
+    /// Like [`Expr::read_value`], with `options` deciding how strictly the
+    /// bytes are checked.
+    pub fn read_value_with(
+        &self,
+        buf: &[u8],
+        layout_id: LayoutId,
+        options: ReadOptions,
+    ) -> Result<Value, ReadError> {
+        let mut cx = ReadContext {
+            options,
+            base: 0,
+            regions: Regions::default(),
+            bits: None,
+            targets: Vec::new(),
+        };
+        let mut offset = 0;
+        let value = self.read_value_helper(buf, &mut offset, layout_id, "", &mut cx)?;
+        // The layout itself must not overlap what its pointers point to.
+        let name = Type::Struct(layout_id).name(self).into_owned();
+        cx.regions
+            .claim(0..offset, &name, false)
+            .map_err(|path| ReadError::Overlap { path, other: name })?;
+        Ok(value)
+    }
+    /// Reads a `Value` from a byte buffer.
+    fn read_value_helper(
+        &self,
+        buf: &[u8],
+        offset: &mut usize,
+        layout_id: LayoutId,
+        path: &str,
+        cx: &mut ReadContext,
+    ) -> Result<Value, ReadError> {
+        let layout = self
+            .get_type(layout_id)
+            .ok_or(ReadError::UnknownLayout(layout_id))?;
+
+        let outer_base = mem::replace(&mut cx.base, *offset);
+        if layout.union {
+            let value = self.read_union(buf, offset, layout, path, cx);
+            cx.base = outer_base;
+            return value;
+        }
+        // `#[bits]` layouts start on a byte, unless they are part of another.
+        let outer_bits = cx.bits;
+        if let Some(order) = layout.bits {
+            let position = cx.bits.map_or(*offset * 8, |reader| reader.position);
+            cx.bits = Some(BitReader { order, position });
+        }
+        let mut fields = Vec::with_capacity(layout.fields.len());
+        // Where each field starts, for the ranges checksums cover.
+        let mut bounds = Vec::with_capacity(layout.fields.len() + 1);
+        for field in &layout.fields {
+            bounds.push(*offset);
+            let path = field_path(path, &field.name);
+            if let Some(condition) = &field.condition {
+                let present =
+                    condition
+                        .check_fields(&fields)
+                        .map_err(|error| ReadError::Condition {
+                            path: path.clone(),
+                            error,
+                        })?;
+                if !present {
+                    continue;
                 }
-                Type::I64 => {
-                    if buf.len() < offset + 8 {
-                        return None;
-                    }
-                    let val = i64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap());
-                    offset += 8;
-                    Value::I64(val)
+            }
+            // `bytes` and arrays sized by the fields before them.
+            let sized;
+            let ty = match &field.size {
+                Some(size) => {
+                    let size = size.eval_fields(&fields).map_err(|error| ReadError::Size {
+                        path: path.clone(),
+                        error,
+                    })?;
+                    let Scalar::Int(size) = size else {
+                        unreachable!("sizes are type checked as integers")
+                    };
+                    let len = usize::try_from(size).map_err(|_| ReadError::InvalidSize {
+                        path: path.clone(),
+                        size,
+                    })?;
+                    sized = field.ty.sized(len);
+                    &sized
+                }
+                None => &field.ty,
+            };
+            let mut val = match &field.location {
+                Some(location) => {
+                    let relative = location.offset.eval_fields(&fields).map_err(|error| {
+                        ReadError::Location {
+                            path: path.clone(),
+                            error,
+                        }
+                    })?;
+                    let Scalar::Int(relative) = relative else {
+                        unreachable!("offsets are type checked as integers")
+                    };
+                    let start = absolute(buf, location.base, cx.base, relative, &path)?;
+                    self.read_at(buf, start, ty, &path, false, cx)?
                 }
-                Type::F32 => {
-                    if buf.len() < offset + 4 {
-                        return None;
+                None => self.read_type(buf, offset, ty, &path, cx)?,
+            };
+            if let Some(scaling) = &field.scaling {
+                val = scaling.apply(val);
+            }
+            if let Some(constant) = &field.constant
+                && val != *constant
+            {
+                return Err(ReadError::ConstMismatch {
+                    path,
+                    expected: constant.to_string(),
+                    found: val.to_string(),
+                });
+            }
+            if let Some(derived) = &field.derived {
+                let expected = self.derive(&field.ty, derived, &fields).map_err(|error| {
+                    ReadError::Derived {
+                        path: path.clone(),
+                        error,
                     }
-                    let val = f32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
-                    offset += 4;
-                    Value::F32(val as f64)
+                })?;
+                if val != expected {
+                    return Err(ReadError::DerivedMismatch {
+                        path,
+                        expression: derived.to_string(),
+                        expected: expected.to_string(),
+                        found: val.to_string(),
+                    });
                 }
-                Type::F64 => {
-                    if buf.len() < offset + 8 {
-                        return None;
+            }
+            if field.padding && !cx.options.keep_padding {
+                continue;
+            }
+            // Only the chosen view of a union.
+            if let Some(view) = &field.view
+                && let Value::Struct { fields: members } = &mut val
+            {
+                members.retain(|(name, _)| name == view);
+            }
+            fields.push((field.name.to_string(), val));
+        }
+        bounds.push(*offset);
+
+        let value_of = |name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value)
+        };
+        for field in &layout.fields {
+            let (Some(length), Some(value)) = (&field.length, value_of(&field.name)) else {
+                continue;
+            };
+            let expected =
+                length.measure(&bounds, value_of(&layout.fields[length.fields.start].name));
+            let Some(Scalar::Int(found)) = Scalar::of(value) else {
+                unreachable!("lengths are stored as integers")
+            };
+            if found != expected {
+                return Err(ReadError::LengthMismatch {
+                    path: field_path(path, &field.name),
+                    length: length.describe(&layout.fields),
+                    expected,
+                    found,
+                });
+            }
+        }
+        for field in &layout.fields {
+            let (Some(checksum), Some((_, value))) = (
+                &field.checksum,
+                fields.iter().find(|(name, _)| *name == field.name),
+            ) else {
+                continue;
+            };
+            let data = &buf[bounds[checksum.fields.start]..bounds[checksum.fields.end]];
+            let expected = checksum.algorithm.compute(checksum.width, data);
+            let Some(Scalar::Int(found)) = Scalar::of(value) else {
+                unreachable!("checksums are stored as integers")
+            };
+            if found != expected as i128 {
+                return Err(ReadError::ChecksumMismatch {
+                    path: field_path(path, &field.name),
+                    algorithm: checksum.algorithm.to_string(),
+                    expected,
+                    found: found as u64,
+                });
+            }
+        }
+
+        if layout.bits.is_some() {
+            let position = cx.bits.map_or(0, |reader| reader.position);
+            cx.bits = outer_bits.map(|outer| BitReader { position, ..outer });
+            if cx.bits.is_none() {
+                *offset = position.div_ceil(8);
+            }
+        }
+        cx.base = outer_base;
+        Ok(Value::Struct { fields })
+    }
+
+    /// The value of an integer or float type `ty` that is `width` bits wide,
+    /// from its bits.
+    fn bits_value(&self, ty: &Type, width: u32, raw: u64) -> Value {
+        let value = match ty {
+            Type::F16 => return Value::F16(f16::from_bits(raw as u16).to_f64()),
+            Type::BF16 => return Value::BF16(bf16::from_bits(raw as u16).to_f64()),
+            Type::F32 => return Value::F32(f32::from_bits(raw as u32) as f64),
+            Type::F64 => return Value::F64(f64::from_bits(raw)),
+            Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::Bits { signed: true, .. } => {
+                // Sign extended from the top bit.
+                ((raw << (64 - width)) as i64 >> (64 - width)) as i128
+            }
+            _ => raw as i128,
+        };
+        Literal::Int(value)
+            .to_value(self, ty)
+            .expect("integers of the type's width fit it")
+    }
+
+    /// Reads every member of a union from the same bytes, advancing the
+    /// offset past the largest.
+    fn read_union(
+        &self,
+        buf: &[u8],
+        offset: &mut usize,
+        layout: &Struct,
+        path: &str,
+        cx: &mut ReadContext,
+    ) -> Result<Value, ReadError> {
+        let start = *offset;
+        let mut fields = Vec::with_capacity(layout.fields.len());
+        for field in &layout.fields {
+            let path = field_path(path, &field.name);
+            let mut end = start;
+            let mut val = self.read_type(buf, &mut end, &field.ty, &path, cx)?;
+            if let Some(scaling) = &field.scaling {
+                val = scaling.apply(val);
+            }
+            if let Some(constant) = &field.constant
+                && val != *constant
+            {
+                return Err(ReadError::ConstMismatch {
+                    path,
+                    expected: constant.to_string(),
+                    found: val.to_string(),
+                });
+            }
+            *offset = (*offset).max(end);
+            fields.push((field.name.clone(), val));
+        }
+        Ok(Value::Struct { fields })
+    }
+
+    /// Reads a value of type `ty` that is not laid out in sequence, starting
+    /// at `start`, and claims the bytes it takes up. Pointer targets are
+    /// `shared`, other pointers may point to them too.
+    fn read_at(
+        &self,
+        buf: &[u8],
+        start: usize,
+        ty: &Type,
+        path: &str,
+        shared: bool,
+        cx: &mut ReadContext,
+    ) -> Result<Value, ReadError> {
+        let mut end = start;
+        let value = self.read_type(buf, &mut end, ty, path, cx)?;
+        cx.regions
+            .claim(start..end, path, shared)
+            .map_err(|other| ReadError::Overlap {
+                path: path.to_string(),
+                other,
+            })?;
+        Ok(value)
+    }
+
+    /// Reads a value of type `ty` at `offset`, advancing the offset past it.
+    fn read_type(
+        &self,
+        buf: &[u8],
+        offset: &mut usize,
+        ty: &Type,
+        path: &str,
+        cx: &mut ReadContext,
+    ) -> Result<Value, ReadError> {
+        if let Some(reader) = &mut cx.bits
+            && let Some(width) = ty.bits()
+        {
+            let raw = reader.read(buf, width).ok_or(ReadError::UnexpectedEof {
+                path: path.to_string(),
+                offset: reader.position / 8,
+            })?;
+            return Ok(self.bits_value(ty, width, raw));
+        }
+        Ok(match ty {
+            Type::UVarInt => Value::U64(read_leb128(buf, offset, path, false)?.0),
+            Type::VarInt => {
+                let (value, bits) = read_leb128(buf, offset, path, true)?;
+                // Sign extend from the top bit of the last byte.
+                if bits < 64 {
+                    Value::I64(((value << (64 - bits)) as i64) >> (64 - bits))
+                } else {
+                    Value::I64(value as i64)
+                }
+            }
+            Type::ZigZag => {
+                let (value, _) = read_leb128(buf, offset, path, false)?;
+                Value::I64((value >> 1) as i64 ^ -((value & 1) as i64))
+            }
+            Type::I8 => Value::I8(i8::from_le_bytes(take(buf, offset, path)?) as i64),
+            Type::I16 => Value::I16(i16::from_le_bytes(take(buf, offset, path)?) as i64),
+            Type::I32 => Value::I32(i32::from_le_bytes(take(buf, offset, path)?) as i64),
+            Type::I64 => Value::I64(i64::from_le_bytes(take(buf, offset, path)?)),
+            Type::U8 => Value::U8(u8::from_le_bytes(take(buf, offset, path)?) as u64),
+            Type::U16 => Value::U16(u16::from_le_bytes(take(buf, offset, path)?) as u64),
+            Type::U32 => Value::U32(u32::from_le_bytes(take(buf, offset, path)?) as u64),
+            Type::U64 => Value::U64(u64::from_le_bytes(take(buf, offset, path)?)),
+            Type::F16 => Value::F16(f16::from_le_bytes(take(buf, offset, path)?).to_f64()),
+            Type::BF16 => Value::BF16(bf16::from_le_bytes(take(buf, offset, path)?).to_f64()),
+            Type::F32 => Value::F32(f32::from_le_bytes(take(buf, offset, path)?) as f64),
+            Type::F64 => Value::F64(f64::from_le_bytes(take(buf, offset, path)?)),
+            Type::Ipv4 => Value::Ipv4(Ipv4Addr::from(take::<4>(buf, offset, path)?)),
+            Type::Ipv6 => Value::Ipv6(Ipv6Addr::from(take::<16>(buf, offset, path)?)),
+            Type::Mac => Value::Mac(Mac(take(buf, offset, path)?)),
+            Type::Uuid => Value::Uuid(Uuid(take(buf, offset, path)?)),
+            Type::Bytes(Some(len)) => {
+                let bytes =
+                    buf.get(*offset..*offset + len)
+                        .ok_or_else(|| ReadError::UnexpectedEof {
+                            path: path.to_string(),
+                            offset: *offset,
+                        })?;
+                *offset += len;
+                Value::Bytes(bytes.to_vec())
+            }
+            Type::Bytes(None) => unreachable!("fields size their bytes before reading them"),
+            Type::Bool(storage) => {
+                let raw = self.read_type(buf, offset, storage, path, cx)?;
+                match Scalar::of(&raw) {
+                    Some(Scalar::Int(0)) => Value::Bool(false),
+                    Some(Scalar::Int(1)) => Value::Bool(true),
+                    _ if cx.options.lenient_bools => Value::Bool(true),
+                    _ => {
+                        return Err(ReadError::InvalidBool {
+                            path: path.to_string(),
+                            found: raw.to_string(),
+                        });
                     }
-                    let val = f64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap());
-                    offset += 8;
-                    Value::F64(val)
                 }
-                Type::Struct(inner_id) => {
-                    let (val, added_offset) = self.read_value_helper(&buf[offset..], *inner_id)?;
-                    offset += added_offset;
-                    val
+            }
+            Type::Fixed(fixed) => {
+                let raw = self.read_type(buf, offset, &fixed.storage(), path, cx)?;
+                let Some(Scalar::Int(raw)) = Scalar::of(&raw) else {
+                    unreachable!("fixed-point numbers are stored as integers")
+                };
+                Value::Fixed {
+                    raw,
+                    frac_bits: fixed.frac_bits,
                 }
-            };
-            fields.push((name.to_string(), val));
+            }
+            Type::Timestamp(timestamp) => {
+                let raw = self.read_type(buf, offset, &timestamp.storage, path, cx)?;
+                let Some(Scalar::Int(raw)) = Scalar::of(&raw) else {
+                    unreachable!("timestamps are stored as integers")
+                };
+                Value::Timestamp {
+                    raw,
+                    epoch: timestamp.epoch,
+                    unit: timestamp.unit,
+                }
+            }
+            Type::Struct(inner_id) => self.read_value_helper(buf, offset, *inner_id, path, cx)?,
+            Type::Pointer(pointer) => {
+                let raw = self.read_type(buf, offset, &pointer.storage, path, cx)?;
+                let Some(Scalar::Int(raw)) = Scalar::of(&raw) else {
+                    unreachable!("offsets are stored as integers")
+                };
+                // Nothing is stored at offset 0, that is where the buffer or
+                // the struct holding the pointer starts.
+                if raw == 0 {
+                    return Ok(Value::Pointer {
+                        offset: Some(0),
+                        target: None,
+                    });
+                }
+                let start = absolute(buf, pointer.base, cx.base, raw, path)?;
+                if cx.targets.contains(&start) {
+                    return Err(ReadError::PointerCycle {
+                        path: path.to_string(),
+                        offset: start,
+                    });
+                }
+                if cx.targets.len() == MAX_POINTER_DEPTH {
+                    return Err(ReadError::PointerDepth {
+                        path: path.to_string(),
+                    });
+                }
+                cx.targets.push(start);
+                let target = self.read_at(buf, start, &pointer.target, path, true, cx);
+                cx.targets.pop();
+                Value::Pointer {
+                    offset: Some(raw as i64),
+                    target: Some(Box::new(target?)),
+                }
+            }
+            Type::Enum(id) => {
+                let enumeration = self.enums.get(id).ok_or(ReadError::UnknownEnum(*id))?;
+                let raw = self.read_type(buf, offset, &enumeration.storage, path, cx)?;
+                let name = match Scalar::of(&raw) {
+                    Some(Scalar::Int(value)) => enumeration.name_of(value).map(str::to_string),
+                    _ => None,
+                };
+                Value::Enum {
+                    raw: Box::new(raw),
+                    name,
+                }
+            }
+            Type::Array(_, None) => unreachable!("fields size their arrays before reading them"),
+            Type::Array(element, Some(len)) => Value::Array(
+                (0..*len)
+                    .map(|i| {
+                        let path = format!("{path}[{i}]");
+                        self.read_type(buf, offset, element, &path, cx)
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            // Only whole bytes are allowed outside of #[bits] layouts.
+            Type::Bits { width, .. } => {
+                let len = *width as usize / 8;
+                let bytes =
+                    buf.get(*offset..*offset + len)
+                        .ok_or_else(|| ReadError::UnexpectedEof {
+                            path: path.to_string(),
+                            offset: *offset,
+                        })?;
+                *offset += len;
+                let mut raw = [0; 8];
+                raw[..len].copy_from_slice(bytes);
+                self.bits_value(ty, *width, u64::from_le_bytes(raw))
+            }
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("Unknown layout {0:?}")]
+    UnknownLayout(LayoutId),
+    #[error("Unknown enum {0:?}")]
+    UnknownEnum(EnumId),
+    #[error("Buffer ended while reading {path} at offset {offset}")]
+    UnexpectedEof { path: String, offset: usize },
+    #[error("{path}: expected constant {expected}, found {found}")]
+    ConstMismatch {
+        path: String,
+        expected: String,
+        found: String,
+    },
+    #[error("{path}: expected a bool (0 or 1), found {found}")]
+    InvalidBool { path: String, found: String },
+    #[error("{path}: cannot evaluate condition: {error}")]
+    Condition { path: String, error: EvalError },
+    #[error("{path}: {length} is {expected}, found {found}")]
+    LengthMismatch {
+        path: String,
+        length: String,
+        expected: i128,
+        found: i128,
+    },
+    #[error("{path}: cannot evaluate value: {error}")]
+    Derived { path: String, error: EvalError },
+    #[error("{path}: {expression} is {expected}, found {found}")]
+    DerivedMismatch {
+        path: String,
+        expression: String,
+        expected: String,
+        found: String,
+    },
+    #[error("{path}: {algorithm} mismatch, expected {expected:#X}, found {found:#X}")]
+    ChecksumMismatch {
+        path: String,
+        algorithm: String,
+        expected: u64,
+        found: u64,
+    },
+    #[error("{path}: cannot evaluate offset: {error}")]
+    Location { path: String, error: EvalError },
+    #[error("{path}: cannot evaluate size: {error}")]
+    Size { path: String, error: EvalError },
+    #[error("{path}: size {size} is negative")]
+    InvalidSize { path: String, size: i128 },
+    #[error("{path}: offset {offset} is outside of the buffer")]
+    InvalidOffset { path: String, offset: i128 },
+    #[error("{path}: overlaps {other}")]
+    Overlap { path: String, other: String },
+    #[error("{path}: points back to the value at offset {offset}, which holds it")]
+    PointerCycle { path: String, offset: usize },
+    #[error("{path}: pointers are nested more than {MAX_POINTER_DEPTH} deep")]
+    PointerDepth { path: String },
+    #[error("{path}: varint at offset {offset} does not fit in 64 bits")]
+    VarIntOverflow { path: String, offset: usize },
+}
+
+/// How strictly [`Expr::read_value_with`] checks the bytes it decodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// Reads any non-zero bool as true, instead of rejecting values other
+    /// than 0 and 1.
+    pub lenient_bools: bool,
+    /// Includes padding and reserved fields in the decoded value.
+    pub keep_padding: bool,
+}
+
+/// State carried through one [`Expr::read_value_with`] call.
+struct ReadContext {
+    options: ReadOptions,
+    /// Where the innermost struct being read starts.
+    base: usize,
+    regions: Regions,
+    /// Set while reading a `#[bits]` layout, integers and floats are then
+    /// read from it instead of from whole bytes.
+    bits: Option<BitReader>,
+    /// Where the targets of the pointers being followed start, innermost
+    /// last.
+    targets: Vec<usize>,
+}
+
+/// How many pointers may be followed from one another, e.g. the length of a
+/// linked list, before reading gives up.
+const MAX_POINTER_DEPTH: usize = 32;
+
+/// Turns an offset from `base` into a position in `buf`, for reading at.
+fn absolute(
+    buf: &[u8],
+    base: Base,
+    struct_start: usize,
+    offset: i128,
+    path: &str,
+) -> Result<usize, ReadError> {
+    let base = match base {
+        Base::Buffer => 0,
+        Base::Struct => struct_start,
+    };
+    let position = base as i128 + offset;
+    usize::try_from(position)
+        .ok()
+        .filter(|position| *position <= buf.len())
+        .ok_or_else(|| ReadError::InvalidOffset {
+            path: path.to_string(),
+            offset: position,
+        })
+}
+
+/// Takes the next `N` bytes of `buf` at `offset`, advancing the offset.
+fn take<const N: usize>(buf: &[u8], offset: &mut usize, path: &str) -> Result<[u8; N], ReadError> {
+    let bytes = buf
+        .get(*offset..*offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ReadError::UnexpectedEof {
+            path: path.to_string(),
+            offset: *offset,
+        })?;
+    *offset += N;
+    Ok(bytes)
+}
+
+/// Reads a LEB128 number at `offset`, advancing the offset past it. Returns
+/// the bits read along with how many there were, for sign extension.
+fn read_leb128(
+    buf: &[u8],
+    offset: &mut usize,
+    path: &str,
+    signed: bool,
+) -> Result<(u64, u32), ReadError> {
+    let start = *offset;
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let [byte] = take(buf, offset, path)?;
+        // The tenth byte only has room for the top bit, the rest of a signed
+        // number's byte is its sign extension.
+        if shift == 63 && !matches!((signed, byte), (false, 0 | 1) | (true, 0 | 0x7F)) {
+            break;
+        }
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, shift + 7));
         }
+    }
+    Err(ReadError::VarIntOverflow {
+        path: path.to_string(),
+        offset: start,
+    })
+}
 
-        Some((Value::Struct { fields }, offset))
+/// Looks up `name` in a map of qualified names, starting in the namespace
+/// `scope` and walking outwards, but never past the namespace `root`. Returns
+/// the qualified name that matched.
+pub(crate) fn lookup_scoped<'a, T>(
+    map: &'a HashMap<String, T>,
+    root: &str,
+    scope: &str,
+    name: &str,
+) -> Option<(String, &'a T)> {
+    let mut scope = scope;
+    loop {
+        let qualified = if scope.is_empty() {
+            name.to_string()
+        } else {
+            format!("{scope}::{name}")
+        };
+        if let Some(value) = map.get(&qualified) {
+            return Some((qualified, value));
+        }
+        if scope == root {
+            return None;
+        }
+        scope = scope.rsplit_once("::").map_or("", |(parent, _)| parent);
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Struct {
-    pub fields: Vec<(String, Type)>,
+    /// The qualified name it was declared with, including generic arguments,
+    /// e.g. `nav::Pair<u8>`. Inline structs are named after their fields,
+    /// `struct { x: f32, y: f32 }`, and are left out of `Expr::layout_ids`.
+    pub name: String,
+    pub fields: Vec<Field>,
+    /// Conditions declared with `assert(...)` that every instance must meet.
+    pub asserts: Vec<Expression>,
+    /// A `union`, whose fields all start at the same byte. Values of it hold
+    /// one member when encoded, and every member when decoded.
+    pub union: bool,
+    /// Set for `#[bits]` layouts, whose fields are packed bit by bit in this
+    /// order instead of taking whole bytes.
+    pub bits: Option<BitOrder>,
 }
 
 #[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+    /// Qualified name of the alias the type was written as, e.g. `Meters`
+    /// for a field declared as `altitude: Meters`.
+    pub alias: Option<String>,
+    /// Value of a `const` field, which is always encoded as is and checked
+    /// when decoding.
+    pub constant: Option<Value>,
+    /// Value used for the field when none is given, e.g. `rate: u16 = 100`.
+    pub default: Option<Value>,
+    /// Limits declared with attributes such as `#[range(0, 360)]`.
+    pub constraints: Vec<Constraint>,
+    /// Conversion to engineering units, for numeric fields.
+    pub scaling: Option<Scaling>,
+    /// The engineering unit, declared with `#[unit("degC")]`.
+    pub unit: Option<String>,
+    /// The field is only present when this holds for the fields before it.
+    pub condition: Option<Expression>,
+    /// The field holds a checksum of other fields, computed when encoding.
+    pub checksum: Option<Checksum>,
+    /// The field holds the size of other fields, computed when encoding.
+    pub length: Option<Length>,
+    /// The field holds a value computed from the fields before it when
+    /// encoding, e.g. `derived total: u16 = a + b`.
+    pub derived: Option<Expression>,
+    /// Where the field lives when it is not laid out after the one before
+    /// it, e.g. `header: Header @ 0x40`. It then takes no space in sequence.
+    pub location: Option<Location>,
+    /// The member of a union field to decode, from `#[view("raw")]`. All of
+    /// them are when there is none.
+    pub view: Option<String>,
+    /// The length of a `bytes` or array field that refers to the fields
+    /// before it, e.g. `payload: bytes[payload_len]` or `items: [u16; count]`.
+    pub size: Option<Expression>,
+    /// Padding or a reserved region, e.g. `_: pad[3]`. Encoded as its
+    /// default, zeros unless a fill is declared, and left out of decoded
+    /// values. Only checked when decoding if it is `const`.
+    pub padding: bool,
+}
+
+impl Field {
+    /// Whether the field's value is computed when encoding, a checksum,
+    /// length or expression.
+    pub fn is_computed(&self) -> bool {
+        self.checksum.is_some() || self.length.is_some() || self.derived.is_some()
+    }
+
+    /// The name the field's type was written as.
+    pub fn type_name<'a>(&'a self, expr: &'a Expr) -> Cow<'a, str> {
+        match &self.alias {
+            Some(alias) => Cow::Borrowed(alias),
+            None => self.ty.name(expr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Struct(LayoutId),
+    /// `bool`, or `bool<u32>` to store it as a wider integer.
+    Bool(Box<Type>),
+    /// A fixed-point number such as `q15` or `uq8.8`.
+    Fixed(Fixed),
+    /// An integer with named values.
+    Enum(EnumId),
+    /// Consecutive elements, `[T; N]`. `None` for a field whose number of
+    /// elements refers to the fields before it, see [`Field::size`].
+    Array(Box<Type>, Option<usize>),
+    /// An offset to a value elsewhere in the buffer.
+    Pointer(Box<Pointer>),
+    /// A point in time, counted in an integer, `timestamp<u32, unix, s>`.
+    Timestamp(Box<Timestamp>),
     I8,
     I16,
     I32,
     I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    /// IEEE 754 half precision.
+    F16,
+    /// bfloat16, the upper half of an `f32`.
+    BF16,
     F32,
     F64,
+    /// Signed LEB128.
+    VarInt,
+    /// Unsigned LEB128.
+    UVarInt,
+    /// A signed number, zigzag encoded and stored as unsigned LEB128.
+    ZigZag,
+    /// An IPv4 address, its 4 bytes in network order.
+    Ipv4,
+    /// An IPv6 address, its 16 bytes in network order.
+    Ipv6,
+    /// A 6 byte MAC address.
+    Mac,
+    /// A 16 byte UUID.
+    Uuid,
+    /// Opaque bytes, `bytes[16]`. `None` for a field whose length refers to
+    /// the fields before it, `bytes[len]`, see [`Field::size`].
+    Bytes(Option<usize>),
+    /// An integer of any width from 1 to 64 bits, e.g. `i12` or `u5`, in a
+    /// `#[bits]` layout. Widths of whole bytes, like `u24`, are also allowed
+    /// elsewhere. Held in the smallest value that fits, a `u12` in a
+    /// [`Value::U16`].
+    Bits {
+        signed: bool,
+        width: u32,
+    },
+}
+
+impl Type {
+    /// Parses the name of a primitive type.
+    pub fn primitive(name: &str) -> Option<Self> {
+        Some(match name {
+            "bool" => Type::Bool(Box::new(Type::U8)),
+            "i8" => Type::I8,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "u8" => Type::U8,
+            "u16" => Type::U16,
+            "u32" => Type::U32,
+            "u64" => Type::U64,
+            "f16" => Type::F16,
+            "bf16" => Type::BF16,
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "varint" => Type::VarInt,
+            "uvarint" => Type::UVarInt,
+            "zigzag" => Type::ZigZag,
+            "ipv4" => Type::Ipv4,
+            "ipv6" => Type::Ipv6,
+            "mac" => Type::Mac,
+            "uuid" => Type::Uuid,
+            _ => {
+                let (signed, digits) = match name.split_at_checked(1) {
+                    Some(("i", digits)) => (true, digits),
+                    Some(("u", digits)) => (false, digits),
+                    _ => return Some(Type::Fixed(Fixed::from_name(name)?)),
+                };
+                match digits.parse() {
+                    Ok(width @ 1..=64) if !digits.starts_with('0') => Type::Bits { signed, width },
+                    _ => Type::Fixed(Fixed::from_name(name)?),
+                }
+            }
+        })
+    }
+
+    /// The width of an integer or float type, as packed in a `#[bits]`
+    /// layout. `None` for types made up of others.
+    pub fn bits(&self) -> Option<u32> {
+        Some(match self {
+            Type::Bits { width, .. } => *width,
+            Type::I8 | Type::U8 => 8,
+            Type::I16 | Type::U16 | Type::F16 | Type::BF16 => 16,
+            Type::I32 | Type::U32 | Type::F32 => 32,
+            Type::I64 | Type::U64 | Type::F64 => 64,
+            _ => return None,
+        })
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Type::I8
+                | Type::I16
+                | Type::I32
+                | Type::I64
+                | Type::U8
+                | Type::U16
+                | Type::U32
+                | Type::U64
+                | Type::VarInt
+                | Type::UVarInt
+                | Type::ZigZag
+                | Type::Bits { .. }
+        )
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::F16 | Type::BF16 | Type::F32 | Type::F64)
+    }
+
+    /// The type of a field sized by the fields before it, `bytes` or an
+    /// array, once its length is known.
+    pub fn sized(&self, len: usize) -> Type {
+        match self {
+            Type::Array(element, _) => Type::Array(element.clone(), Some(len)),
+            _ => Type::Bytes(Some(len)),
+        }
+    }
+
+    /// The value a float of this type ends up as when `value` is stored in
+    /// it, `None` for other types.
+    pub fn round_float(&self, value: f64) -> Option<f64> {
+        Some(match self {
+            Type::F16 => f16::from_f64(value).to_f64(),
+            Type::BF16 => bf16::from_f64(value).to_f64(),
+            Type::F32 => value as f32 as f64,
+            Type::F64 => value,
+            _ => return None,
+        })
+    }
+
+    pub fn name<'a>(&self, expr: &'a Expr) -> Cow<'a, str> {
+        Cow::Borrowed(match self {
+            Type::Struct(id) => {
+                return expr
+                    .layouts
+                    .get(id)
+                    .map_or(Cow::Borrowed("?"), |layout| Cow::Borrowed(&layout.name));
+            }
+            Type::Enum(id) => {
+                return expr
+                    .enum_ids
+                    .iter()
+                    .find(|(_, enum_id)| *enum_id == id)
+                    .map_or(Cow::Borrowed("?"), |(name, _)| Cow::Borrowed(name));
+            }
+            Type::Array(element, Some(len)) => {
+                return Cow::Owned(format!("[{}; {len}]", element.name(expr)));
+            }
+            Type::Array(element, None) => return Cow::Owned(format!("[{}]", element.name(expr))),
+            Type::Pointer(pointer) => {
+                let function = match pointer.base {
+                    Base::Buffer => "ptr",
+                    Base::Struct => "rel_ptr",
+                };
+                let target = pointer.target.name(expr);
+                return Cow::Owned(match pointer.storage {
+                    Type::U32 => format!("{function}<{target}>"),
+                    ref storage => format!("{function}<{target}, {}>", storage.name(expr)),
+                });
+            }
+            Type::Bool(storage) if **storage == Type::U8 => "bool",
+            Type::Bool(storage) => return Cow::Owned(format!("bool<{}>", storage.name(expr))),
+            Type::Fixed(fixed) => return Cow::Owned(fixed.to_string()),
+            Type::Timestamp(timestamp) => {
+                return Cow::Owned(format!(
+                    "timestamp<{}, {}, {}>",
+                    timestamp.storage.name(expr),
+                    timestamp.epoch.name(),
+                    timestamp.unit.name()
+                ));
+            }
+            Type::I8 => "i8",
+            Type::I16 => "i16",
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+            Type::U8 => "u8",
+            Type::U16 => "u16",
+            Type::U32 => "u32",
+            Type::U64 => "u64",
+            Type::F16 => "f16",
+            Type::BF16 => "bf16",
+            Type::F32 => "f32",
+            Type::F64 => "f64",
+            Type::VarInt => "varint",
+            Type::UVarInt => "uvarint",
+            Type::ZigZag => "zigzag",
+            Type::Ipv4 => "ipv4",
+            Type::Ipv6 => "ipv6",
+            Type::Mac => "mac",
+            Type::Uuid => "uuid",
+            Type::Bytes(Some(len)) => return Cow::Owned(format!("bytes[{len}]")),
+            Type::Bytes(None) => "bytes",
+            Type::Bits { signed, width } => {
+                return Cow::Owned(format!("{}{width}", if *signed { 'i' } else { 'u' }));
+            }
+        })
+    }
+}
+
+/// A table of named values for an integer type, declared with
+/// `enum Mode: u8 { IDLE = 0, ARMED, FAULT }`.
+#[derive(Debug, Clone)]
+pub struct Enumeration {
+    /// The integer type the values are stored as.
+    pub storage: Type,
+    pub values: Vec<(String, i128)>,
+}
+
+impl Enumeration {
+    pub fn name_of(&self, value: i128) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(_, v)| *v == value)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn value_of(&self, name: &str) -> Option<i128> {
+        self.values
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseError;
+    use crate::value::EncodeError;
+
+    /// The layout `A` declared in `source`.
+    fn layout(source: &str) -> (Expr, LayoutId) {
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("A").unwrap();
+        (expr, id)
+    }
+
+    #[test]
+    fn constants_are_checked_both_ways() {
+        let (expr, id) = layout("struct A { const magic: u16 = 0xCAFE, x: u8 }");
+        let value = Value::Struct {
+            fields: vec![("x".into(), Value::U8(1))],
+        };
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [0xFE, 0xCA, 1]);
+        let decoded = expr.read_value(&[0xFE, 0xCA, 1], id).unwrap();
+        assert_eq!(decoded.field("magic"), Some(&Value::U16(0xCAFE)));
+
+        let error = expr.read_value(&[0xFF, 0xCA, 1], id).unwrap_err();
+        assert!(matches!(
+            error,
+            ReadError::ConstMismatch { path, expected, found }
+                if path == "magic" && expected == "51966" && found == "51967"
+        ));
+        let value = Value::Struct {
+            fields: vec![("magic".into(), Value::U16(1)), ("x".into(), Value::U8(1))],
+        };
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(error, EncodeError::ConstMismatch { path, .. } if path == "magic"));
+    }
+
+    #[test]
+    fn defaults_fill_in_what_is_missing() {
+        let (expr, id) = layout("struct A { rate: u16 = 100, name: [u8; 2], x: i8 = -1 }");
+        let value = expr.default_value(id).unwrap();
+        assert_eq!(value.field("rate"), Some(&Value::U16(100)));
+        assert_eq!(
+            value.field("name"),
+            Some(&Value::Array(vec![Value::U8(0); 2]))
+        );
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [100, 0, 0, 0, 0xFF]);
+
+        // Fields left out when encoding take their default.
+        let value = Value::Struct {
+            fields: vec![(
+                "name".into(),
+                Value::Array(vec![Value::U8(1), Value::U8(2)]),
+            )],
+        };
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [100, 0, 1, 2, 0xFF]);
+        let value = Value::Struct { fields: Vec::new() };
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(error, EncodeError::MissingField(path) if path == "name"));
+
+        let Err(ParseError::InFile { error, .. }) = Expr::parse("struct A { x: u8 = 256 }") else {
+            panic!("256 does not fit in a u8");
+        };
+        assert!(matches!(*error, ParseError::InvalidLiteral { .. }));
+    }
+
+    #[test]
+    fn unknown_enum_values_are_kept_raw() {
+        let (expr, id) = layout("enum Kind: u8 { Ping = 1, Pong = 2 } struct A { k: Kind }");
+        let value = expr.read_value(&[2], id).unwrap();
+        let Some(Value::Enum {
+            name: Some(name), ..
+        }) = value.field("k")
+        else {
+            panic!("2 is Pong");
+        };
+        assert_eq!(name, "Pong");
+
+        // Values missing from the table are decoded, but only as numbers.
+        let value = expr.read_value(&[7], id).unwrap();
+        let Some(unknown @ Value::Enum { name: None, .. }) = value.field("k") else {
+            panic!("7 has no name");
+        };
+        assert_eq!(unknown.to_string(), "7");
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [7]);
+
+        let k = Value::Enum {
+            raw: Box::new(Value::U8(1)),
+            name: Some("Pang".into()),
+        };
+        let value = Value::Struct {
+            fields: vec![("k".into(), k)],
+        };
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(error, EncodeError::UnknownName { name, .. } if name == "Pang"));
+    }
+
+    #[test]
+    fn bools_other_than_0_and_1_are_rejected_unless_lenient() {
+        let (expr, id) = layout("struct A { a: bool, b: bool<u32> }");
+        let value = expr.read_value(&[1, 0, 0, 0, 0], id).unwrap();
+        assert_eq!(value.field("a"), Some(&Value::Bool(true)));
+        assert_eq!(value.field("b"), Some(&Value::Bool(false)));
+
+        let error = expr.read_value(&[2, 0, 0, 0, 0], id).unwrap_err();
+        assert!(matches!(
+            error,
+            ReadError::InvalidBool { path, found } if path == "a" && found == "2"
+        ));
+        let error = expr.read_value(&[0, 0, 0, 0, 1], id).unwrap_err();
+        assert!(matches!(error, ReadError::InvalidBool { path, .. } if path == "b"));
+
+        let lenient = ReadOptions {
+            lenient_bools: true,
+            ..ReadOptions::default()
+        };
+        let value = expr
+            .read_value_with(&[0xFF, 0, 0, 0, 1], id, lenient)
+            .unwrap();
+        assert_eq!(value.field("a"), Some(&Value::Bool(true)));
+        assert_eq!(value.field("b"), Some(&Value::Bool(true)));
+        // Encoding always writes 1 for true.
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [1, 1, 0, 0, 0]);
+
+        let (expr, id) = layout("struct A { a: bool = true, b: bool<u32> = false }");
+        let value = expr.default_value(id).unwrap();
+        assert_eq!(value.field("a"), Some(&Value::Bool(true)));
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [1, 0, 0, 0, 0]);
+        let Err(ParseError::InFile { error, .. }) = Expr::parse("struct A { x: u8 = true }") else {
+            panic!("true is not a u8");
+        };
+        assert!(matches!(*error, ParseError::InvalidLiteral { .. }));
+    }
+
+    #[test]
+    fn varints_take_as_many_bytes_as_they_need() {
+        let (expr, id) = layout("struct A { a: uvarint, b: varint, c: zigzag, d: u8 }");
+        assert_eq!(expr.size_of(id), Some(Size::Variable { min: 4 }));
+        let bytes = [0xAC, 0x02, 0x7F, 0x03, 9];
+        let value = expr.read_value(&bytes, id).unwrap();
+        assert_eq!(value.field("a"), Some(&Value::U64(300)));
+        assert_eq!(value.field("b"), Some(&Value::I64(-1)));
+        assert_eq!(value.field("c"), Some(&Value::I64(-2)));
+        assert_eq!(value.field("d"), Some(&Value::U8(9)));
+        assert_eq!(value.encode_value(&expr, id).unwrap(), bytes);
+
+        let (expr, id) = layout("struct A { a: uvarint }");
+        let max = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(
+            expr.read_value(&max, id).unwrap().field("a"),
+            Some(&Value::U64(u64::MAX))
+        );
+        let too_long = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02];
+        let error = expr.read_value(&too_long, id).unwrap_err();
+        assert!(matches!(error, ReadError::VarIntOverflow { offset: 0, .. }));
+        let error = expr.read_value(&[0x80, 0x80], id).unwrap_err();
+        assert!(matches!(error, ReadError::UnexpectedEof { offset: 2, .. }));
+
+        let (expr, id) = layout("struct A { b: varint }");
+        let min = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7F];
+        assert_eq!(
+            expr.read_value(&min, id).unwrap().field("b"),
+            Some(&Value::I64(i64::MIN))
+        );
+    }
+
+    #[test]
+    fn conditional_fields_follow_the_fields_before_them() {
+        let source = "struct A { flags: u8, ext: u16 if flags & 1 != 0, if flags & 2 != 0 { a: u8, b: u8 } }";
+        let (expr, id) = layout(source);
+        let value = expr.read_value(&[0], id).unwrap();
+        assert_eq!(
+            value,
+            Value::Struct {
+                fields: vec![("flags".into(), Value::U8(0))]
+            }
+        );
+        let value = expr.read_value(&[3, 1, 0, 2, 3], id).unwrap();
+        assert_eq!(value.field("ext"), Some(&Value::U16(1)));
+        assert_eq!(value.field("b"), Some(&Value::U8(3)));
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [3, 1, 0, 2, 3]);
+        let value = expr.read_value(&[2, 5, 6], id).unwrap();
+        assert_eq!(value.field("ext"), None);
+        assert_eq!(value.field("a"), Some(&Value::U8(5)));
+
+        let value = Value::Struct {
+            fields: vec![
+                ("flags".into(), Value::U8(0)),
+                ("ext".into(), Value::U16(1)),
+            ],
+        };
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(error, EncodeError::UnexpectedField { path, .. } if path == "ext"));
+        let value = Value::Struct {
+            fields: vec![("flags".into(), Value::U8(1))],
+        };
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(error, EncodeError::MissingField(path) if path == "ext"));
+    }
+
+    #[test]
+    fn padding_is_hidden_and_filled() {
+        let source = "struct A { a: u8, _: pad[2], #[reserved] reserved: [u8; 2] = 0xFF, b: u8 }";
+        let (expr, id) = layout(source);
+        assert_eq!(expr.size_of(id), Some(Size::Fixed(6)));
+        let value = expr.read_value(&[1, 9, 9, 9, 9, 2], id).unwrap();
+        let visible = vec![("a".into(), Value::U8(1)), ("b".into(), Value::U8(2))];
+        assert_eq!(value, Value::Struct { fields: visible });
+        assert_eq!(
+            value.encode_value(&expr, id).unwrap(),
+            [1, 0, 0, 0xFF, 0xFF, 2]
+        );
+        let options = ReadOptions {
+            keep_padding: true,
+            ..ReadOptions::default()
+        };
+        let value = expr
+            .read_value_with(&[1, 9, 9, 9, 9, 2], id, options)
+            .unwrap();
+        assert_eq!(
+            value.field("reserved"),
+            Some(&Value::Array(vec![Value::U8(9); 2]))
+        );
+
+        // Only constant padding is checked.
+        let (expr, id) = layout("struct A { const _: pad[1] = 0, b: u8 }");
+        let error = expr.read_value(&[1, 2], id).unwrap_err();
+        assert!(matches!(error, ReadError::ConstMismatch { .. }));
+    }
+
+    #[test]
+    fn fields_named_reserved_are_kept_unless_marked() {
+        let (expr, id) = layout("struct A { reserved: u32, b: u8 }");
+        let value = expr.read_value(&[1, 0, 0, 0, 2], id).unwrap();
+        assert_eq!(value.field("reserved"), Some(&Value::U32(1)));
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [1, 0, 0, 0, 2]);
+        let (expr, id) = layout("struct A { reserved: [u8; 2] = 0xFF, b: u8 }");
+        let value = expr.read_value(&[1, 2, 3], id).unwrap();
+        assert_eq!(
+            value.field("reserved"),
+            Some(&Value::Array(vec![Value::U8(1), Value::U8(2)]))
+        );
+
+        let (expr, id) = layout("struct A { #[reserved] flags: u32, b: u8 }");
+        let value = expr.read_value(&[1, 0, 0, 0, 2], id).unwrap();
+        assert_eq!(value.field("flags"), None);
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [0, 0, 0, 0, 2]);
+        let Err(ParseError::InFile { error, .. }) =
+            Expr::parse("struct A { #[reserved(1)] x: u32 }")
+        else {
+            panic!("reserved takes no arguments");
+        };
+        assert!(matches!(*error, ParseError::InvalidAttribute { .. }));
+    }
 }
@@ -0,0 +1,612 @@
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::epxr::Type;
+use crate::fixed;
+use crate::parser::Literal;
+use crate::value::Value;
+
+/// An expression over the fields of a struct, as used in `assert(...)` and
+/// field conditions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(Literal),
+    /// A dotted path to a field, relative to the struct being checked.
+    Field(Vec<String>),
+    /// Arithmetic and bitwise operators, e.g. `len - 4` or `flags & 0x80`.
+    Binary(Box<Expression>, BinaryOp, Box<Expression>),
+    Negate(Box<Expression>),
+    /// Bitwise complement, `~mask`.
+    Complement(Box<Expression>),
+    Compare(Box<Expression>, CompareOp, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+        }
+    }
+
+    fn is_bitwise(self) -> bool {
+        !matches!(
+            self,
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem
+        )
+    }
+}
+
+/// The type of a [`Scalar`], checked when a layout is parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+    Int,
+    Float,
+    Bool,
+}
+
+impl fmt::Display for ScalarType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScalarType::Int => "integer",
+            ScalarType::Float => "float",
+            ScalarType::Bool => "bool",
+        })
+    }
+}
+
+impl ScalarType {
+    /// The type a field of type `ty` evaluates to, `None` for structs and
+    /// arrays. Scaled fields evaluate to their engineering value, pointers
+    /// to what they point to.
+    pub fn of(ty: &Type, scaled: bool) -> Option<Self> {
+        Some(match ty {
+            Type::Struct(_) | Type::Array(..) => return None,
+            Type::Ipv4 | Type::Ipv6 | Type::Mac | Type::Uuid | Type::Bytes(_) => return None,
+            Type::Pointer(pointer) => return ScalarType::of(&pointer.target, scaled),
+            Type::Bool(_) => ScalarType::Bool,
+            _ if scaled => ScalarType::Float,
+            Type::Fixed(_) => ScalarType::Float,
+            ty if ty.is_float() => ScalarType::Float,
+            _ => ScalarType::Int,
+        })
+    }
+
+    fn binary(self, op: BinaryOp, other: ScalarType) -> Result<ScalarType, EvalError> {
+        match (self, other) {
+            (ScalarType::Int, ScalarType::Int) => Ok(ScalarType::Int),
+            (ScalarType::Int | ScalarType::Float, ScalarType::Int | ScalarType::Float)
+                if !op.is_bitwise() =>
+            {
+                Ok(ScalarType::Float)
+            }
+            _ => Err(EvalError::TypeMismatch(format!(
+                "cannot apply {} to {self} and {other}",
+                op.symbol()
+            ))),
+        }
+    }
+
+    fn compare(self, op: CompareOp, other: ScalarType) -> Result<ScalarType, EvalError> {
+        match (self, other) {
+            (ScalarType::Int | ScalarType::Float, ScalarType::Int | ScalarType::Float) => {}
+            (ScalarType::Bool, ScalarType::Bool) if matches!(op, CompareOp::Eq | CompareOp::Ne) => {
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch(format!(
+                    "cannot compare {self} {} {other}",
+                    op.symbol()
+                )));
+            }
+        }
+        Ok(ScalarType::Bool)
+    }
+
+    fn expect(self, expected: ScalarType) -> Result<ScalarType, EvalError> {
+        if self != expected {
+            return Err(EvalError::TypeMismatch(format!(
+                "expected {expected}, found {self}"
+            )));
+        }
+        Ok(self)
+    }
+}
+
+/// The result of evaluating an expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scalar {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scalar::Int(v) => write!(f, "{v}"),
+            Scalar::Float(v) => write!(f, "{v}"),
+            Scalar::Bool(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+impl From<Literal> for Scalar {
+    fn from(literal: Literal) -> Self {
+        match literal {
+            Literal::Int(v) => Scalar::Int(v),
+            Literal::Float(v) => Scalar::Float(v),
+            Literal::Bool(v) => Scalar::Bool(v),
+        }
+    }
+}
+
+impl Scalar {
+    /// The scalar a primitive value evaluates to.
+    pub fn of(value: &Value) -> Option<Self> {
+        Some(match value {
+            Value::Bool(v) => Scalar::Bool(*v),
+            Value::I8(v) | Value::I16(v) | Value::I32(v) | Value::I64(v) => Scalar::Int(*v as i128),
+            Value::U8(v) | Value::U16(v) | Value::U32(v) | Value::U64(v) => Scalar::Int(*v as i128),
+            Value::F16(v) | Value::BF16(v) | Value::F32(v) | Value::F64(v) => Scalar::Float(*v),
+            Value::Enum { raw, .. } => return Scalar::of(raw),
+            Value::Scaled { value, .. } => Scalar::Float(*value),
+            Value::Fixed { raw, frac_bits } => Scalar::Float(fixed::to_f64(*raw, *frac_bits)),
+            // The count, as a timestamp is stored.
+            Value::Timestamp { raw, .. } => Scalar::Int(*raw),
+            Value::Pointer { target, .. } => return Scalar::of(target.as_ref()?),
+            Value::Struct { .. } | Value::Array(_) => return None,
+            Value::Ipv4(_) | Value::Ipv6(_) | Value::Mac(_) | Value::Uuid(_) | Value::Bytes(_) => {
+                return None;
+            }
+        })
+    }
+
+    /// Compares two scalars, promoting integers to floats when mixed.
+    pub fn compare(self, op: CompareOp, other: Scalar) -> Result<bool, EvalError> {
+        let ordering = match (self, other) {
+            (Scalar::Int(a), Scalar::Int(b)) => a.partial_cmp(&b),
+            (Scalar::Bool(a), Scalar::Bool(b)) if matches!(op, CompareOp::Eq | CompareOp::Ne) => {
+                a.partial_cmp(&b)
+            }
+            (Scalar::Int(_) | Scalar::Float(_), Scalar::Int(_) | Scalar::Float(_)) => {
+                self.as_f64().partial_cmp(&other.as_f64())
+            }
+            _ => {
+                return Err(EvalError::TypeMismatch(format!(
+                    "cannot compare {self} {} {other}",
+                    op.symbol()
+                )));
+            }
+        };
+        // NaN compares unequal to everything.
+        let Some(ordering) = ordering else {
+            return Ok(op == CompareOp::Ne);
+        };
+        Ok(match op {
+            CompareOp::Eq => ordering.is_eq(),
+            CompareOp::Ne => ordering.is_ne(),
+            CompareOp::Lt => ordering.is_lt(),
+            CompareOp::Le => ordering.is_le(),
+            CompareOp::Gt => ordering.is_gt(),
+            CompareOp::Ge => ordering.is_ge(),
+        })
+    }
+
+    /// Applies an arithmetic or bitwise operator. Integer arithmetic is
+    /// exact and fails on overflow, mixing in a float makes it a float.
+    pub fn binary(self, op: BinaryOp, other: Scalar) -> Result<Scalar, EvalError> {
+        let overflow = || EvalError::Overflow(format!("{self} {} {other}", op.symbol()));
+        match (self, other) {
+            (Scalar::Int(a), Scalar::Int(b)) => {
+                if matches!(op, BinaryOp::Div | BinaryOp::Rem) && b == 0 {
+                    return Err(EvalError::DivisionByZero(format!("{a} {} 0", op.symbol())));
+                }
+                let shift = || u32::try_from(b).ok().filter(|b| *b < 128);
+                let result = match op {
+                    BinaryOp::Add => a.checked_add(b),
+                    BinaryOp::Sub => a.checked_sub(b),
+                    BinaryOp::Mul => a.checked_mul(b),
+                    BinaryOp::Div => a.checked_div(b),
+                    BinaryOp::Rem => a.checked_rem(b),
+                    BinaryOp::BitAnd => Some(a & b),
+                    BinaryOp::BitOr => Some(a | b),
+                    BinaryOp::BitXor => Some(a ^ b),
+                    BinaryOp::Shl => shift().and_then(|b| Some(a << b).filter(|v| v >> b == a)),
+                    BinaryOp::Shr => shift().map(|b| a >> b),
+                };
+                result.map(Scalar::Int).ok_or_else(overflow)
+            }
+            (Scalar::Int(_) | Scalar::Float(_), Scalar::Int(_) | Scalar::Float(_))
+                if !op.is_bitwise() =>
+            {
+                let (a, b) = (self.as_f64(), other.as_f64());
+                Ok(Scalar::Float(match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    _ => a % b,
+                }))
+            }
+            _ => Err(EvalError::TypeMismatch(format!(
+                "cannot apply {} to {self} and {other}",
+                op.symbol()
+            ))),
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Scalar::Int(v) => v as f64,
+            Scalar::Float(v) => v,
+            Scalar::Bool(v) => v as u8 as f64,
+        }
+    }
+
+    fn as_bool(self) -> Result<bool, EvalError> {
+        match self {
+            Scalar::Bool(v) => Ok(v),
+            other => Err(EvalError::TypeMismatch(format!(
+                "expected a condition, found {other}"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum EvalError {
+    #[error("Unknown field {0}")]
+    UnknownField(String),
+    #[error("{0}")]
+    TypeMismatch(String),
+    #[error("{0} overflows")]
+    Overflow(String),
+    #[error("Division by zero in {0}")]
+    DivisionByZero(String),
+    #[error("{value} does not fit in {ty}")]
+    OutOfRange { value: String, ty: String },
+}
+
+impl Expression {
+    /// Evaluates the expression, resolving field paths in the struct `scope`.
+    pub fn eval(&self, scope: &Value) -> Result<Scalar, EvalError> {
+        match scope {
+            Value::Struct { fields } => self.eval_fields(fields),
+            _ => self.eval_fields(&[]),
+        }
+    }
+
+    /// Evaluates the expression, resolving field paths in the fields of a
+    /// struct, such as the ones decoded so far.
+    pub fn eval_fields(&self, scope: &[(String, Value)]) -> Result<Scalar, EvalError> {
+        Ok(match self {
+            Expression::Literal(literal) => (*literal).into(),
+            Expression::Field(path) => {
+                let unknown = || EvalError::UnknownField(path.join("."));
+                let (first, rest) = path.split_first().ok_or_else(unknown)?;
+                let value = scope
+                    .iter()
+                    .find(|(name, _)| name == first)
+                    .map(|(_, value)| value);
+                let value = rest
+                    .iter()
+                    .try_fold(value.ok_or_else(unknown)?, |value, name| value.field(name))
+                    .ok_or_else(unknown)?;
+                Scalar::of(value).ok_or_else(unknown)?
+            }
+            Expression::Binary(lhs, op, rhs) => lhs
+                .eval_fields(scope)?
+                .binary(*op, rhs.eval_fields(scope)?)?,
+            Expression::Negate(inner) => match inner.eval_fields(scope)? {
+                Scalar::Int(v) => Scalar::Int(
+                    v.checked_neg()
+                        .ok_or_else(|| EvalError::Overflow(format!("-{v}")))?,
+                ),
+                Scalar::Float(v) => Scalar::Float(-v),
+                Scalar::Bool(v) => {
+                    return Err(EvalError::TypeMismatch(format!("cannot negate {v}")));
+                }
+            },
+            Expression::Complement(inner) => match inner.eval_fields(scope)? {
+                Scalar::Int(v) => Scalar::Int(!v),
+                other => {
+                    return Err(EvalError::TypeMismatch(format!(
+                        "cannot complement {other}"
+                    )));
+                }
+            },
+            Expression::Compare(lhs, op, rhs) => Scalar::Bool(
+                lhs.eval_fields(scope)?
+                    .compare(*op, rhs.eval_fields(scope)?)?,
+            ),
+            Expression::And(lhs, rhs) => Scalar::Bool(
+                lhs.eval_fields(scope)?.as_bool()? && rhs.eval_fields(scope)?.as_bool()?,
+            ),
+            Expression::Or(lhs, rhs) => Scalar::Bool(
+                lhs.eval_fields(scope)?.as_bool()? || rhs.eval_fields(scope)?.as_bool()?,
+            ),
+            Expression::Not(inner) => Scalar::Bool(!inner.eval_fields(scope)?.as_bool()?),
+        })
+    }
+
+    /// Works out the type of the expression without evaluating it, with
+    /// `field` giving the type of a field path. Every operator is checked the
+    /// way evaluating it would, so only arithmetic errors are left for later.
+    pub fn type_of(
+        &self,
+        field: &impl Fn(&[String]) -> Result<ScalarType, EvalError>,
+    ) -> Result<ScalarType, EvalError> {
+        Ok(match self {
+            Expression::Literal(Literal::Int(_)) => ScalarType::Int,
+            Expression::Literal(Literal::Float(_)) => ScalarType::Float,
+            Expression::Literal(Literal::Bool(_)) => ScalarType::Bool,
+            Expression::Field(path) => field(path)?,
+            Expression::Binary(lhs, op, rhs) => {
+                lhs.type_of(field)?.binary(*op, rhs.type_of(field)?)?
+            }
+            Expression::Negate(inner) => match inner.type_of(field)? {
+                ScalarType::Bool => {
+                    return Err(EvalError::TypeMismatch("cannot negate bool".to_string()));
+                }
+                ty => ty,
+            },
+            Expression::Complement(inner) => inner.type_of(field)?.expect(ScalarType::Int)?,
+            Expression::Compare(lhs, op, rhs) => {
+                lhs.type_of(field)?.compare(*op, rhs.type_of(field)?)?
+            }
+            Expression::And(lhs, rhs) | Expression::Or(lhs, rhs) => {
+                lhs.type_of(field)?.expect(ScalarType::Bool)?;
+                rhs.type_of(field)?.expect(ScalarType::Bool)?
+            }
+            Expression::Not(inner) => inner.type_of(field)?.expect(ScalarType::Bool)?,
+        })
+    }
+
+    /// Whether the expression refers to the field `name` or a field in it.
+    pub fn refers_to(&self, name: &str) -> bool {
+        match self {
+            Expression::Literal(_) => false,
+            Expression::Field(path) => path.first().is_some_and(|first| first == name),
+            Expression::Binary(lhs, _, rhs)
+            | Expression::Compare(lhs, _, rhs)
+            | Expression::And(lhs, rhs)
+            | Expression::Or(lhs, rhs) => lhs.refers_to(name) || rhs.refers_to(name),
+            Expression::Negate(inner) | Expression::Complement(inner) | Expression::Not(inner) => {
+                inner.refers_to(name)
+            }
+        }
+    }
+
+    /// Calls `visit` with the path of each field the expression refers to.
+    pub fn visit_fields<'e>(&'e self, visit: &mut impl FnMut(&'e [String])) {
+        match self {
+            Expression::Literal(_) => {}
+            Expression::Field(path) => visit(path),
+            Expression::Binary(lhs, _, rhs)
+            | Expression::Compare(lhs, _, rhs)
+            | Expression::And(lhs, rhs)
+            | Expression::Or(lhs, rhs) => {
+                lhs.visit_fields(visit);
+                rhs.visit_fields(visit);
+            }
+            Expression::Negate(inner) | Expression::Complement(inner) | Expression::Not(inner) => {
+                inner.visit_fields(visit)
+            }
+        }
+    }
+
+    /// Evaluates the expression as a condition.
+    pub fn check(&self, scope: &Value) -> Result<bool, EvalError> {
+        self.eval(scope)?.as_bool()
+    }
+
+    /// Evaluates the expression as a condition on the fields of a struct.
+    pub fn check_fields(&self, scope: &[(String, Value)]) -> Result<bool, EvalError> {
+        self.eval_fields(scope)?.as_bool()
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Nested binary expressions are parenthesized to keep precedence clear.
+        let operand = |f: &mut fmt::Formatter<'_>, e: &Expression| match e {
+            Expression::Binary(..)
+            | Expression::Compare(..)
+            | Expression::And(..)
+            | Expression::Or(..) => write!(f, "({e})"),
+            _ => write!(f, "{e}"),
+        };
+        match self {
+            Expression::Literal(literal) => write!(f, "{literal}"),
+            Expression::Field(path) => write!(f, "{}", path.join(".")),
+            Expression::Binary(lhs, op, rhs) => {
+                operand(f, lhs)?;
+                write!(f, " {} ", op.symbol())?;
+                operand(f, rhs)
+            }
+            Expression::Negate(inner) => {
+                write!(f, "-")?;
+                operand(f, inner)
+            }
+            Expression::Complement(inner) => {
+                write!(f, "~")?;
+                operand(f, inner)
+            }
+            Expression::Compare(lhs, op, rhs) => {
+                operand(f, lhs)?;
+                write!(f, " {} ", op.symbol())?;
+                operand(f, rhs)
+            }
+            Expression::And(lhs, rhs) => {
+                operand(f, lhs)?;
+                write!(f, " && ")?;
+                operand(f, rhs)
+            }
+            Expression::Or(lhs, rhs) => {
+                operand(f, lhs)?;
+                write!(f, " || ")?;
+                operand(f, rhs)
+            }
+            Expression::Not(inner) => {
+                write!(f, "!")?;
+                operand(f, inner)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::struct_parser;
+
+    fn parse(text: &str) -> Expression {
+        struct_parser::expression(text).unwrap()
+    }
+
+    /// Evaluates `text` with a field of each kind in scope.
+    fn eval(text: &str) -> Result<Scalar, EvalError> {
+        let header = Value::Struct {
+            fields: vec![("len".into(), Value::U16(12))],
+        };
+        let scope = [
+            ("a".to_string(), Value::U8(5)),
+            ("flags".to_string(), Value::U8(0x81)),
+            ("ratio".to_string(), Value::F32(0.5)),
+            ("valid".to_string(), Value::Bool(true)),
+            ("header".to_string(), header),
+        ];
+        parse(text).eval_fields(&scope)
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("a + 2 * 3").unwrap(), Scalar::Int(11));
+        assert_eq!(eval("(a + 2) * 3").unwrap(), Scalar::Int(21));
+        assert_eq!(eval("a / 2").unwrap(), Scalar::Int(2));
+        assert_eq!(eval("a % 2").unwrap(), Scalar::Int(1));
+        assert_eq!(eval("a * ratio").unwrap(), Scalar::Float(2.5));
+        assert_eq!(eval("-a + header.len").unwrap(), Scalar::Int(7));
+        assert_eq!(eval("flags & 0x80").unwrap(), Scalar::Int(0x80));
+        assert_eq!(eval("~flags & 0xFF").unwrap(), Scalar::Int(0x7E));
+        assert_eq!(eval("1 << a | flags >> 7").unwrap(), Scalar::Int(0x21));
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(
+            eval("flags & 0x80 != 0 && a > 4").unwrap(),
+            Scalar::Bool(true)
+        );
+        assert_eq!(eval("!valid || a == 6").unwrap(), Scalar::Bool(false));
+        assert_eq!(eval("ratio < a").unwrap(), Scalar::Bool(true));
+        assert_eq!(eval("valid == valid").unwrap(), Scalar::Bool(true));
+        assert_eq!(eval("valid != true || false").unwrap(), Scalar::Bool(false));
+        assert!(matches!(eval("trueish"), Err(EvalError::UnknownField(_))));
+        let nan = Scalar::Float(f64::NAN);
+        assert!(!nan.compare(CompareOp::Eq, nan).unwrap());
+        assert!(nan.compare(CompareOp::Ne, nan).unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(eval("a / 0"), Err(EvalError::DivisionByZero(_))));
+        assert!(matches!(eval("1 << 200"), Err(EvalError::Overflow(_))));
+        assert!(matches!(eval("b + 1"), Err(EvalError::UnknownField(name)) if name == "b"));
+        assert!(matches!(
+            eval("header.size"),
+            Err(EvalError::UnknownField(_))
+        ));
+        assert!(matches!(
+            eval("valid < valid"),
+            Err(EvalError::TypeMismatch(_))
+        ));
+        assert!(matches!(eval("ratio & 1"), Err(EvalError::TypeMismatch(_))));
+        assert!(matches!(
+            eval("a && valid"),
+            Err(EvalError::TypeMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn types() {
+        let field = |path: &[String]| match path[0].as_str() {
+            "valid" => Ok(ScalarType::Bool),
+            "ratio" => Ok(ScalarType::Float),
+            _ => Ok(ScalarType::Int),
+        };
+        let type_of = |text: &str| parse(text).type_of(&field);
+        assert_eq!(type_of("a + 1").unwrap(), ScalarType::Int);
+        assert_eq!(type_of("a * ratio").unwrap(), ScalarType::Float);
+        assert_eq!(type_of("a > 1 && valid").unwrap(), ScalarType::Bool);
+        assert_eq!(type_of("valid == false").unwrap(), ScalarType::Bool);
+        assert!(type_of("-valid").is_err());
+        assert!(type_of("~ratio").is_err());
+        assert!(type_of("!a").is_err());
+    }
+
+    #[test]
+    fn display_and_references() {
+        assert_eq!(parse("a+2*3").to_string(), "a + (2 * 3)");
+        assert_eq!(
+            parse("!(a>1)||header.len==2").to_string(),
+            "!(a > 1) || (header.len == 2)"
+        );
+        let expression = parse("header.len - a > 0");
+        let mut paths = Vec::new();
+        expression.visit_fields(&mut |path| paths.push(path.join(".")));
+        assert_eq!(paths, ["header.len", "a"]);
+        assert!(expression.refers_to("header"));
+        assert!(expression.refers_to("a"));
+        assert!(!expression.refers_to("len"));
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+
+use crate::epxr::Type;
+
+/// A fixed-point format in ARM's Q notation: `q8.8` is a signed 16 bit
+/// number with 8 integer bits, the sign included, and 8 fraction bits. `q15`
+/// is short for `q1.15`, and the `uq` formats are unsigned, with `uq16`
+/// short for `uq0.16`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fixed {
+    pub signed: bool,
+    pub int_bits: u32,
+    pub frac_bits: u32,
+}
+
+impl Fixed {
+    /// Parses a format name such as `q15` or `uq8.8`. The total width has to
+    /// be that of an integer primitive.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (signed, bits) = match name.strip_prefix("uq") {
+            Some(bits) => (false, bits),
+            None => (true, name.strip_prefix('q')?),
+        };
+        let number = |digits: &str| -> Option<u32> {
+            // Reject signs and leading zeros, so every format has one name.
+            if digits.starts_with(['+', '-']) || (digits.len() > 1 && digits.starts_with('0')) {
+                return None;
+            }
+            digits.parse().ok()
+        };
+        let (int_bits, frac_bits) = match bits.split_once('.') {
+            Some((int_bits, frac_bits)) => (number(int_bits)?, number(frac_bits)?),
+            None => (signed as u32, number(bits)?),
+        };
+        let fixed = Fixed {
+            signed,
+            int_bits,
+            frac_bits,
+        };
+        if signed && int_bits == 0 {
+            return None;
+        }
+        matches!(fixed.bits(), 8 | 16 | 32 | 64).then_some(fixed)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.int_bits + self.frac_bits
+    }
+
+    /// The integer type the raw value is stored as.
+    pub fn storage(&self) -> Type {
+        match (self.signed, self.bits()) {
+            (true, 8) => Type::I8,
+            (true, 16) => Type::I16,
+            (true, 32) => Type::I32,
+            (true, _) => Type::I64,
+            (false, 8) => Type::U8,
+            (false, 16) => Type::U16,
+            (false, 32) => Type::U32,
+            (false, _) => Type::U64,
+        }
+    }
+
+    /// The smallest and largest raw values.
+    fn range(&self) -> (i128, i128) {
+        if self.signed {
+            (
+                -(1i128 << (self.bits() - 1)),
+                (1i128 << (self.bits() - 1)) - 1,
+            )
+        } else {
+            (0, (1i128 << self.bits()) - 1)
+        }
+    }
+
+    /// Rounds `value` to the nearest raw value, `None` if it is out of range.
+    pub fn raw(&self, value: f64) -> Option<i128> {
+        let raw = (value * 2f64.powi(self.frac_bits as i32)).round();
+        let (min, max) = self.range();
+        // Compare as integers, as floats `u64::MAX` and the number after it
+        // are the same.
+        Some(raw as i128).filter(|raw| !value.is_nan() && (min..=max).contains(raw))
+    }
+
+    /// Rounds a plain decimal such as `-1.25` to the nearest raw value,
+    /// without going through a float, so every value [`decimal`] writes is
+    /// read back as is. Half way cases round away from zero, like
+    /// [`Fixed::raw`]. `None` if `text` is not a plain decimal or the value
+    /// is out of range.
+    pub fn parse_decimal(&self, text: &str) -> Option<i128> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        let (int, frac) = text.split_once('.').unwrap_or((text, ""));
+        let decimal = |digits: &str| digits.bytes().all(|digit| digit.is_ascii_digit());
+        if int.is_empty() || !decimal(int) || !decimal(frac) {
+            return None;
+        }
+        // The total width is at most 64 bits, so larger integer parts are out
+        // of range anyway.
+        let int = int.parse::<u64>().ok()? as u128;
+        // Double the fraction once per fraction bit, what carries over into
+        // the integer part is the next bit.
+        let mut digits = frac.bytes().map(|digit| digit - b'0').collect::<Vec<_>>();
+        let mut bits = 0u128;
+        for _ in 0..self.frac_bits {
+            let mut carry = 0;
+            for digit in digits.iter_mut().rev() {
+                let doubled = *digit * 2 + carry;
+                *digit = doubled % 10;
+                carry = doubled / 10;
+            }
+            bits = bits << 1 | carry as u128;
+        }
+        let round_up = digits.first().is_some_and(|digit| *digit >= 5);
+        let magnitude = (int << self.frac_bits | bits) + round_up as u128;
+        let raw = if negative {
+            -i128::try_from(magnitude).ok()?
+        } else {
+            i128::try_from(magnitude).ok()?
+        };
+        let (min, max) = self.range();
+        (min..=max).contains(&raw).then_some(raw)
+    }
+
+    /// The difference between two consecutive values, as an exact decimal.
+    pub fn resolution(&self) -> String {
+        decimal(1, self.frac_bits)
+    }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = if self.signed { "q" } else { "uq" };
+        write!(f, "{prefix}{}.{}", self.int_bits, self.frac_bits)
+    }
+}
+
+/// The value of `raw / 2^frac_bits` as a float, which is exact for up to 53
+/// significant bits.
+pub fn to_f64(raw: i128, frac_bits: u32) -> f64 {
+    raw as f64 / 2f64.powi(frac_bits as i32)
+}
+
+/// Writes `raw / 2^frac_bits` as an exact decimal. Every binary fraction has
+/// a finite decimal expansion, of at most `frac_bits` digits.
+pub fn decimal(raw: i128, frac_bits: u32) -> String {
+    let sign = if raw < 0 { "-" } else { "" };
+    let magnitude = raw.unsigned_abs();
+    let int = magnitude >> frac_bits;
+    let mut frac = magnitude & ((1u128 << frac_bits) - 1);
+    if frac == 0 {
+        return format!("{sign}{int}");
+    }
+    let mut digits = String::new();
+    while frac != 0 {
+        frac *= 10;
+        digits.push(char::from(b'0' + (frac >> frac_bits) as u8));
+        frac &= (1u128 << frac_bits) - 1;
+    }
+    format!("{sign}{int}.{digits}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(name: &str) -> Fixed {
+        Fixed::from_name(name).unwrap()
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(format("q15").to_string(), "q1.15");
+        assert_eq!(format("uq16").to_string(), "uq0.16");
+        assert_eq!(format("uq8.8").storage(), Type::U16);
+        assert_eq!(Fixed::from_name("q0.16"), None);
+        assert_eq!(Fixed::from_name("q8.08"), None);
+        assert_eq!(Fixed::from_name("q8.7"), None);
+        assert_eq!(format("q8.8").resolution(), "0.00390625");
+    }
+
+    #[test]
+    fn rounds_to_nearest_and_rejects_overflow() {
+        let q = format("q8.8");
+        assert_eq!(q.raw(1.5), Some(0x180));
+        assert_eq!(q.raw(0.001), Some(0));
+        assert_eq!(q.raw(0.002), Some(1));
+        assert_eq!(q.raw(-0.002), Some(-1));
+        assert_eq!(q.raw(127.99609375), Some(i16::MAX as i128));
+        assert_eq!(q.raw(-128.0), Some(i16::MIN as i128));
+        assert_eq!(q.raw(128.0), None);
+        assert_eq!(q.raw(-128.002), None);
+        assert_eq!(q.raw(f64::NAN), None);
+        assert_eq!(format("uq0.64").raw(1.0), None);
+        assert_eq!(format("uq8.8").raw(-0.5), None);
+    }
+
+    #[test]
+    fn decimals_are_exact() {
+        assert_eq!(decimal(0x180, 8), "1.5");
+        assert_eq!(decimal(-1, 8), "-0.00390625");
+        let max = u64::MAX as i128;
+        let digits = "0.9999999999999999999457898913757247782996273599565029144287109375";
+        assert_eq!(decimal(max, 64), digits);
+        assert_eq!(to_f64(max, 64), 1.0);
+
+        // What decimal writes reads back as the same raw value.
+        let uq = format("uq0.64");
+        assert_eq!(uq.parse_decimal(&decimal(max, 64)), Some(max));
+        assert_eq!(uq.parse_decimal("1"), None);
+        let q = format("q32.32");
+        let min = i64::MIN as i128;
+        assert_eq!(q.parse_decimal(&decimal(min, 32)), Some(min));
+        assert_eq!(q.parse_decimal(&decimal(min + 1, 32)), Some(min + 1));
+
+        // Digits beyond the resolution round to nearest, away from zero.
+        let q = format("q8.8");
+        assert_eq!(q.parse_decimal("0.001953125"), Some(1));
+        assert_eq!(q.parse_decimal("0.0019531"), Some(0));
+        assert_eq!(q.parse_decimal("-0.001953125"), Some(-1));
+        assert_eq!(q.parse_decimal("127.998046875"), None);
+        assert_eq!(q.parse_decimal("1e3"), None);
+        assert_eq!(q.parse_decimal("0x10"), None);
+    }
+}
@@ -0,0 +1,203 @@
+use serde_json::{Map, Number, Value as Json};
+use thiserror::Error;
+
+use crate::epxr::{Expr, LayoutId, Type};
+use crate::fixed;
+use crate::scaling::Scaling;
+use crate::utils::field_path;
+use crate::value::{Value, ValueParseError};
+
+#[derive(Debug, Error)]
+pub enum JsonError {
+    #[error("Unknown layout {0:?}")]
+    UnknownLayout(LayoutId),
+    #[error("{path}: expected {expected}")]
+    TypeMismatch { path: String, expected: String },
+    #[error("{path}: {error}")]
+    Invalid {
+        path: String,
+        error: ValueParseError,
+    },
+}
+
+impl Value {
+    /// Converts the value to JSON. Structs become objects with fields in
+    /// layout order, enums their name, or their number when unknown, and
+    /// timestamps, addresses and UUIDs strings. Bytes become hex strings, and
+    /// fixed-point numbers too wide for a float exact decimal strings.
+    pub fn to_json(&self) -> Json {
+        match self {
+            Value::Struct { fields } => Json::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.to_json()))
+                    .collect::<Map<_, _>>(),
+            ),
+            Value::Bool(v) => Json::Bool(*v),
+            Value::I8(v) | Value::I16(v) | Value::I32(v) | Value::I64(v) => Json::from(*v),
+            Value::U8(v) | Value::U16(v) | Value::U32(v) | Value::U64(v) => Json::from(*v),
+            // JSON has no NaN or infinity.
+            Value::F16(v) | Value::BF16(v) | Value::F32(v) | Value::F64(v) => {
+                Number::from_f64(*v).map_or(Json::Null, Json::Number)
+            }
+            Value::Array(elements) => Json::Array(elements.iter().map(Value::to_json).collect()),
+            Value::Enum { raw, name } => match name {
+                Some(name) => Json::String(name.clone()),
+                None => raw.to_json(),
+            },
+            Value::Scaled { value, .. } => Value::F64(*value).to_json(),
+            // A float holds up to 53 significant bits, wider values would be
+            // rounded and could no longer be encoded as they were read.
+            Value::Fixed { raw, frac_bits } if raw.unsigned_abs() < 1 << f64::MANTISSA_DIGITS => {
+                Value::F64(fixed::to_f64(*raw, *frac_bits)).to_json()
+            }
+            Value::Fixed { .. } => Json::String(self.to_string()),
+            Value::Timestamp { .. }
+            | Value::Ipv4(_)
+            | Value::Ipv6(_)
+            | Value::Mac(_)
+            | Value::Uuid(_)
+            | Value::Bytes(_) => Json::String(self.to_string()),
+            Value::Pointer { offset, target } => {
+                let mut object = Map::new();
+                object.insert("offset".to_string(), offset.map_or(Json::Null, Json::from));
+                let target = target
+                    .as_ref()
+                    .map_or(Json::Null, |target| target.to_json());
+                object.insert("target".to_string(), target);
+                Json::Object(object)
+            }
+        }
+    }
+
+    /// Reads an instance of the layout `id` from JSON. Numbers may also be
+    /// given as strings, which are parsed like [`Value::from_text`], so enums
+    /// can be written by name. Scaled fields are read in engineering units.
+    /// Fields missing from an object are left out, encoding fills in
+    /// constants and defaults.
+    pub fn from_json(expr: &Expr, id: LayoutId, json: &Json) -> Result<Value, JsonError> {
+        if expr.get_type(id).is_none() {
+            return Err(JsonError::UnknownLayout(id));
+        }
+        Self::from_json_type(expr, &Type::Struct(id), None, json, "")
+    }
+
+    fn from_json_type(
+        expr: &Expr,
+        ty: &Type,
+        scaling: Option<&Scaling>,
+        json: &Json,
+        path: &str,
+    ) -> Result<Value, JsonError> {
+        let mismatch = |expected: &str| JsonError::TypeMismatch {
+            path: path.to_string(),
+            expected: expected.to_string(),
+        };
+        match ty {
+            Type::Struct(id) => {
+                let layout = expr.get_type(*id).ok_or(JsonError::UnknownLayout(*id))?;
+                let Json::Object(object) = json else {
+                    return Err(mismatch("an object"));
+                };
+                let mut fields = Vec::new();
+                for field in &layout.fields {
+                    let Some(json) = object.get(&field.name) else {
+                        continue;
+                    };
+                    let path = field_path(path, &field.name);
+                    let scaling = field.scaling.as_ref();
+                    let value = Self::from_json_type(expr, &field.ty, scaling, json, &path)?;
+                    fields.push((field.name.clone(), value));
+                }
+                Ok(Value::Struct { fields })
+            }
+            Type::Array(element, _) => {
+                let Json::Array(elements) = json else {
+                    return Err(mismatch("an array"));
+                };
+                let elements = elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, json)| {
+                        let path = format!("{path}[{i}]");
+                        Self::from_json_type(expr, element, scaling, json, &path)
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Array(elements))
+            }
+            Type::Pointer(pointer) => {
+                // `{"offset": 64, "target": ...}`, the offset may be left out
+                // or null to have the value placed when encoding. A null
+                // target, or null itself, is a null pointer.
+                let object = match json {
+                    Json::Object(object) => object,
+                    Json::Null => {
+                        return Ok(Value::Pointer {
+                            offset: None,
+                            target: None,
+                        });
+                    }
+                    _ => return Err(mismatch("an object or null")),
+                };
+                let offset = match object.get("offset") {
+                    None | Some(Json::Null) => None,
+                    Some(offset) => {
+                        Some(offset.as_i64().ok_or_else(|| JsonError::TypeMismatch {
+                            path: field_path(path, "offset"),
+                            expected: "an integer".to_string(),
+                        })?)
+                    }
+                };
+                let target = match object.get("target") {
+                    None | Some(Json::Null) => None,
+                    Some(target) => Some(Box::new(Self::from_json_type(
+                        expr,
+                        &pointer.target,
+                        scaling,
+                        target,
+                        path,
+                    )?)),
+                };
+                Ok(Value::Pointer { offset, target })
+            }
+            _ => {
+                let text = match json {
+                    Json::Bool(v) => v.to_string(),
+                    Json::Number(number) => number.to_string(),
+                    Json::String(text) => text.clone(),
+                    _ => return Err(mismatch("a bool, number or string")),
+                };
+                let value = match scaling {
+                    Some(scaling) => Value::from_scaled_text(expr, ty, scaling, &text),
+                    None => Value::from_text(expr, ty, &text),
+                };
+                value.map_err(|error| JsonError::Invalid {
+                    path: path.to_string(),
+                    error,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn fixed_point_numbers_survive_a_round_trip() {
+        let expr = Expr::parse("struct A { x: uq0.64, y: q8.8 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let bytes = [[0xFF; 8].as_slice(), &[0x80, 0x01]].concat();
+        let json = expr.read_value(&bytes, id).unwrap().to_json();
+        let x = "0.9999999999999999999457898913757247782996273599565029144287109375";
+        assert_eq!(json, json!({ "x": x, "y": 1.5 }));
+
+        let value = Value::from_json(&expr, id, &json).unwrap();
+        assert_eq!(value.encode_value(&expr, id).unwrap(), bytes);
+        let value = Value::from_json(&expr, id, &json!({ "x": "1", "y": 1.5 })).unwrap_err();
+        assert!(matches!(value, JsonError::Invalid { path, .. } if path == "x"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::ops::Range;
+
+use crate::epxr::Field;
+use crate::value::Value;
+
+/// A field holding the size of other fields of its struct, filled in when
+/// encoding and checked when decoding, declared as
+/// `payload_len: u16 = len(payload)` or `total_len: u16 = len(..) + 2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Length {
+    pub unit: LengthUnit,
+    /// Indices of the fields that are measured, in the same struct. May
+    /// include the length field itself.
+    pub fields: Range<usize>,
+    /// Added to the measured size, e.g. for bytes the range leaves out.
+    pub adjust: i128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthUnit {
+    /// `len(...)`, the encoded size of the fields.
+    Bytes,
+    /// `count(items)`, the number of elements of an array field.
+    Elements,
+}
+
+impl Length {
+    /// The length of the measured fields, given where each field of the
+    /// struct starts, followed by where it ends, and the value of the field
+    /// `count` measures.
+    pub fn measure(&self, bounds: &[usize], counted: Option<&Value>) -> i128 {
+        let size = match (self.unit, counted) {
+            (LengthUnit::Bytes, _) => bounds[self.fields.end] - bounds[self.fields.start],
+            (LengthUnit::Elements, Some(Value::Array(elements))) => elements.len(),
+            // A conditional array that was left out.
+            (LengthUnit::Elements, _) => 0,
+        };
+        size as i128 + self.adjust
+    }
+
+    /// Describes the length with the names of the fields it measures, e.g.
+    /// `len(header..=payload) + 2`.
+    pub fn describe(&self, fields: &[Field]) -> String {
+        let first = &fields[self.fields.start].name;
+        let last = &fields[self.fields.end - 1].name;
+        let function = match self.unit {
+            LengthUnit::Bytes => "len",
+            LengthUnit::Elements => "count",
+        };
+        let range = if first == last {
+            first.clone()
+        } else {
+            format!("{first}..={last}")
+        };
+        match self.adjust {
+            0 => format!("{function}({range})"),
+            adjust if adjust < 0 => format!("{function}({range}) - {}", -adjust),
+            adjust => format!("{function}({range}) + {adjust}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::epxr::{Expr, ReadError};
+    use crate::value::Value;
+
+    #[test]
+    fn lengths_are_filled_in_and_checked() {
+        let source = "struct A { total: u8 = len(..) + 1, a: u16, b: [u8; 3], n: u8 = count(b) }";
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = Value::Struct {
+            fields: vec![
+                ("total".into(), Value::U8(0)),
+                ("a".into(), Value::U16(7)),
+                ("b".into(), Value::Array(vec![Value::U8(1); 3])),
+            ],
+        };
+        // Stale lengths are replaced when encoding.
+        let bytes = value.encode_value(&expr, id).unwrap();
+        assert_eq!(bytes, [8, 7, 0, 1, 1, 1, 3]);
+        let decoded = expr.read_value(&bytes, id).unwrap();
+        assert_eq!(decoded.field("total"), Some(&Value::U8(8)));
+
+        let error = expr.read_value(&[7, 7, 0, 1, 1, 1, 3], id).unwrap_err();
+        assert!(matches!(
+            error,
+            ReadError::LengthMismatch { path, expected: 8, found: 7, .. } if path == "total"
+        ));
+        let error = expr.read_value(&[8, 7, 0, 1, 1, 1, 2], id).unwrap_err();
+        assert!(matches!(error, ReadError::LengthMismatch { path, .. } if path == "n"));
+    }
+}
@@ -1,4 +1,16 @@
+pub mod address;
+pub mod bits;
+pub mod checksum;
 pub mod epxr;
+pub mod expression;
+pub mod fixed;
+pub mod json;
+pub mod length;
 pub mod parser;
+pub mod pointer;
+pub mod resolver;
+pub mod scaling;
+pub mod time;
 pub mod utils;
+pub mod validate;
 pub mod value;
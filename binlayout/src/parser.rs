@@ -1,9 +1,406 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, io, ops::Range};
 
 use peg::str::LineCol;
 use thiserror::Error;
 
-use crate::epxr::{Expr, LayoutId, Struct, Type};
+use crate::bits::BitOrder;
+use crate::checksum::{Algorithm, Checksum, ChecksumError};
+use crate::epxr::{EnumId, Enumeration, Expr, Field, LayoutId, Size, Struct, Type, lookup_scoped};
+use crate::expression::{BinaryOp, CompareOp, EvalError, Expression, Scalar, ScalarType};
+use crate::length::{Length, LengthUnit};
+use crate::pointer::{Base, Location, Pointer};
+use crate::resolver::{MemoryResolver, Resolver, Source};
+use crate::scaling::{Rounding, Scaling};
+use crate::time::{Epoch, Timestamp, Unit};
+use crate::validate::Constraint;
+use crate::value::Value;
+
+/// A top level declaration in a layout file.
+#[derive(Debug, Clone)]
+pub enum Item {
+    Import { path: String, alias: Option<String> },
+    Struct(StructDef),
+    Module { name: String, items: Vec<Item> },
+    Alias { name: String, ty: TypeRef },
+    Enum(EnumDef),
+}
+
+#[derive(Debug, Clone)]
+pub struct StructDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub fields: Vec<FieldDef>,
+    pub asserts: Vec<Expression>,
+    /// Declared with `union`, the fields are views of the same bytes.
+    pub union: bool,
+    /// Struct attributes, `#[bits]` is the only one.
+    pub attributes: Vec<Attribute>,
+}
+
+/// A table of named values, e.g. `enum Mode: u8 { IDLE, ARMED, FAULT = 7 }`.
+/// Values without an explicit number follow the previous one, starting at 0.
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    pub name: String,
+    pub storage: TypeRef,
+    pub variants: Vec<(String, Option<i128>)>,
+}
+
+/// A member of a struct body.
+#[derive(Debug, Clone)]
+pub enum Member {
+    Field(Box<FieldDef>),
+    Assert(Expression),
+    /// `if condition { members }`.
+    If(Expression, Vec<Member>),
+}
+
+/// Moves the members of `if` blocks into `fields` and `asserts`, giving them
+/// the block's condition. An assertion in a block only applies when the
+/// condition holds.
+fn flatten_members(
+    members: Vec<Member>,
+    condition: Option<&Expression>,
+    fields: &mut Vec<FieldDef>,
+    asserts: &mut Vec<Expression>,
+) {
+    let and = |inner: Expression| match condition {
+        Some(condition) => Expression::And(Box::new(condition.clone()), Box::new(inner)),
+        None => inner,
+    };
+    for member in members {
+        match member {
+            Member::Field(mut field) => {
+                field.condition = match field.condition.take() {
+                    Some(own) => Some(and(own)),
+                    None => condition.cloned(),
+                };
+                fields.push(*field);
+            }
+            Member::Assert(assertion) => asserts.push(match condition {
+                Some(condition) => Expression::Or(
+                    Box::new(Expression::Not(Box::new(condition.clone()))),
+                    Box::new(assertion),
+                ),
+                None => assertion,
+            }),
+            Member::If(inner, members) => {
+                flatten_members(members, Some(&and(inner)), fields, asserts);
+            }
+        }
+    }
+}
+
+/// `ty` with the inline structs in it packed in `order`, like the `#[bits]`
+/// struct they are declared in.
+fn packed(ty: &TypeRef, order: BitOrder) -> TypeRef {
+    match ty {
+        TypeRef::Struct(def) if !def.union && def.attributes.is_empty() => {
+            let mut def = def.clone();
+            def.attributes.push(Attribute {
+                name: "bits".to_string(),
+                args: vec![AttributeArg::String(order.name().to_string())],
+            });
+            TypeRef::Struct(def)
+        }
+        TypeRef::Array { element, len } => TypeRef::Array {
+            element: Box::new(packed(element, order)),
+            len: len.clone(),
+        },
+        _ => ty.clone(),
+    }
+}
+
+/// How an inline struct is written, given its fields as `name: type`.
+fn inline_name(union: bool, fields: impl Iterator<Item = String>) -> String {
+    let kind = if union { "union" } else { "struct" };
+    let fields = fields.collect::<Vec<_>>();
+    match fields.is_empty() {
+        true => format!("{kind} {{}}"),
+        false => format!("{kind} {{ {} }}", fields.join(", ")),
+    }
+}
+
+fn binary(lhs: Expression, op: BinaryOp, rhs: Expression) -> Expression {
+    Expression::Binary(Box::new(lhs), op, Box::new(rhs))
+}
+
+/// A generic parameter of a struct, either a type `T` or a constant `const N`.
+#[derive(Debug, Clone)]
+pub enum Param {
+    Type(String),
+    Const(String),
+}
+
+impl Param {
+    pub fn name(&self) -> &str {
+        match self {
+            Param::Type(name) | Param::Const(name) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldDef {
+    pub name: String,
+    pub ty: TypeRef,
+    /// The value of a `const` field.
+    pub constant: Option<Literal>,
+    pub default: Option<Literal>,
+    pub attributes: Vec<Attribute>,
+    /// The field is only present when this holds, e.g. `ext: u8 if flags == 1`
+    /// or a field inside an `if flags == 1 { ... }` block.
+    pub condition: Option<Expression>,
+    /// `= crc16_ccitt(version..crc)`, the field holds a checksum.
+    pub checksum: Option<ChecksumDef>,
+    /// `= len(payload)`, the field holds the size of other fields.
+    pub length: Option<LengthDef>,
+    /// `derived total: u16 = count * 2`, the field holds a value computed
+    /// from the fields before it.
+    pub derived: Option<Expression>,
+    /// `@ 0x40` or `@ self + offset`, where the field lives.
+    pub location: Option<Location>,
+}
+
+impl FieldDef {
+    /// Whether the field only takes up space: `_: pad[3]`, any other
+    /// `pad[N]` field, one named `_`, or one marked `#[reserved]`.
+    fn is_padding(&self) -> bool {
+        matches!(self.ty, TypeRef::Pad(_))
+            || self.name == "_"
+            || self
+                .attributes
+                .iter()
+                .any(|attribute| attribute.name == "reserved")
+    }
+
+    /// Whether the field's value is computed when encoding.
+    fn is_computed(&self) -> bool {
+        self.checksum.is_some() || self.length.is_some() || self.derived.is_some()
+    }
+}
+
+/// The size of a range of fields as written, e.g. `len(header..=payload) + 2`.
+#[derive(Debug, Clone)]
+pub struct LengthDef {
+    pub unit: LengthUnit,
+    pub range: FieldRange,
+    pub adjust: i128,
+}
+
+/// A checksum over a range of fields as written, e.g.
+/// `crc(header..=payload, poly = 0x1021)`.
+#[derive(Debug, Clone)]
+pub struct ChecksumDef {
+    pub algorithm: String,
+    pub range: FieldRange,
+    pub params: Vec<(String, Literal)>,
+}
+
+/// A range of fields of a struct, `start..end`, `start..=end`, `..end` or
+/// `start..`, where leaving out an end extends it to that end of the struct.
+/// A single field `name` is the range `name..=name`.
+#[derive(Debug, Clone)]
+pub struct FieldRange {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub inclusive: bool,
+}
+
+impl fmt::Display for FieldRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(start), Some(end), true) = (&self.start, &self.end, self.inclusive)
+            && start == end
+        {
+            return write!(f, "{start}");
+        }
+        let dots = if self.inclusive { "..=" } else { ".." };
+        let start = self.start.as_deref().unwrap_or("");
+        let end = self.end.as_deref().unwrap_or("");
+        write!(f, "{start}{dots}{end}")
+    }
+}
+
+/// A keyword before a field name.
+enum Modifier {
+    /// `const`, the field always holds its value.
+    Const,
+    /// `derived`, the field is computed from the fields before it.
+    Derived,
+}
+
+/// What follows the `=` of a field.
+enum Initializer {
+    Literal(Literal),
+    Checksum(ChecksumDef),
+    Length(LengthDef),
+    Expression(Expression),
+}
+
+/// An annotation on a field or struct, e.g. `#[range(0, 360)]`.
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub args: Vec<AttributeArg>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AttributeArg {
+    Literal(Literal),
+    String(String),
+}
+
+/// A number or bool as written in a layout file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Literal {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Int(value) => write!(f, "{value}"),
+            // Keep the fraction, `2.0` and `2` differ in expressions.
+            Literal::Float(value) => write!(f, "{value:?}"),
+            Literal::Bool(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl Literal {
+    /// Converts the literal to a value of type `ty`, if it is representable.
+    /// For arrays, every element is set to the literal.
+    pub fn to_value(self, expr: &Expr, ty: &Type) -> Option<Value> {
+        Some(match (ty, self) {
+            (Type::Array(element, Some(len)), literal) => {
+                Value::Array(vec![literal.to_value(expr, element)?; *len])
+            }
+            (Type::Enum(id), Literal::Int(v)) => expr.enum_value(*id, v)?,
+            (Type::Bool(_), Literal::Int(v @ (0 | 1))) => Value::Bool(v == 1),
+            (Type::Bool(_), Literal::Bool(v)) => Value::Bool(v),
+            (Type::Fixed(fixed), Literal::Int(v)) => Value::Fixed {
+                raw: fixed.raw(v as f64)?,
+                frac_bits: fixed.frac_bits,
+            },
+            (Type::Fixed(fixed), Literal::Float(v)) => Value::Fixed {
+                raw: fixed.raw(v)?,
+                frac_bits: fixed.frac_bits,
+            },
+            (Type::Timestamp(timestamp), Literal::Int(v)) => {
+                Literal::Int(v).to_value(expr, &timestamp.storage)?;
+                Value::Timestamp {
+                    raw: v,
+                    epoch: timestamp.epoch,
+                    unit: timestamp.unit,
+                }
+            }
+            (Type::I8, Literal::Int(v)) => Value::I8(i8::try_from(v).ok()? as i64),
+            (Type::I16, Literal::Int(v)) => Value::I16(i16::try_from(v).ok()? as i64),
+            (Type::I32, Literal::Int(v)) => Value::I32(i32::try_from(v).ok()? as i64),
+            (Type::I64 | Type::VarInt | Type::ZigZag, Literal::Int(v)) => {
+                Value::I64(i64::try_from(v).ok()?)
+            }
+            (Type::U8, Literal::Int(v)) => Value::U8(u8::try_from(v).ok()? as u64),
+            (Type::U16, Literal::Int(v)) => Value::U16(u16::try_from(v).ok()? as u64),
+            (Type::U32, Literal::Int(v)) => Value::U32(u32::try_from(v).ok()? as u64),
+            (Type::U64 | Type::UVarInt, Literal::Int(v)) => Value::U64(u64::try_from(v).ok()?),
+            (
+                Type::Bits {
+                    signed: true,
+                    width,
+                },
+                Literal::Int(v),
+            ) => {
+                let limit = 1i128 << (width - 1);
+                if !(-limit..limit).contains(&v) {
+                    return None;
+                }
+                match width {
+                    ..=8 => Value::I8(v as i64),
+                    ..=16 => Value::I16(v as i64),
+                    ..=32 => Value::I32(v as i64),
+                    _ => Value::I64(v as i64),
+                }
+            }
+            (
+                Type::Bits {
+                    signed: false,
+                    width,
+                },
+                Literal::Int(v),
+            ) => {
+                if !(0..1i128 << width).contains(&v) {
+                    return None;
+                }
+                match width {
+                    ..=8 => Value::U8(v as u64),
+                    ..=16 => Value::U16(v as u64),
+                    ..=32 => Value::U32(v as u64),
+                    _ => Value::U64(v as u64),
+                }
+            }
+            (Type::F16, Literal::Int(v)) => Value::F16(v as f64),
+            (Type::BF16, Literal::Int(v)) => Value::BF16(v as f64),
+            (Type::F32, Literal::Int(v)) => Value::F32(v as f64),
+            (Type::F64, Literal::Int(v)) => Value::F64(v as f64),
+            (Type::F16, Literal::Float(v)) => Value::F16(v),
+            (Type::BF16, Literal::Float(v)) => Value::BF16(v),
+            (Type::F32, Literal::Float(v)) => Value::F32(v),
+            (Type::F64, Literal::Float(v)) => Value::F64(v),
+            _ => return None,
+        })
+    }
+}
+
+/// A type as written in a layout file, before names are resolved.
+#[derive(Debug, Clone)]
+pub enum TypeRef {
+    Named {
+        name: String,
+        args: Vec<TypeRef>,
+    },
+    /// `[T; N]`, or `[T; count]` for a length from the fields before it.
+    Array {
+        element: Box<TypeRef>,
+        len: Box<Expression>,
+    },
+    /// `pad[N]`, `N` bytes of padding.
+    Pad(Box<TypeRef>),
+    /// An integer, used as a padding length or a `const` generic argument.
+    Const(u64),
+    /// A struct or union declared in place, `struct { x: f32, y: f32 }`.
+    Struct(Box<StructDef>),
+    /// `bytes[N]`, or `bytes[len]` for a length from the fields before it.
+    Bytes(Box<Expression>),
+}
+
+impl fmt::Display for TypeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeRef::Named { name, args } => {
+                write!(f, "{name}")?;
+                if !args.is_empty() {
+                    let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+                    write!(f, "<{}>", args.join(", "))?;
+                }
+                Ok(())
+            }
+            TypeRef::Array { element, len } => write!(f, "[{element}; {len}]"),
+            TypeRef::Pad(len) => write!(f, "pad[{len}]"),
+            TypeRef::Const(value) => write!(f, "{value}"),
+            TypeRef::Bytes(len) => write!(f, "bytes[{len}]"),
+            TypeRef::Struct(def) => {
+                let fields = def
+                    .fields
+                    .iter()
+                    .map(|field| format!("{}: {}", field.name, field.ty));
+                write!(f, "{}", inline_name(def.union, fields))
+            }
+        }
+    }
+}
 
 peg::parser! {
     pub grammar struct_parser() for str {
@@ -11,23 +408,255 @@ peg::parser! {
             = s:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*) { s.to_string() }
 
         rule type_name() -> String
-            = identifier()
+            = s:$(identifier() ++ "::") { s.to_string() }
 
-        rule field() -> (String, String)
-            = name:identifier() _ ":" _ type_name:type_name() _ ","? _ { (name, type_name) }
+        rule integer() -> u64
+            = "0x" n:$(['0'..='9' | 'a'..='f' | 'A'..='F']+) {? u64::from_str_radix(n, 16).or(Err("integer")) }
+            / n:$(['0'..='9']+) {? n.parse().or(Err("integer")) }
 
-        rule fields() -> Vec<(String, String)>
-            = field_list:(field()*) { field_list }
+        rule string() -> String
+            = "\"" s:$([^ '"' | '\n']*) "\"" { s.to_string() }
 
-        rule struct_def() -> (String, Vec<(String, String)>)
-            = _ "struct" _ name:identifier() _ "{" _ fields:fields() _ "}" { (name, fields) }
+        rule type_ref() -> TypeRef
+            = "[" _ element:type_ref() _ ";" _ len:expression() _ "]" {
+                TypeRef::Array { element: Box::new(element), len: Box::new(len) }
+            }
+            / "pad" _ "[" _ len:type_arg() _ "]" { TypeRef::Pad(Box::new(len)) }
+            / "bytes" _ "[" _ len:expression() _ "]" { TypeRef::Bytes(Box::new(len)) }
+            / union:struct_kind() _ body:struct_body() {
+                let (fields, asserts) = body;
+                let def = StructDef {
+                    name: String::new(),
+                    params: Vec::new(),
+                    fields,
+                    asserts,
+                    union,
+                    attributes: Vec::new(),
+                };
+                TypeRef::Struct(Box::new(def))
+            }
+            / name:$(("uq" / "q") ['0'..='9']+ "." ['0'..='9']+) {
+                TypeRef::Named { name: name.to_string(), args: Vec::new() }
+            }
+            / name:type_name() _ args:("<" _ args:(type_arg() ** (_ "," _)) _ ">" { args })? {
+                TypeRef::Named { name, args: args.unwrap_or_default() }
+            }
+
+        rule type_arg() -> TypeRef
+            = n:integer() { TypeRef::Const(n) }
+            / type_ref()
+
+        rule literal() -> Literal
+            = n:$("-"? ['0'..='9']+ "." ['0'..='9']+ (['e' | 'E'] ['-' | '+']? ['0'..='9']+)?) {?
+                n.parse().map(Literal::Float).or(Err("float"))
+            }
+            / "-" _ n:integer() { Literal::Int(-(n as i128)) }
+            / n:integer() { Literal::Int(n as i128) }
+            / "true" !['a'..='z' | 'A'..='Z' | '0'..='9' | '_'] { Literal::Bool(true) }
+            / "false" !['a'..='z' | 'A'..='Z' | '0'..='9' | '_'] { Literal::Bool(false) }
 
+        rule param() -> Param
+            = "const" __ name:identifier() { Param::Const(name) }
+            / name:identifier() { Param::Type(name) }
 
-        pub rule structs() -> Vec<(String, Vec<(String, String)>)>
-            = struct_defs:(struct_def()*) _ { struct_defs }
+        rule params() -> Vec<Param>
+            = "<" _ params:(param() ** (_ "," _)) _ ">" { params }
 
+        rule compare_op() -> CompareOp
+            = "==" { CompareOp::Eq }
+            / "!=" { CompareOp::Ne }
+            / "<=" { CompareOp::Le }
+            / ">=" { CompareOp::Ge }
+            / "<" !"<" { CompareOp::Lt }
+            / ">" !">" { CompareOp::Gt }
 
-        rule _() = [' ' | '\n' | '\t']*
+        pub rule expression() -> Expression = precedence!{
+            x:(@) _ "||" _ y:@ { Expression::Or(Box::new(x), Box::new(y)) }
+            --
+            x:(@) _ "&&" _ y:@ { Expression::And(Box::new(x), Box::new(y)) }
+            --
+            x:(@) _ op:compare_op() _ y:@ { Expression::Compare(Box::new(x), op, Box::new(y)) }
+            --
+            x:(@) _ "|" !"|" _ y:@ { binary(x, BinaryOp::BitOr, y) }
+            --
+            x:(@) _ "^" _ y:@ { binary(x, BinaryOp::BitXor, y) }
+            --
+            x:(@) _ "&" !"&" _ y:@ { binary(x, BinaryOp::BitAnd, y) }
+            --
+            x:(@) _ "<<" _ y:@ { binary(x, BinaryOp::Shl, y) }
+            x:(@) _ ">>" _ y:@ { binary(x, BinaryOp::Shr, y) }
+            --
+            x:(@) _ "+" _ y:@ { binary(x, BinaryOp::Add, y) }
+            x:(@) _ "-" _ y:@ { binary(x, BinaryOp::Sub, y) }
+            --
+            x:(@) _ "*" _ y:@ { binary(x, BinaryOp::Mul, y) }
+            x:(@) _ "/" _ y:@ { binary(x, BinaryOp::Div, y) }
+            x:(@) _ "%" _ y:@ { binary(x, BinaryOp::Rem, y) }
+            --
+            "!" _ x:@ { Expression::Not(Box::new(x)) }
+            "-" !['0'..='9'] _ x:@ { Expression::Negate(Box::new(x)) }
+            "~" _ x:@ { Expression::Complement(Box::new(x)) }
+            --
+            literal:literal() { Expression::Literal(literal) }
+            path:(identifier() ++ ".") { Expression::Field(path) }
+            "(" _ e:expression() _ ")" { e }
+        }
+
+        rule attribute_arg() -> AttributeArg
+            = literal:literal() { AttributeArg::Literal(literal) }
+            / s:string() { AttributeArg::String(s) }
+
+        rule attribute() -> Attribute
+            = "#[" _ name:identifier() _ args:("(" _ args:(attribute_arg() ** (_ "," _)) _ ")" { args })? _ "]" _ {
+                Attribute { name, args: args.unwrap_or_default() }
+            }
+
+        rule field_range() -> FieldRange
+            = start:identifier()? _ ".." inclusive:"="? _ end:identifier()? {?
+                if inclusive.is_some() && end.is_none() {
+                    return Err("range end");
+                }
+                Ok(FieldRange { start, end, inclusive: inclusive.is_some() })
+            }
+            / name:identifier() {
+                FieldRange { start: Some(name.clone()), end: Some(name), inclusive: true }
+            }
+
+        rule length() -> LengthDef
+            = unit:("len" { LengthUnit::Bytes } / "count" { LengthUnit::Elements }) _
+              "(" _ range:field_range() _ ")"
+              adjust:(_ sign:$(['+' | '-']) _ n:integer() {
+                  if sign == "-" { -(n as i128) } else { n as i128 }
+              })? {
+                LengthDef { unit, range, adjust: adjust.unwrap_or(0) }
+            }
+
+        rule checksum_param() -> (String, Literal)
+            = "," _ name:identifier() _ "=" _ value:literal() _ { (name, value) }
+
+        rule checksum() -> ChecksumDef
+            = algorithm:identifier() _ "(" _ range:field_range() _ params:checksum_param()* ")" {
+                ChecksumDef { algorithm, range, params }
+            }
+
+        rule modifier() -> Modifier
+            = "const" { Modifier::Const }
+            / "derived" { Modifier::Derived }
+
+        rule initializer() -> Initializer
+            = length:length() { Initializer::Length(length) }
+            / checksum:checksum() { Initializer::Checksum(checksum) }
+            / e:expression() {
+                // Literals alone, such as `-1` or `2 * 8`, are a default.
+                match e.eval_fields(&[]) {
+                    Ok(Scalar::Int(value)) => Initializer::Literal(Literal::Int(value)),
+                    Ok(Scalar::Float(value)) => Initializer::Literal(Literal::Float(value)),
+                    Ok(Scalar::Bool(value)) => Initializer::Literal(Literal::Bool(value)),
+                    _ => Initializer::Expression(e),
+                }
+            }
+
+        rule field() -> FieldDef
+            = attributes:attribute()* modifier:(modifier:modifier() __ { modifier })?
+              name:identifier() _ ":" _ ty:type_ref() _
+              location:("@" _ location:location() _ { location })?
+              value:("=" _ value:initializer() _ { value })?
+              condition:("if" __ condition:expression() _ !"{" { condition })? ","? _ {?
+                let (mut constant, mut default, mut checksum, mut length, mut derived) =
+                    (None, None, None, None, None);
+                match (modifier, value) {
+                    (Some(Modifier::Const), Some(Initializer::Literal(v))) => constant = Some(v),
+                    (Some(Modifier::Const), _) => return Err("constant value"),
+                    // Computed from the fields before it, spelled out so it is
+                    // not mistaken for a default.
+                    (Some(Modifier::Derived), Some(Initializer::Expression(e))) => derived = Some(e),
+                    (Some(Modifier::Derived), Some(Initializer::Literal(v))) => {
+                        derived = Some(Expression::Literal(v));
+                    }
+                    (Some(Modifier::Derived), _) => return Err("expression"),
+                    (None, Some(Initializer::Expression(_))) => {
+                        return Err("constant default, or `derived` for a computed field");
+                    }
+                    (None, Some(Initializer::Literal(value))) => default = Some(value),
+                    (None, Some(Initializer::Checksum(def))) => checksum = Some(def),
+                    (None, Some(Initializer::Length(def))) => length = Some(def),
+                    (None, None) => {}
+                }
+                Ok(FieldDef {
+                    name, ty, constant, default, attributes, condition, checksum, length, derived,
+                    location,
+                })
+            }
+
+        rule location() -> Location
+            = "self" _ "+" _ offset:expression() { Location { base: Base::Struct, offset } }
+            / offset:expression() { Location { base: Base::Buffer, offset } }
+
+        rule assertion() -> Expression
+            = "assert" _ "(" _ e:expression() _ ")" _ [',' | ';']? _ { e }
+
+        rule conditional() -> Member
+            = "if" __ condition:expression() _ "{" _ members:(member()*) _ "}" _ {
+                Member::If(condition, members)
+            }
+
+        rule member() -> Member
+            = conditional()
+            / field:field() { Member::Field(Box::new(field)) }
+            / assertion:assertion() { Member::Assert(assertion) }
+
+        rule struct_kind() -> bool
+            = kind:$("struct" / "union") { kind == "union" }
+
+        rule struct_body() -> (Vec<FieldDef>, Vec<Expression>)
+            = "{" _ members:(member()*) _ "}" {
+                let mut fields = Vec::new();
+                let mut asserts = Vec::new();
+                flatten_members(members, None, &mut fields, &mut asserts);
+                (fields, asserts)
+            }
+
+        rule struct_def() -> Item
+            = attributes:attribute()* union:struct_kind() _ name:identifier() _ params:params()? _ body:struct_body() {
+                let (fields, asserts) = body;
+                let params = params.unwrap_or_default();
+                Item::Struct(StructDef { name, params, fields, asserts, union, attributes })
+            }
+
+        rule variant() -> (String, Option<i128>)
+            = name:identifier() _ value:("=" _ value:literal() {? match value {
+                Literal::Int(value) => Ok(value),
+                Literal::Float(_) | Literal::Bool(_) => Err("integer"),
+            } })? { (name, value) }
+
+        rule enum_def() -> Item
+            = "enum" _ name:identifier() _ ":" _ storage:type_ref() _ "{" _
+              variants:(variant() ** (_ "," _)) _ ","? _ "}" {
+                Item::Enum(EnumDef { name, storage, variants })
+            }
+
+        rule import() -> Item
+            = "import" _ path:string() _ alias:("as" _ alias:identifier() _ { alias })? ";" {
+                Item::Import { path, alias }
+            }
+
+        rule alias() -> Item
+            = "type" _ name:identifier() _ "=" _ ty:type_ref() _ ";" { Item::Alias { name, ty } }
+
+        rule module() -> Item
+            = ("mod" / "package") _ name:identifier() _ "{" _ items:item_list() _ "}" {
+                Item::Module { name, items }
+            }
+
+        rule item_list() -> Vec<Item>
+            = (import() / struct_def() / enum_def() / alias() / module()) ** _
+
+        pub rule items() -> Vec<Item>
+            = _ items:item_list() _ { items }
+
+
+        rule _() = [' ' | '\n' | '\t' | '\r']*
+        rule __() = [' ' | '\n' | '\t' | '\r']+
     }
 }
 
@@ -37,48 +666,1858 @@ pub enum ParseError {
     Parse(#[from] peg::error::ParseError<LineCol>),
     #[error("Unknown type: {0}")]
     UnknownType(String),
+    #[error("Cannot import \"{path}\": {error}")]
+    Import { path: String, error: io::Error },
+    #[error("Import cycle: {}", .0.join(" -> "))]
+    ImportCycle(Vec<String>),
+    #[error("Duplicate definition of {0}")]
+    DuplicateName(String),
+    #[error("Expected an integer type, found {0}")]
+    ExpectedInteger(String),
+    #[error("Unknown epoch {0}, expected unix, gps or ntp")]
+    UnknownEpoch(String),
+    #[error("Unknown time unit {0}, expected ns, us, ms, s, day or week")]
+    UnknownTimeUnit(String),
+    #[error("Type alias {0} refers to itself")]
+    AliasCycle(String),
+    #[error("{name} takes {expected} generic arguments but {found} were given")]
+    GenericArity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Expected a type, found {0}")]
+    ExpectedType(String),
+    #[error("Expected a constant, found {0}")]
+    ExpectedConst(String),
+    #[error("Instantiating {0} requires instantiating it again")]
+    InstantiationCycle(String),
+    #[error("{field}: unknown attribute #[{name}]")]
+    UnknownAttribute { field: String, name: String },
+    #[error("{field}: invalid arguments to #[{name}]")]
+    InvalidAttribute { field: String, name: String },
+    #[error("Unknown field {0}")]
+    UnknownField(String),
+    #[error("{expression}: {error}")]
+    InvalidExpression {
+        expression: String,
+        error: EvalError,
+    },
+    #[error("{field}: {literal} is not a valid {ty} value")]
+    InvalidLiteral {
+        field: String,
+        literal: Literal,
+        ty: String,
+    },
+    #[error("{field}: {error}")]
+    Checksum { field: String, error: ChecksumError },
+    #[error("{field}: the range {range} covers no fields")]
+    EmptyRange { field: String, range: String },
+    #[error("{0}: a length has to be stored in a fixed-size integer")]
+    InvalidLengthType(String),
+    #[error("{field}: count() takes a single array field, found {range}")]
+    InvalidCount { field: String, range: String },
+    #[error("{field}: conditions cannot refer to {computed}, it is computed when encoding")]
+    ComputedInCondition { field: String, computed: String },
+    #[error("{0} has a negative length")]
+    InvalidArrayLength(String),
+    #[error("{0} needs a constant length, only fields can be sized by other fields")]
+    UnsizedArray(String),
+    #[error("{0}: computed fields and padding cannot be placed with @")]
+    InvalidLocation(String),
+    #[error("bytes[{0}] has a negative length")]
+    InvalidBytesLength(String),
+    #[error("bytes[{0}] needs a constant length, only fields can be sized by other fields")]
+    UnsizedBytes(String),
+    #[error("{0}: union members cannot be conditional, computed, padding or placed with @")]
+    InvalidUnionMember(String),
+    #[error("{0}: union members must have a fixed size")]
+    UnsizedUnionMember(String),
+    #[error("{0}: a union needs at least one member and cannot have asserts")]
+    InvalidUnion(String),
+    #[error("{field}: {ty} is only allowed in #[bits] structs, other structs take whole bytes")]
+    BitsOutsideBitLayout { field: String, ty: String },
+    #[error("{field}: {ty} cannot be packed in a #[bits] struct")]
+    NotBitField { field: String, ty: String },
+    #[error("{0}: checksums, lengths and @ are not supported in #[bits] structs")]
+    InvalidBitField(String),
+    #[error("{file}: {error}")]
+    InFile {
+        file: String,
+        error: Box<ParseError>,
+    },
 }
 
-impl Expr {
-    pub fn parse(input: &str) -> Result<Self, ParseError> {
-        let mut layout_ids = HashMap::new();
-        let mut layouts = HashMap::new();
-        let parsed_structs = struct_parser::structs(input)?;
-        for (id_counter, (name, _)) in parsed_structs.iter().enumerate() {
-            let id = LayoutId::new(id_counter as u32);
-            layout_ids.insert(name.clone(), id);
-        }
-        for (name, fields) in parsed_structs {
-            let id = *layout_ids.get(&name).unwrap();
-            let mut parsed_fields = Vec::new();
-            for (field_name, field_type) in fields {
-                let field_type = match field_type.as_str() {
-                    "i8" => Type::I8,
-                    "i16" => Type::I16,
-                    "i32" => Type::I32,
-                    "i64" => Type::I64,
-                    "f32" => Type::F32,
-                    "f64" => Type::F64,
-                    other => {
-                        if let Some(layout_id) = layout_ids.get(other) {
-                            Type::Struct(*layout_id)
-                        } else {
-                            return Err(ParseError::UnknownType(other.to_string()));
-                        }
-                    }
+impl ParseError {
+    fn in_file(self, file: &str) -> Self {
+        ParseError::InFile {
+            file: file.to_string(),
+            error: Box::new(self),
+        }
+    }
+}
+
+/// The structs declared directly in one namespace of a parsed file.
+struct Module {
+    file: String,
+    /// Namespace the file was imported into, names never resolve outside of it.
+    root: String,
+    prefix: String,
+    structs: Vec<StructDef>,
+    enums: Vec<EnumDef>,
+    aliases: Vec<(String, TypeRef)>,
+}
+
+fn qualify(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}::{name}")
+    }
+}
+
+/// A name declared somewhere in the loaded files.
+#[derive(Clone)]
+enum Decl {
+    Struct {
+        module: usize,
+        index: usize,
+    },
+    Enum {
+        module: usize,
+        index: usize,
+    },
+    /// A type alias, along with the module its target is resolved in.
+    Alias {
+        module: usize,
+        target: TypeRef,
+    },
+}
+
+/// A resolved generic argument.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Arg {
+    Type(Type),
+    Const(u64),
+}
+
+/// Generic arguments by parameter name.
+type Env = HashMap<String, Arg>;
+
+/// Turns the loaded modules into an [`Expr`], resolving type names and
+/// instantiating generic structs.
+struct Lowering {
+    modules: Vec<Module>,
+    links: Vec<Link>,
+    decls: HashMap<String, Decl>,
+    /// Aliases currently being resolved, used to detect cycles.
+    resolving: Vec<String>,
+    /// Layouts by qualified struct name and generic arguments.
+    instances: HashMap<(String, Vec<Arg>), LayoutId>,
+    /// Structs currently being lowered, innermost last, used to detect
+    /// cycles. `None` marks a pointer, what it points to may be one of them.
+    instantiating: Vec<Option<(String, Vec<Arg>)>>,
+    id_counter: u32,
+    expr: Expr,
+}
+
+impl Lowering {
+    fn new(modules: Vec<Module>, links: Vec<Link>) -> Result<Self, ParseError> {
+        let mut decls = HashMap::new();
+        for (index, module) in modules.iter().enumerate() {
+            let structs = module.structs.iter().enumerate().map(|(i, def)| {
+                let decl = Decl::Struct {
+                    module: index,
+                    index: i,
+                };
+                (&def.name, decl)
+            });
+            let enums = module.enums.iter().enumerate().map(|(i, def)| {
+                let decl = Decl::Enum {
+                    module: index,
+                    index: i,
                 };
-                parsed_fields.push((field_name, field_type));
+                (&def.name, decl)
+            });
+            let aliases = module.aliases.iter().map(|(name, target)| {
+                let decl = Decl::Alias {
+                    module: index,
+                    target: target.clone(),
+                };
+                (name, decl)
+            });
+            for (name, decl) in structs.chain(enums).chain(aliases) {
+                let name = qualify(&module.prefix, name);
+                if decls.insert(name.clone(), decl).is_some() {
+                    return Err(ParseError::DuplicateName(name).in_file(&module.file));
+                }
+            }
+        }
+        // Files imported again declare their names under each alias.
+        for link in &links {
+            for (name, decl) in link.names(&decls) {
+                if decls.insert(name.clone(), decl).is_some() {
+                    return Err(ParseError::DuplicateName(name).in_file(&link.file));
+                }
             }
-            layouts.insert(
-                id,
-                Struct {
-                    fields: parsed_fields,
-                },
-            );
         }
         Ok(Self {
-            layouts,
-            layout_ids,
+            modules,
+            links,
+            decls,
+            resolving: Vec::new(),
+            instances: HashMap::new(),
+            instantiating: Vec::new(),
+            id_counter: 0,
+            expr: Expr {
+                layouts: HashMap::new(),
+                layout_ids: HashMap::new(),
+                aliases: HashMap::new(),
+                enums: HashMap::new(),
+                enum_ids: HashMap::new(),
+            },
         })
     }
+
+    fn error(&self, module: usize, error: ParseError) -> ParseError {
+        error.in_file(&self.modules[module].file)
+    }
+
+    /// Resolves a type written in `module`, returning the type along with the
+    /// qualified alias it was written through, if any. `env` holds the
+    /// arguments of the generic struct the type appears in.
+    fn resolve_type(
+        &mut self,
+        module: usize,
+        ty: &TypeRef,
+        env: &Env,
+    ) -> Result<(Type, Option<String>), ParseError> {
+        let (name, args) = match ty {
+            TypeRef::Named { name, args } => (name, args),
+            TypeRef::Array { element, len } => {
+                let (element, _) = self.resolve_type(module, element, env)?;
+                let Some(len) = self.const_len(len, env) else {
+                    return Err(self.error(module, ParseError::UnsizedArray(ty.to_string())));
+                };
+                let len = usize::try_from(len).map_err(|_| {
+                    self.error(module, ParseError::InvalidArrayLength(ty.to_string()))
+                })?;
+                return Ok((Type::Array(Box::new(element), Some(len)), None));
+            }
+            TypeRef::Pad(len) => {
+                let len = self.resolve_const(module, len, env)?;
+                return Ok((Type::Array(Box::new(Type::U8), Some(len as usize)), None));
+            }
+            TypeRef::Const(_) => {
+                return Err(self.error(module, ParseError::ExpectedType(ty.to_string())));
+            }
+            TypeRef::Bytes(len) => {
+                let Some(len) = self.const_len(len, env) else {
+                    return Err(self.error(module, ParseError::UnsizedBytes(len.to_string())));
+                };
+                let len = usize::try_from(len).map_err(|_| {
+                    self.error(module, ParseError::InvalidBytesLength(len.to_string()))
+                })?;
+                return Ok((Type::Bytes(Some(len)), None));
+            }
+            TypeRef::Struct(def) => {
+                // Named after how it is written, it is not a layout of its own.
+                let mut def = (**def).clone();
+                def.name = ty.to_string();
+                let layout = self.lower_struct(module, def, env)?;
+                let id = LayoutId::new(self.id_counter);
+                self.id_counter += 1;
+                let fields = layout
+                    .fields
+                    .iter()
+                    .map(|field| format!("{}: {}", field.name, field.ty.name(&self.expr)));
+                let name = inline_name(layout.union, fields);
+                self.expr.layouts.insert(id, Struct { name, ..layout });
+                return Ok((Type::Struct(id), None));
+            }
+        };
+        if let ("bool", [storage]) = (name.as_str(), args.as_slice()) {
+            let (storage, _) = self.resolve_type(module, storage, env)?;
+            if !storage.is_integer() {
+                let error = ParseError::ExpectedInteger(storage.name(&self.expr).into_owned());
+                return Err(self.error(module, error));
+            }
+            return Ok((Type::Bool(Box::new(storage)), None));
+        }
+        if let ("ptr" | "rel_ptr", [target] | [target, _]) = (name.as_str(), args.as_slice()) {
+            self.instantiating.push(None);
+            let target = self.resolve_type(module, target, env);
+            self.instantiating.pop();
+            let (target, _) = target?;
+            let storage = match args.get(1) {
+                Some(storage) => self.resolve_type(module, storage, env)?.0,
+                None => Type::U32,
+            };
+            if !storage.is_integer() {
+                let error = ParseError::ExpectedInteger(storage.name(&self.expr).into_owned());
+                return Err(self.error(module, error));
+            }
+            let base = match name.as_str() {
+                "ptr" => Base::Buffer,
+                _ => Base::Struct,
+            };
+            let pointer = Pointer {
+                target,
+                storage,
+                base,
+            };
+            return Ok((Type::Pointer(Box::new(pointer)), None));
+        }
+        if let ("timestamp", [storage, epoch, unit]) = (name.as_str(), args.as_slice()) {
+            let (storage, _) = self.resolve_type(module, storage, env)?;
+            if !storage.is_integer() {
+                let error = ParseError::ExpectedInteger(storage.name(&self.expr).into_owned());
+                return Err(self.error(module, error));
+            }
+            let epoch = match epoch {
+                TypeRef::Named { name, args } if args.is_empty() => Epoch::from_name(name),
+                _ => None,
+            };
+            let Some(epoch) = epoch else {
+                return Err(self.error(module, ParseError::UnknownEpoch(args[1].to_string())));
+            };
+            let unit = match unit {
+                TypeRef::Named { name, args } if args.is_empty() => Unit::from_name(name),
+                _ => None,
+            };
+            let Some(unit) = unit else {
+                return Err(self.error(module, ParseError::UnknownTimeUnit(args[2].to_string())));
+            };
+            let timestamp = Timestamp {
+                storage,
+                epoch,
+                unit,
+            };
+            return Ok((Type::Timestamp(Box::new(timestamp)), None));
+        }
+        if args.is_empty() {
+            match env.get(name) {
+                Some(Arg::Type(ty)) => return Ok((ty.clone(), None)),
+                Some(Arg::Const(_)) => {
+                    return Err(self.error(module, ParseError::ExpectedType(name.clone())));
+                }
+                None => {}
+            }
+            if let Some(ty) = Type::primitive(name) {
+                return Ok((ty, None));
+            }
+        }
+        let Module { root, prefix, .. } = &self.modules[module];
+        let Some((qualified, decl)) = lookup_scoped(&self.decls, root, prefix, name) else {
+            return Err(self.error(module, ParseError::UnknownType(name.clone())));
+        };
+        match decl {
+            Decl::Struct {
+                module: def_module,
+                index,
+            } => {
+                let (def_module, index) = (*def_module, *index);
+                let args = args
+                    .iter()
+                    .map(|arg| self.resolve_arg(module, arg, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let id = self.instantiate(module, def_module, index, args)?;
+                Ok((Type::Struct(id), None))
+            }
+            Decl::Enum { .. } | Decl::Alias { .. } if !args.is_empty() => {
+                let error = ParseError::GenericArity {
+                    name: qualified,
+                    expected: 0,
+                    found: args.len(),
+                };
+                Err(self.error(module, error))
+            }
+            Decl::Enum {
+                module: def_module,
+                index,
+            } => {
+                let (def_module, index) = (*def_module, *index);
+                Ok((Type::Enum(self.lower_enum(def_module, index)?), None))
+            }
+            Decl::Alias {
+                module: alias_module,
+                target,
+            } => {
+                if let Some(ty) = self.expr.aliases.get(&qualified) {
+                    return Ok((ty.clone(), Some(qualified)));
+                }
+                if self.resolving.contains(&qualified) {
+                    return Err(self.error(module, ParseError::AliasCycle(qualified)));
+                }
+                let (alias_module, target) = (*alias_module, target.clone());
+                self.resolving.push(qualified.clone());
+                let (ty, _) = self.resolve_type(alias_module, &target, &Env::new())?;
+                self.resolving.pop();
+                self.expr.aliases.insert(qualified.clone(), ty.clone());
+                Ok((ty, Some(qualified)))
+            }
+        }
+    }
+
+    /// The length of `bytes[len]` or `[T; len]` if it is constant, made of
+    /// literals and `const` generic parameters. `None` if it refers to fields.
+    fn const_len(&self, len: &Expression, env: &Env) -> Option<i128> {
+        let consts = env
+            .iter()
+            .filter_map(|(name, arg)| match arg {
+                Arg::Const(value) => Some((name.clone(), Value::U64(*value))),
+                Arg::Type(_) => None,
+            })
+            .collect::<Vec<_>>();
+        match len.eval_fields(&consts) {
+            Ok(Scalar::Int(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn resolve_const(&mut self, module: usize, ty: &TypeRef, env: &Env) -> Result<u64, ParseError> {
+        match self.resolve_arg(module, ty, env)? {
+            Arg::Const(value) => Ok(value),
+            Arg::Type(_) => Err(self.error(module, ParseError::ExpectedConst(ty.to_string()))),
+        }
+    }
+
+    fn resolve_arg(&mut self, module: usize, ty: &TypeRef, env: &Env) -> Result<Arg, ParseError> {
+        match ty {
+            TypeRef::Const(value) => Ok(Arg::Const(*value)),
+            TypeRef::Named { name, args } if args.is_empty() && env.contains_key(name) => {
+                Ok(env[name].clone())
+            }
+            _ => Ok(Arg::Type(self.resolve_type(module, ty, env)?.0)),
+        }
+    }
+
+    /// Returns the layout of the struct `index` of `module` for the given
+    /// generic arguments, lowering it the first time it is used. Errors about
+    /// the arguments are reported against `use_module`.
+    fn instantiate(
+        &mut self,
+        use_module: usize,
+        module: usize,
+        index: usize,
+        args: Vec<Arg>,
+    ) -> Result<LayoutId, ParseError> {
+        let def = self.modules[module].structs[index].clone();
+        let qualified = qualify(&self.modules[module].prefix, &def.name);
+        let key = (qualified.clone(), args);
+        if let Some(id) = self.instances.get(&key) {
+            // A struct can only contain itself through a pointer.
+            let contained = self
+                .instantiating
+                .iter()
+                .rev()
+                .map_while(Option::as_ref)
+                .any(|outer| *outer == key);
+            if contained {
+                return Err(self.error(use_module, ParseError::InstantiationCycle(qualified)));
+            }
+            return Ok(*id);
+        }
+        let (_, args) = key;
+        if def.params.len() != args.len() {
+            let error = ParseError::GenericArity {
+                name: qualified,
+                expected: def.params.len(),
+                found: args.len(),
+            };
+            return Err(self.error(use_module, error));
+        }
+        let mut env = Env::new();
+        for (param, arg) in def.params.iter().zip(&args) {
+            match (param, arg) {
+                (Param::Type(_), Arg::Const(value)) => {
+                    let error = ParseError::ExpectedType(value.to_string());
+                    return Err(self.error(use_module, error));
+                }
+                (Param::Const(_), Arg::Type(ty)) => {
+                    let error = ParseError::ExpectedConst(ty.name(&self.expr).into_owned());
+                    return Err(self.error(use_module, error));
+                }
+                _ => {}
+            }
+            env.insert(param.name().to_string(), arg.clone());
+        }
+        // Other arguments to a struct being instantiated would go on forever.
+        let generic = !def.params.is_empty();
+        let expanding = self
+            .instantiating
+            .iter()
+            .flatten()
+            .any(|(name, _)| *name == qualified);
+        if generic && expanding {
+            return Err(self.error(use_module, ParseError::InstantiationCycle(qualified)));
+        }
+
+        let id = LayoutId::new(self.id_counter);
+        self.id_counter += 1;
+        let name = if generic {
+            let args = args
+                .iter()
+                .map(|arg| match arg {
+                    Arg::Type(ty) => ty.name(&self.expr).into_owned(),
+                    Arg::Const(value) => value.to_string(),
+                })
+                .collect::<Vec<_>>();
+            format!("{qualified}<{}>", args.join(", "))
+        } else {
+            qualified.clone()
+        };
+        self.expr.layout_ids.insert(name.clone(), id);
+        self.instances.insert((qualified.clone(), args.clone()), id);
+        // Pointers back to it can name it before it is lowered.
+        let placeholder = Struct {
+            name: name.clone(),
+            fields: Vec::new(),
+            asserts: Vec::new(),
+            union: def.union,
+            bits: None,
+        };
+        self.expr.layouts.insert(id, placeholder);
+
+        self.instantiating.push(Some((qualified, args)));
+        let def = StructDef { name, ..def };
+        let layout = self.lower_struct(module, def, &env)?;
+        self.instantiating.pop();
+        self.expr.layouts.insert(id, layout);
+        Ok(id)
+    }
+
+    /// Lowers the fields of `def`, declared in `module`, with the generic
+    /// arguments in `env`.
+    fn lower_struct(
+        &mut self,
+        module: usize,
+        def: StructDef,
+        env: &Env,
+    ) -> Result<Struct, ParseError> {
+        let bits = self.bit_order(module, &def)?;
+        let mut fields = Vec::new();
+        for field in &def.fields {
+            let declared = match bits {
+                Some(order) => packed(&field.ty, order),
+                None => field.ty.clone(),
+            };
+            // `bytes` and arrays whose length refers to the fields before it.
+            let size = match &declared {
+                TypeRef::Bytes(len) | TypeRef::Array { len, .. }
+                    if self.const_len(len, env).is_none() =>
+                {
+                    Some((**len).clone())
+                }
+                _ => None,
+            };
+            let (ty, alias) = match (&size, &declared) {
+                (Some(size), TypeRef::Array { element, .. }) => {
+                    self.check_integer(module, &fields, size, "a size")?;
+                    let (element, _) = self.resolve_type(module, element, env)?;
+                    (Type::Array(Box::new(element), None), None)
+                }
+                (Some(size), _) => {
+                    self.check_integer(module, &fields, size, "a size")?;
+                    (Type::Bytes(None), None)
+                }
+                (None, _) => self.resolve_type(module, &declared, env)?,
+            };
+            self.check_packing(module, field, &ty, bits.is_some())?;
+            let (scaling, unit) = self.presentation(module, field, &ty)?;
+            // Literals are raw values, shown in engineering units like
+            // decoded ones.
+            let scale = |value: Value| match &scaling {
+                Some(scaling) => scaling.apply(value),
+                None => value,
+            };
+            let constant = match field.constant {
+                Some(literal) => Some(scale(self.literal_value(
+                    module,
+                    &field.name,
+                    &ty,
+                    literal,
+                )?)),
+                None => None,
+            };
+            let padding = field.is_padding();
+            let default = match field.default {
+                Some(literal) => Some(scale(self.literal_value(
+                    module,
+                    &field.name,
+                    &ty,
+                    literal,
+                )?)),
+                // Padding is zeroed unless it declares a fill.
+                None if padding && field.constant.is_none() => self.expr.default_for_type(&ty),
+                None => None,
+            };
+            let constraints = self.constraints(module, field)?;
+            let view = self.view(module, field, &ty)?;
+            // Computed from the fields before it, like conditions.
+            if let Some(derived) = &field.derived {
+                self.check_derived(module, &fields, field, &ty, scaling.is_some(), derived)?;
+            }
+            let computed = field.is_computed();
+            if def.union {
+                if field.condition.is_some() || computed || padding || field.location.is_some() {
+                    let error = ParseError::InvalidUnionMember(field.name.clone());
+                    return Err(self.error(module, error));
+                }
+                if self.expr.size_of_type(&ty).and_then(Size::fixed).is_none() {
+                    let error = ParseError::UnsizedUnionMember(field.name.clone());
+                    return Err(self.error(module, error));
+                }
+            }
+            // Conditions can only refer to the fields before them.
+            if let Some(condition) = &field.condition {
+                self.check_condition(module, &fields, condition)?;
+            }
+            if let Some(location) = &field.location {
+                if padding || computed {
+                    let error = ParseError::InvalidLocation(field.name.clone());
+                    return Err(self.error(module, error));
+                }
+                self.check_integer(module, &fields, &location.offset, "an offset")?;
+            }
+            fields.push(Field {
+                name: field.name.clone(),
+                ty,
+                alias,
+                constant,
+                default,
+                constraints,
+                scaling,
+                unit,
+                condition: field.condition.clone(),
+                checksum: None,
+                length: None,
+                derived: field.derived.clone(),
+                location: field.location.clone(),
+                padding,
+                view,
+                size,
+            });
+        }
+        for (index, field) in def.fields.iter().enumerate() {
+            if let Some(checksum) = &field.checksum {
+                let checksum = self.checksum(module, &fields, index, checksum)?;
+                fields[index].checksum = Some(checksum);
+            }
+            if let Some(length) = &field.length {
+                let length = self.length(module, &fields, index, length)?;
+                fields[index].length = Some(length);
+            }
+        }
+        self.check_checksum_cycles(module, &fields)?;
+        self.check_checksum_references(module, &fields)?;
+        self.check_condition_references(module, &fields)?;
+        if def.union && (fields.is_empty() || !def.asserts.is_empty()) {
+            let error = ParseError::InvalidUnion(def.name.clone());
+            return Err(self.error(module, error));
+        }
+        for assertion in &def.asserts {
+            self.check_condition(module, &fields, assertion)?;
+        }
+        Ok(Struct {
+            name: def.name,
+            fields,
+            asserts: def.asserts,
+            union: def.union,
+            bits,
+        })
+    }
+
+    /// Returns the id of the enum `index` of `module`, lowering it the first
+    /// time it is used.
+    fn lower_enum(&mut self, module: usize, index: usize) -> Result<EnumId, ParseError> {
+        let def = self.modules[module].enums[index].clone();
+        let qualified = qualify(&self.modules[module].prefix, &def.name);
+        if let Some(id) = self.expr.enum_ids.get(&qualified) {
+            return Ok(*id);
+        }
+        let (storage, _) = self.resolve_type(module, &def.storage, &Env::new())?;
+        if !storage.is_integer() {
+            let error = ParseError::ExpectedInteger(storage.name(&self.expr).into_owned());
+            return Err(self.error(module, error));
+        }
+        let mut values: Vec<(String, i128)> = Vec::new();
+        for (name, value) in def.variants {
+            if values.iter().any(|(other, _)| *other == name) {
+                let error = ParseError::DuplicateName(qualify(&qualified, &name));
+                return Err(self.error(module, error));
+            }
+            let value = value.unwrap_or_else(|| values.last().map_or(0, |(_, last)| last + 1));
+            let literal = Literal::Int(value);
+            if literal.to_value(&self.expr, &storage).is_none() {
+                let error = ParseError::InvalidLiteral {
+                    field: qualify(&qualified, &name),
+                    literal,
+                    ty: storage.name(&self.expr).into_owned(),
+                };
+                return Err(self.error(module, error));
+            }
+            values.push((name, value));
+        }
+
+        let id = EnumId::new(self.expr.enums.len() as u32);
+        self.expr.enum_ids.insert(qualified, id);
+        self.expr.enums.insert(id, Enumeration { storage, values });
+        Ok(id)
+    }
+
+    fn literal_value(
+        &self,
+        module: usize,
+        field: &str,
+        ty: &Type,
+        literal: Literal,
+    ) -> Result<Value, ParseError> {
+        // Stored as the type stores it, so that it matches what is read.
+        let value = literal
+            .to_value(&self.expr, ty)
+            .map(|value| value.rounded());
+        value.ok_or_else(|| {
+            let error = ParseError::InvalidLiteral {
+                field: field.to_string(),
+                literal,
+                ty: ty.name(&self.expr).into_owned(),
+            };
+            self.error(module, error)
+        })
+    }
+
+    fn constraints(&self, module: usize, field: &FieldDef) -> Result<Vec<Constraint>, ParseError> {
+        let mut constraints = Vec::new();
+        for attribute in &field.attributes {
+            // Read by `presentation` and `view`.
+            if matches!(
+                attribute.name.as_str(),
+                "scale" | "offset" | "round" | "unit" | "view"
+            ) {
+                continue;
+            }
+            let invalid = || {
+                let error = ParseError::InvalidAttribute {
+                    field: field.name.clone(),
+                    name: attribute.name.clone(),
+                };
+                self.error(module, error)
+            };
+            let numbers = attribute
+                .args
+                .iter()
+                .map(|arg| match arg {
+                    AttributeArg::Literal(literal) => Ok(Scalar::from(*literal)),
+                    AttributeArg::String(_) => Err(invalid()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let constraint = match (attribute.name.as_str(), numbers.as_slice()) {
+                ("range", [min, max]) => Constraint::Range(*min, *max),
+                ("one_of", [_, ..]) => Constraint::OneOf(numbers),
+                // Read by `is_padding`.
+                ("reserved", []) => continue,
+                ("range" | "one_of" | "reserved", _) => return Err(invalid()),
+                (name, _) => {
+                    let error = ParseError::UnknownAttribute {
+                        field: field.name.clone(),
+                        name: name.to_string(),
+                    };
+                    return Err(self.error(module, error));
+                }
+            };
+            constraints.push(constraint);
+        }
+        Ok(constraints)
+    }
+
+    /// Reads the `#[scale]`, `#[offset]`, `#[round]` and `#[unit]` attributes
+    /// of a field of type `ty`.
+    fn presentation(
+        &self,
+        module: usize,
+        field: &FieldDef,
+        ty: &Type,
+    ) -> Result<(Option<Scaling>, Option<String>), ParseError> {
+        let mut scaling = Scaling {
+            scale: 1.0,
+            offset: 0.0,
+            rounding: Rounding::default(),
+            unit: None,
+        };
+        let mut scaled = false;
+        let mut unit = None;
+        for attribute in &field.attributes {
+            let invalid = || {
+                let error = ParseError::InvalidAttribute {
+                    field: field.name.clone(),
+                    name: attribute.name.clone(),
+                };
+                self.error(module, error)
+            };
+            let number = match attribute.args.as_slice() {
+                [AttributeArg::Literal(Literal::Int(v))] => Some(*v as f64),
+                [AttributeArg::Literal(Literal::Float(v))] => Some(*v),
+                _ => None,
+            };
+            match (attribute.name.as_str(), number, attribute.args.as_slice()) {
+                ("scale", Some(scale), _) if scale != 0.0 => scaling.scale = scale,
+                ("offset", Some(offset), _) => scaling.offset = offset,
+                ("round", _, [AttributeArg::String(name)]) => {
+                    scaling.rounding = Rounding::from_name(name).ok_or_else(invalid)?;
+                }
+                ("unit", _, [AttributeArg::String(name)]) => {
+                    unit = Some(name.clone());
+                    continue;
+                }
+                ("scale" | "offset" | "round" | "unit", _, _) => return Err(invalid()),
+                _ => continue,
+            }
+            scaled = true;
+        }
+        let mut element = ty;
+        while let Type::Array(inner, _) = element {
+            element = inner;
+        }
+        if scaled && !(element.is_integer() || element.is_float()) {
+            let error = ParseError::InvalidAttribute {
+                field: field.name.clone(),
+                name: "scale".to_string(),
+            };
+            return Err(self.error(module, error));
+        }
+        scaling.unit = unit.clone();
+        Ok((scaled.then_some(scaling), unit))
+    }
+
+    /// Reads the `#[bits]` attribute of a struct, `#[bits("lsb")]` for least
+    /// significant bit first.
+    fn bit_order(&self, module: usize, def: &StructDef) -> Result<Option<BitOrder>, ParseError> {
+        let mut order = None;
+        for attribute in &def.attributes {
+            let invalid = || {
+                let error = ParseError::InvalidAttribute {
+                    field: def.name.clone(),
+                    name: attribute.name.clone(),
+                };
+                self.error(module, error)
+            };
+            order = match (attribute.name.as_str(), attribute.args.as_slice()) {
+                ("bits", _) if def.union => return Err(invalid()),
+                ("bits", []) => Some(BitOrder::Msb),
+                ("bits", [AttributeArg::String(name)]) => {
+                    Some(BitOrder::from_name(name).ok_or_else(invalid)?)
+                }
+                ("bits", _) => return Err(invalid()),
+                (name, _) => {
+                    let error = ParseError::UnknownAttribute {
+                        field: def.name.clone(),
+                        name: name.to_string(),
+                    };
+                    return Err(self.error(module, error));
+                }
+            };
+        }
+        Ok(order)
+    }
+
+    /// Checks that a field of type `ty` can be laid out like the rest of its
+    /// struct, packed bit by bit if it is a `#[bits]` struct.
+    fn check_packing(
+        &self,
+        module: usize,
+        field: &FieldDef,
+        ty: &Type,
+        bits: bool,
+    ) -> Result<(), ParseError> {
+        let error = if !bits {
+            if !self.needs_bits(ty) {
+                return Ok(());
+            }
+            ParseError::BitsOutsideBitLayout {
+                field: field.name.clone(),
+                ty: ty.name(&self.expr).into_owned(),
+            }
+        } else if field.checksum.is_some() || field.length.is_some() || field.location.is_some() {
+            ParseError::InvalidBitField(field.name.clone())
+        } else if !self.packs_into_bits(ty) {
+            ParseError::NotBitField {
+                field: field.name.clone(),
+                ty: ty.name(&self.expr).into_owned(),
+            }
+        } else {
+            return Ok(());
+        };
+        Err(self.error(module, error))
+    }
+
+    /// Whether `ty` is made of integers that are not whole bytes, like `u5`
+    /// or an enum stored in one.
+    fn needs_bits(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Bits { width, .. } => width % 8 != 0,
+            Type::Bool(storage) => self.needs_bits(storage),
+            Type::Enum(id) => self
+                .expr
+                .enums
+                .get(id)
+                .is_some_and(|enumeration| self.needs_bits(&enumeration.storage)),
+            Type::Array(element, _) => self.needs_bits(element),
+            Type::Pointer(pointer) => {
+                self.needs_bits(&pointer.target) || self.needs_bits(&pointer.storage)
+            }
+            Type::Timestamp(timestamp) => self.needs_bits(&timestamp.storage),
+            _ => false,
+        }
+    }
+
+    /// Whether `ty` can be packed bit by bit, which takes a fixed width.
+    fn packs_into_bits(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Bool(storage) => self.packs_into_bits(storage),
+            Type::Enum(id) => self
+                .expr
+                .enums
+                .get(id)
+                .is_some_and(|enumeration| self.packs_into_bits(&enumeration.storage)),
+            Type::Array(element, _) => self.packs_into_bits(element),
+            Type::Timestamp(timestamp) => self.packs_into_bits(&timestamp.storage),
+            Type::Struct(id) => self
+                .expr
+                .layouts
+                .get(id)
+                .is_some_and(|layout| layout.bits.is_some()),
+            Type::VarInt | Type::UVarInt | Type::ZigZag | Type::Pointer(_) => false,
+            Type::Ipv4 | Type::Ipv6 | Type::Mac | Type::Uuid | Type::Bytes(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Reads the `#[view("member")]` attribute of a field of type `ty`, which
+    /// has to be a union with that member.
+    fn view(
+        &self,
+        module: usize,
+        field: &FieldDef,
+        ty: &Type,
+    ) -> Result<Option<String>, ParseError> {
+        let Some(attribute) = field.attributes.iter().find(|a| a.name == "view") else {
+            return Ok(None);
+        };
+        let members = match ty {
+            Type::Struct(id) => self.expr.layouts.get(id).filter(|layout| layout.union),
+            _ => None,
+        };
+        match (members, attribute.args.as_slice()) {
+            (Some(layout), [AttributeArg::String(name)])
+                if layout.fields.iter().any(|member| member.name == *name) =>
+            {
+                Ok(Some(name.clone()))
+            }
+            _ => {
+                let error = ParseError::InvalidAttribute {
+                    field: field.name.clone(),
+                    name: attribute.name.clone(),
+                };
+                Err(self.error(module, error))
+            }
+        }
+    }
+
+    /// Resolves the checksum declared on the field `index` of `fields`.
+    fn checksum(
+        &self,
+        module: usize,
+        fields: &[Field],
+        index: usize,
+        def: &ChecksumDef,
+    ) -> Result<Checksum, ParseError> {
+        let field = &fields[index];
+        let error = |error| {
+            let error = ParseError::Checksum {
+                field: field.name.clone(),
+                error,
+            };
+            self.error(module, error)
+        };
+        let width = match (&field.ty, &field.scaling) {
+            (Type::U8, None) => 8,
+            (Type::U16, None) => 16,
+            (Type::U32, None) => 32,
+            (Type::U64, None) => 64,
+            _ => return Err(error(ChecksumError::InvalidType)),
+        };
+        let algorithm = Algorithm::new(&def.algorithm, width, &def.params).map_err(error)?;
+        let range = self.field_range(module, fields, index, &def.range)?;
+        if range.contains(&index) {
+            return Err(error(ChecksumError::CoversItself));
+        }
+        Ok(Checksum {
+            algorithm,
+            width,
+            fields: range,
+        })
+    }
+
+    /// Resolves the length declared on the field `index` of `fields`.
+    fn length(
+        &self,
+        module: usize,
+        fields: &[Field],
+        index: usize,
+        def: &LengthDef,
+    ) -> Result<Length, ParseError> {
+        let field = &fields[index];
+        let fixed_size = matches!(
+            field.ty,
+            Type::I8
+                | Type::I16
+                | Type::I32
+                | Type::I64
+                | Type::U8
+                | Type::U16
+                | Type::U32
+                | Type::U64
+        );
+        if !fixed_size || field.scaling.is_some() {
+            let error = ParseError::InvalidLengthType(field.name.clone());
+            return Err(self.error(module, error));
+        }
+        let range = self.field_range(module, fields, index, &def.range)?;
+        if def.unit == LengthUnit::Elements
+            && (range.len() != 1 || !matches!(fields[range.start].ty, Type::Array(..)))
+        {
+            let error = ParseError::InvalidCount {
+                field: field.name.clone(),
+                range: def.range.to_string(),
+            };
+            return Err(self.error(module, error));
+        }
+        Ok(Length {
+            unit: def.unit,
+            fields: range,
+            adjust: def.adjust,
+        })
+    }
+
+    /// Resolves a range of `fields` written on the field `index`.
+    fn field_range(
+        &self,
+        module: usize,
+        fields: &[Field],
+        index: usize,
+        range: &FieldRange,
+    ) -> Result<Range<usize>, ParseError> {
+        let position = |name: &String| {
+            fields
+                .iter()
+                .position(|field| field.name == *name)
+                .ok_or_else(|| self.error(module, ParseError::UnknownField(name.clone())))
+        };
+        let start = match &range.start {
+            Some(name) => position(name)?,
+            None => 0,
+        };
+        let end = match &range.end {
+            Some(name) => position(name)? + range.inclusive as usize,
+            None => fields.len(),
+        };
+        if start >= end {
+            let error = ParseError::EmptyRange {
+                field: fields[index].name.clone(),
+                range: range.to_string(),
+            };
+            return Err(self.error(module, error));
+        }
+        Ok(start..end)
+    }
+
+    /// Checks that the checksums of a struct can be computed one after
+    /// another, i.e. that no checksum ends up covering itself through others.
+    fn check_checksum_cycles(&self, module: usize, fields: &[Field]) -> Result<(), ParseError> {
+        let mut pending = (0..fields.len())
+            .filter(|index| fields[*index].checksum.is_some())
+            .collect::<Vec<_>>();
+        let covers = |index: usize, other: usize| {
+            fields[index]
+                .checksum
+                .as_ref()
+                .is_some_and(|checksum| checksum.fields.contains(&other))
+        };
+        while !pending.is_empty() {
+            let ready = pending
+                .iter()
+                .position(|index| !pending.iter().any(|other| covers(*index, *other)));
+            let Some(ready) = ready else {
+                let index = pending[0];
+                let covered = pending.iter().find(|other| covers(index, **other)).unwrap();
+                let error = ParseError::Checksum {
+                    field: fields[index].name.clone(),
+                    error: ChecksumError::Cycle(fields[*covered].name.clone()),
+                };
+                return Err(self.error(module, error));
+            };
+            pending.remove(ready);
+        }
+        Ok(())
+    }
+
+    /// Checks that no field is conditioned, sized, placed or computed by a
+    /// checksum, which is not known until the whole struct is written.
+    fn check_checksum_references(&self, module: usize, fields: &[Field]) -> Result<(), ParseError> {
+        for field in fields {
+            let expressions = [
+                field.condition.as_ref(),
+                field.size.as_ref(),
+                field.location.as_ref().map(|location| &location.offset),
+                field.derived.as_ref(),
+            ];
+            let checksum = fields.iter().find(|checksum| {
+                checksum.checksum.is_some()
+                    && expressions
+                        .iter()
+                        .flatten()
+                        .any(|e| e.refers_to(&checksum.name))
+            });
+            if let Some(checksum) = checksum {
+                let error = ParseError::Checksum {
+                    field: checksum.name.clone(),
+                    error: ChecksumError::Referenced(field.name.clone()),
+                };
+                return Err(self.error(module, error));
+            }
+        }
+        Ok(())
+    }
+
+    /// Conditions are checked while a value is put together, before the
+    /// fields computed when encoding it are known, so they can only refer to
+    /// fields that are entered. Sizes and offsets are only needed once they
+    /// are.
+    fn check_condition_references(
+        &self,
+        module: usize,
+        fields: &[Field],
+    ) -> Result<(), ParseError> {
+        for (index, field) in fields.iter().enumerate() {
+            let Some(condition) = &field.condition else {
+                continue;
+            };
+            let mut computed = None;
+            condition.visit_fields(&mut |path| {
+                let found = self.find_field(&fields[..index], path);
+                if found.is_some_and(|found| found.derived.is_some() || found.length.is_some()) {
+                    computed.get_or_insert_with(|| path.join("."));
+                }
+            });
+            if let Some(computed) = computed {
+                let error = ParseError::ComputedInCondition {
+                    field: field.name.clone(),
+                    computed,
+                };
+                return Err(self.error(module, error));
+            }
+        }
+        Ok(())
+    }
+
+    /// The field `path` refers to among `fields`, looking into the fields of
+    /// nested structs. Padding is left out of values, so it cannot be
+    /// referred to.
+    fn find_field<'f>(&'f self, fields: &'f [Field], path: &[String]) -> Option<&'f Field> {
+        let mut fields = fields;
+        let mut found = None;
+        for name in path {
+            let field = fields
+                .iter()
+                .find(|field| field.name == *name && !field.padding)?;
+            fields = match &field.ty {
+                Type::Struct(id) => self.expr.layouts.get(id)?.fields.as_slice(),
+                _ => &[],
+            };
+            found = Some(field);
+        }
+        found
+    }
+
+    /// Type checks `expression` against a struct with `fields`, returning the
+    /// type it evaluates to.
+    fn check_expression(
+        &self,
+        module: usize,
+        fields: &[Field],
+        expression: &Expression,
+    ) -> Result<ScalarType, ParseError> {
+        let field_type = |path: &[String]| {
+            let field = self
+                .find_field(fields, path)
+                .ok_or_else(|| EvalError::UnknownField(path.join(".")))?;
+            ScalarType::of(&field.ty, field.scaling.is_some()).ok_or_else(|| {
+                EvalError::TypeMismatch(format!("{} is not a number or bool", path.join(".")))
+            })
+        };
+        expression.type_of(&field_type).map_err(|error| {
+            let error = match error {
+                EvalError::UnknownField(path) => ParseError::UnknownField(path),
+                error => ParseError::InvalidExpression {
+                    expression: expression.to_string(),
+                    error,
+                },
+            };
+            self.error(module, error)
+        })
+    }
+
+    /// Type checks an assertion or field condition, which has to be a bool.
+    fn check_condition(
+        &self,
+        module: usize,
+        fields: &[Field],
+        condition: &Expression,
+    ) -> Result<(), ParseError> {
+        let ty = self.check_expression(module, fields, condition)?;
+        if ty != ScalarType::Bool {
+            let error = ParseError::InvalidExpression {
+                expression: condition.to_string(),
+                error: EvalError::TypeMismatch(format!("expected a condition, found {ty}")),
+            };
+            return Err(self.error(module, error));
+        }
+        Ok(())
+    }
+
+    /// Type checks the expression a field of type `ty` is computed from, which
+    /// has to evaluate to a value of that type.
+    fn check_derived(
+        &self,
+        module: usize,
+        fields: &[Field],
+        field: &FieldDef,
+        ty: &Type,
+        scaled: bool,
+        derived: &Expression,
+    ) -> Result<(), ParseError> {
+        // Computed values are raw ones.
+        if scaled {
+            let error = ParseError::InvalidAttribute {
+                field: field.name.clone(),
+                name: "scale".to_string(),
+            };
+            return Err(self.error(module, error));
+        }
+        let found = self.check_expression(module, fields, derived)?;
+        let expected = match ty {
+            Type::Pointer(_) => None,
+            ty => ScalarType::of(ty, false),
+        };
+        // Integers convert to floats, as in arithmetic.
+        match (expected, found) {
+            (Some(ScalarType::Float), ScalarType::Int) => Ok(()),
+            (Some(expected), found) if expected == found => Ok(()),
+            (expected, found) => {
+                let expected = match expected {
+                    Some(expected) => expected.to_string(),
+                    None => ty.name(&self.expr).into_owned(),
+                };
+                let error = ParseError::InvalidExpression {
+                    expression: derived.to_string(),
+                    error: EvalError::TypeMismatch(format!("expected {expected}, found {found}")),
+                };
+                Err(self.error(module, error))
+            }
+        }
+    }
+
+    /// Checks that an offset or size, named by `what`, is an integer.
+    fn check_integer(
+        &self,
+        module: usize,
+        fields: &[Field],
+        expression: &Expression,
+        what: &str,
+    ) -> Result<(), ParseError> {
+        let ty = self.check_expression(module, fields, expression)?;
+        if ty != ScalarType::Int {
+            let error = ParseError::InvalidExpression {
+                expression: expression.to_string(),
+                error: EvalError::TypeMismatch(format!("expected {what}, found {ty}")),
+            };
+            return Err(self.error(module, error));
+        }
+        Ok(())
+    }
+
+    fn lower(mut self) -> Result<Expr, ParseError> {
+        for module in 0..self.modules.len() {
+            for (name, _) in self.modules[module].aliases.clone() {
+                let alias = TypeRef::Named {
+                    name,
+                    args: Vec::new(),
+                };
+                self.resolve_type(module, &alias, &Env::new())?;
+            }
+            for index in 0..self.modules[module].enums.len() {
+                self.lower_enum(module, index)?;
+            }
+            for index in 0..self.modules[module].structs.len() {
+                if self.modules[module].structs[index].params.is_empty() {
+                    self.instantiate(module, module, index, Vec::new())?;
+                }
+            }
+        }
+        for link in &self.links {
+            let layout_ids = link.names(&self.expr.layout_ids);
+            self.expr.layout_ids.extend(layout_ids);
+            let enum_ids = link.names(&self.expr.enum_ids);
+            self.expr.enum_ids.extend(enum_ids);
+            let aliases = link.names(&self.expr.aliases);
+            self.expr.aliases.extend(aliases);
+        }
+        Ok(self.expr)
+    }
+}
+
+/// Another name for the namespace a file was first loaded into, for files
+/// that are imported more than once.
+struct Link {
+    file: String,
+    alias: String,
+    target: String,
+}
+
+impl Link {
+    /// The names in `names` under the target namespace, moved to the alias.
+    fn names<T: Clone>(&self, names: &HashMap<String, T>) -> Vec<(String, T)> {
+        let target = format!("{}::", self.target);
+        names
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.strip_prefix(&target)?;
+                Some((qualify(&self.alias, name), value.clone()))
+            })
+            .collect()
+    }
+}
+
+/// Parses a file and everything it imports into modules.
+struct Loader<'a, R> {
+    resolver: &'a mut R,
+    /// Files currently being loaded, used to detect import cycles.
+    stack: Vec<String>,
+    /// The namespace each loaded file was imported into, by resolved path.
+    loaded: HashMap<String, String>,
+    links: Vec<Link>,
+    modules: Vec<Module>,
+}
+
+impl<R: Resolver> Loader<'_, R> {
+    /// Parses `source` and everything it imports, depth first. Names declared
+    /// in an imported file live under the import's alias, which defaults to
+    /// the file stem, so `import "net/common.lay";` makes `Header` available
+    /// as `common::Header`. A file is only loaded once, importing it again
+    /// links the new alias to its first namespace, so both refer to the same
+    /// types.
+    fn load_module(&mut self, source: Source, prefix: String) -> Result<(), ParseError> {
+        let items = struct_parser::items(&source.text)
+            .map_err(|e| ParseError::from(e).in_file(&source.name))?;
+
+        self.loaded.insert(source.name.clone(), prefix.clone());
+        self.stack.push(source.name.clone());
+        self.load_items(&source.name, &prefix, prefix.clone(), items)?;
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn load_items(
+        &mut self,
+        file: &str,
+        root: &str,
+        prefix: String,
+        items: Vec<Item>,
+    ) -> Result<(), ParseError> {
+        let mut structs = Vec::new();
+        let mut enums = Vec::new();
+        let mut aliases = Vec::new();
+        for item in items {
+            match item {
+                Item::Import { path, alias } => {
+                    let imported = self.resolver.resolve(file, &path).map_err(|error| {
+                        ParseError::Import {
+                            path: path.clone(),
+                            error,
+                        }
+                        .in_file(file)
+                    })?;
+                    if let Some(start) = self.stack.iter().position(|name| *name == imported.name) {
+                        let mut cycle = self.stack[start..].to_vec();
+                        cycle.push(imported.name);
+                        return Err(ParseError::ImportCycle(cycle).in_file(file));
+                    }
+                    let alias = alias.unwrap_or_else(|| {
+                        let file = path.rsplit('/').next().unwrap_or(&path);
+                        file.split('.').next().unwrap_or(file).to_string()
+                    });
+                    let alias = qualify(&prefix, &alias);
+                    match self.loaded.get(&imported.name) {
+                        Some(target) if *target == alias => {}
+                        Some(target) => self.links.push(Link {
+                            file: file.to_string(),
+                            alias,
+                            target: target.clone(),
+                        }),
+                        None => self.load_module(imported, alias)?,
+                    }
+                }
+                Item::Module { name, items } => {
+                    self.load_items(file, root, qualify(&prefix, &name), items)?;
+                }
+                Item::Struct(def) => structs.push(def),
+                Item::Enum(def) => enums.push(def),
+                Item::Alias { name, ty } => aliases.push((name, ty)),
+            }
+        }
+
+        self.modules.push(Module {
+            file: file.to_string(),
+            root: root.to_string(),
+            prefix,
+            structs,
+            enums,
+            aliases,
+        });
+        Ok(())
+    }
+}
+
+impl Expr {
+    /// Parses a single layout source. There are no other files to load, so
+    /// any `import` in it fails with [`ParseError::Import`], use
+    /// [`Expr::parse_file`] for multi-file layouts.
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let mut resolver = MemoryResolver::new();
+        resolver.insert("<input>", input);
+        Self::parse_file("<input>", &mut resolver)
+    }
+
+    /// Parses the layout file at `path`, loading its imports through `resolver`.
+    pub fn parse_file(path: &str, resolver: &mut impl Resolver) -> Result<Self, ParseError> {
+        let source = resolver
+            .resolve("", path)
+            .map_err(|error| ParseError::Import {
+                path: path.to_string(),
+                error,
+            })?;
+        let mut loader = Loader {
+            resolver,
+            stack: Vec::new(),
+            loaded: HashMap::new(),
+            links: Vec::new(),
+            modules: Vec::new(),
+        };
+        loader.load_module(source, String::new())?;
+
+        Lowering::new(loader.modules, loader.links)?.lower()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use half::{bf16, f16};
+
+    use super::*;
+    use crate::epxr::{ReadError, Size};
+    use crate::value::EncodeError;
+
+    /// The error parsing `source` fails with, unwrapped from the file.
+    fn parse_error(source: &str) -> ParseError {
+        match Expr::parse(source).unwrap_err() {
+            ParseError::InFile { error, .. } => *error,
+            error => error,
+        }
+    }
+
+    #[test]
+    fn struct_containing_itself_is_a_cycle() {
+        let error =
+            parse_error("struct List<T> { v: T, next: List<T> } struct A { list: List<u8> }");
+        assert!(matches!(error, ParseError::InstantiationCycle(name) if name == "List"));
+        let error = parse_error("struct Node { v: u8, next: Node }");
+        assert!(matches!(error, ParseError::InstantiationCycle(name) if name == "Node"));
+        let error = parse_error("struct A { b: B } struct B { a: A }");
+        assert!(matches!(error, ParseError::InstantiationCycle(name) if name == "A"));
+        let error = parse_error("struct Grow<T> { x: Grow<[T; 2]> } struct A { g: Grow<u8> }");
+        assert!(matches!(error, ParseError::InstantiationCycle(name) if name == "Grow"));
+    }
+
+    #[test]
+    fn struct_can_point_to_itself() {
+        let source = "struct List<T> { v: T, next: ptr<List<T>> } struct A { list: List<u8> }";
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("List<u8>").unwrap();
+        assert_eq!(expr.size_of(id), Some(Size::Fixed(5)));
+    }
+
+    #[test]
+    fn pointers_back_to_their_struct() {
+        let expr = Expr::parse("struct Node { v: u8, next: ptr<Node> }").unwrap();
+        let id = expr.get_id("Node").unwrap();
+        let value = expr.default_value(id).unwrap();
+        let null = Value::Pointer {
+            offset: None,
+            target: None,
+        };
+        assert_eq!(value.field("next"), Some(&null));
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [0; 5]);
+        let value = expr.read_value(&[1, 0, 0, 0, 0], id).unwrap();
+        assert!(matches!(
+            value.field("next"),
+            Some(Value::Pointer { target: None, .. })
+        ));
+
+        // The second node points to itself.
+        let error = expr
+            .read_value(&[1, 5, 0, 0, 0, 2, 5, 0, 0, 0], id)
+            .unwrap_err();
+        assert!(matches!(error, ReadError::PointerCycle { offset: 5, .. }));
+
+        // Each node points to the one after it, the last one to nothing.
+        let list = |len: u32| {
+            let node = |i: u32| {
+                let next = if i + 1 < len { 5 * (i + 1) } else { 0 };
+                [[i as u8].as_slice(), &next.to_le_bytes()].concat()
+            };
+            (0..len).flat_map(node).collect::<Vec<_>>()
+        };
+        let value = expr.read_value(&list(10), id).unwrap();
+        let mut node = &value;
+        for i in 0..10 {
+            assert_eq!(node.field("v"), Some(&Value::U8(i)));
+            if let Some(Value::Pointer {
+                target: Some(next), ..
+            }) = node.field("next")
+            {
+                node = next;
+            }
+        }
+        let error = expr.read_value(&list(100), id).unwrap_err();
+        assert!(matches!(error, ReadError::PointerDepth { .. }));
+
+        // Instances taking a pointer back are named after it.
+        let source = "struct Box<T> { t: T } struct Node { v: u8, next: Box<ptr<Node>> }";
+        let expr = Expr::parse(source).unwrap();
+        let next = &expr.get("Node").unwrap().fields[1];
+        assert_eq!(next.type_name(&expr), "Box<ptr<Node>>");
+
+        // Pointers that do not lead back are followed by default.
+        let expr = Expr::parse("struct H { x: u16 = 7 } struct A { h: ptr<H> }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = expr.default_value(id).unwrap();
+        let Some(Value::Pointer {
+            target: Some(target),
+            ..
+        }) = value.field("h")
+        else {
+            panic!("the default points to a default H");
+        };
+        assert_eq!(target.field("x"), Some(&Value::U16(7)));
+    }
+
+    #[test]
+    fn whole_byte_bit_widths_outside_bits_structs() {
+        let source = "struct A { t: timestamp<u48, unix, ms>, x: i24 }";
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("A").unwrap();
+        assert_eq!(expr.size_of(id), Some(Size::Fixed(9)));
+        let bytes = [1, 2, 3, 4, 5, 6, 0xFE, 0xFF, 0xFF];
+        let value = expr.read_value(&bytes, id).unwrap();
+        assert_eq!(value.field("x"), Some(&Value::I32(-2)));
+        let Some(Value::Timestamp { raw, .. }) = value.field("t") else {
+            panic!("t is a timestamp");
+        };
+        assert_eq!(*raw, 0x060504030201);
+        assert_eq!(value.encode_value(&expr, id).unwrap(), bytes);
+
+        let error = parse_error("struct A { x: u12 }");
+        assert!(matches!(error, ParseError::BitsOutsideBitLayout { .. }));
+    }
+
+    #[test]
+    fn float_constants_match_what_is_read() {
+        let expr = Expr::parse("struct A { const x: f32 = 0.1, y: f32 = 0.1 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = expr.default_value(id).unwrap();
+        let bytes = value.encode_value(&expr, id).unwrap();
+        assert_eq!(bytes, [0.1f32.to_le_bytes(), 0.1f32.to_le_bytes()].concat());
+        assert_eq!(expr.read_value(&bytes, id).unwrap(), value);
+
+        let expr = Expr::parse("struct A { const x: f16 = 0.1, const y: bf16 = 0.1 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = expr.default_value(id).unwrap();
+        let bytes = value.encode_value(&expr, id).unwrap();
+        let stored = [
+            f16::from_f64(0.1).to_le_bytes(),
+            bf16::from_f64(0.1).to_le_bytes(),
+        ];
+        assert_eq!(bytes, stored.concat());
+        assert_eq!(expr.read_value(&bytes, id).unwrap(), value);
+    }
+
+    #[test]
+    fn names_resolve_from_the_innermost_module() {
+        let source = "struct Header { a: u8 }
+            mod nav { struct Header { a: u16 } mod gps { struct Fix { h: Header } } }
+            struct A { fix: nav::gps::Fix, h: Header }";
+        let expr = Expr::parse(source).unwrap();
+        assert_eq!(
+            expr.size_of(expr.get_id("nav::gps::Fix").unwrap()),
+            Some(Size::Fixed(2))
+        );
+        assert_eq!(
+            expr.size_of(expr.get_id("A").unwrap()),
+            Some(Size::Fixed(3))
+        );
+        assert_eq!(
+            expr.resolve("nav::gps", "Header"),
+            expr.get_id("nav::Header")
+        );
+        assert_eq!(expr.resolve("", "Header"), expr.get_id("Header"));
+        assert_eq!(
+            expr.resolve("nav", "gps::Fix"),
+            expr.get_id("nav::gps::Fix")
+        );
+
+        let error = parse_error("mod nav { struct Fix { a: u8 } } struct A { fix: Fix }");
+        assert!(matches!(error, ParseError::UnknownType(name) if name == "Fix"));
+        let error =
+            parse_error("mod nav { struct Fix { a: u8 } } mod nav { struct Fix { a: u8 } }");
+        assert!(matches!(error, ParseError::DuplicateName(name) if name == "nav::Fix"));
+    }
+
+    #[test]
+    fn aliases_keep_the_name_they_were_written_as() {
+        let source = "type Meters = u16; type Alt = Meters; struct A { alt: Alt, raw: u16 }";
+        let expr = Expr::parse(source).unwrap();
+        let layout = expr.get("A").unwrap();
+        assert_eq!(layout.fields[0].ty, Type::U16);
+        assert_eq!(layout.fields[0].type_name(&expr), "Alt");
+        assert_eq!(layout.fields[1].type_name(&expr), "u16");
+
+        // Structs keep their qualified name whatever they are referred to as.
+        let source = "mod nav { struct Header { a: u8 } } type H = nav::Header; struct A { h: H }";
+        let expr = Expr::parse(source).unwrap();
+        let h = &expr.get("A").unwrap().fields[0];
+        assert_eq!(
+            (h.type_name(&expr).as_ref(), h.ty.name(&expr).as_ref()),
+            ("H", "nav::Header")
+        );
+        assert_eq!(expr.get("nav::Header").unwrap().name, "nav::Header");
+
+        let error = parse_error("type A = B; type B = A; struct S { x: A }");
+        assert!(matches!(error, ParseError::AliasCycle(_)));
+        let error = parse_error("type Meters = u16; struct A { alt: Meters<u8> }");
+        assert!(matches!(
+            error,
+            ParseError::GenericArity {
+                expected: 0,
+                found: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn generic_structs_are_instantiated_once_per_arguments() {
+        let source = "struct Buf<T, const N> { len: u8, items: [T; N] }
+            struct A { a: Buf<u16, 2>, b: Buf<u16, 2>, c: Buf<u8, 3> }";
+        let expr = Expr::parse(source).unwrap();
+        let fields = &expr.get("A").unwrap().fields;
+        assert_eq!(fields[0].ty, fields[1].ty);
+        assert_ne!(fields[0].ty, fields[2].ty);
+        let id = expr.get_id("Buf<u16, 2>").unwrap();
+        assert_eq!(expr.layouts[&id].name, "Buf<u16, 2>");
+        assert_eq!(expr.size_of(id), Some(Size::Fixed(5)));
+        assert_eq!(
+            expr.size_of(expr.get_id("Buf<u8, 3>").unwrap()),
+            Some(Size::Fixed(4))
+        );
+        // Only instances are laid out, the template itself is not.
+        assert_eq!(expr.get_id("Buf"), None);
+
+        let error = parse_error("struct Buf<T, const N> { items: [T; N] } struct A { b: Buf<u8> }");
+        assert!(matches!(
+            error,
+            ParseError::GenericArity {
+                expected: 2,
+                found: 1,
+                ..
+            }
+        ));
+        let error =
+            parse_error("struct Buf<T, const N> { items: [T; N] } struct A { b: Buf<2, u8> }");
+        assert!(matches!(error, ParseError::ExpectedType(arg) if arg == "2"));
+        let error =
+            parse_error("struct Buf<T, const N> { items: [T; N] } struct A { b: Buf<u8, u8> }");
+        assert!(matches!(error, ParseError::ExpectedConst(arg) if arg == "u8"));
+    }
+
+    #[test]
+    fn if_block_after_field_without_comma() {
+        let expr = Expr::parse("struct A { flags: u8\n if flags == 1 { x: u8 } }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = expr.read_value(&[1, 2], id).unwrap();
+        assert_eq!(value.field("x"), Some(&Value::U8(2)));
+        let expr = Expr::parse("struct A { flags: u8, x: u8 if flags == 1\n y: u8 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        assert_eq!(
+            expr.read_value(&[0, 2], id).unwrap().field("y"),
+            Some(&Value::U8(2))
+        );
+    }
+
+    #[test]
+    fn array_sized_by_fields() {
+        let expr = Expr::parse("struct A { n: u8, items: [u16; n - 1], rest: u8 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let bytes = [3, 1, 0, 2, 0, 9];
+        let value = expr.read_value(&bytes, id).unwrap();
+        let items = Value::Array(vec![Value::U16(1), Value::U16(2)]);
+        assert_eq!(value.field("items"), Some(&items));
+        assert_eq!(value.encode_value(&expr, id).unwrap(), bytes);
+
+        let error = parse_error("struct A { n: u8, items: [[u8; n]; 2] }");
+        assert!(matches!(error, ParseError::UnsizedArray(ty) if ty == "[u8; n]"));
+        let error = parse_error("struct A { n: u8, items: [u8; m] }");
+        assert!(matches!(error, ParseError::UnknownField(name) if name == "m"));
+    }
+
+    #[test]
+    fn lengths_size_the_fields_after_them() {
+        let expr = Expr::parse("struct A { n: u8 = len(payload), payload: bytes[n] }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = Value::Struct {
+            fields: vec![("payload".into(), Value::Bytes(vec![7, 8, 9]))],
+        };
+        let bytes = value.encode_value(&expr, id).unwrap();
+        assert_eq!(bytes, [3, 7, 8, 9]);
+        let decoded = expr.read_value(&bytes, id).unwrap();
+        assert_eq!(decoded.field("payload"), value.field("payload"));
+
+        let expr = Expr::parse("struct A { n: u8 = count(items), items: [u16; n] }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let items = Value::Array(vec![Value::U16(1), Value::U16(2)]);
+        let value = Value::Struct {
+            fields: vec![("items".into(), items)],
+        };
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [2, 1, 0, 2, 0]);
+
+        let source = "struct A { n: u16 = len(..), tag: varint, data: bytes[n - 3] }";
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = Value::Struct {
+            fields: vec![
+                ("tag".into(), Value::I64(1)),
+                ("data".into(), Value::Bytes(vec![9, 9])),
+            ],
+        };
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [5, 0, 1, 9, 9]);
+
+        let error = parse_error("struct A { a: u8, crc: u8 = crc8(a), b: bytes[crc] }");
+        assert!(matches!(
+            error,
+            ParseError::Checksum { field, error: ChecksumError::Referenced(name) }
+                if field == "crc" && name == "b"
+        ));
+    }
+
+    #[test]
+    fn conditions_only_refer_to_entered_fields() {
+        let computed = |source| match parse_error(source) {
+            ParseError::ComputedInCondition { computed, .. } => computed,
+            error => panic!("{error}"),
+        };
+        assert_eq!(
+            computed("struct A { a: u8, derived s: u8 = a + 1, if s > 1 { x: u8 } }"),
+            "s"
+        );
+        assert_eq!(
+            computed("struct A { n: u8 = count(b), b: [u8; n], if n > 0 { x: u8 } }"),
+            "n"
+        );
+        let source = "struct H { n: u8 = len(p), p: [u8; 2] } struct A { h: H, x: u8 if h.n > 0 }";
+        assert_eq!(computed(source), "h.n");
+        // Padding is not part of values at all.
+        let error = parse_error("struct A { #[reserved] r: u8, x: u8 if r > 0 }");
+        assert!(matches!(error, ParseError::UnknownField(name) if name == "r"));
+
+        // What they are computed from can be referred to, and sizes can refer to them.
+        let source = "struct A { a: u8, derived s: u8 = a + 1, n: u8 = count(b), b: [u8; n], x: u8 if a > 0 }";
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = expr.read_value(&[1, 2, 1, 7, 9], id).unwrap();
+        assert_eq!(value.field("x"), Some(&Value::U8(9)));
+    }
+
+    #[test]
+    fn fields_computed_from_expressions() {
+        let expr = Expr::parse("struct A { a: u8, b: u8, derived t: u16 = a + b, d: u8 = 2 * 8 }")
+            .unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = Value::Struct {
+            fields: vec![("a".into(), Value::U8(200)), ("b".into(), Value::U8(100))],
+        };
+        let bytes = value.encode_value(&expr, id).unwrap();
+        assert_eq!(bytes, [200, 100, 0x2C, 0x01, 16]);
+        let decoded = expr.read_value(&bytes, id).unwrap();
+        assert_eq!(decoded.field("t"), Some(&Value::U16(300)));
+        let error = expr.read_value(&[200, 100, 0, 0, 16], id).unwrap_err();
+        assert!(matches!(error, ReadError::DerivedMismatch { path, .. } if path == "t"));
+
+        let expr = Expr::parse("struct A { a: u8, b: u8, derived t: u8 = a + b }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(
+            error,
+            EncodeError::Derived {
+                error: EvalError::OutOfRange { .. },
+                ..
+            }
+        ));
+
+        let error = parse_error("struct A { a: u8, derived t: u8 = a > 1 }");
+        assert!(matches!(error, ParseError::InvalidExpression { .. }));
+        let error = parse_error("struct A { derived t: u8 = a + 1, a: u8 }");
+        assert!(matches!(error, ParseError::UnknownField(name) if name == "a"));
+        // Only constants are defaults, computed fields say so.
+        let error = parse_error("struct A { a: u8, t: u8 = a + 1 }");
+        assert!(matches!(error, ParseError::Parse(_)));
+        let error = parse_error("struct A { a: u8, derived t: u8 }");
+        assert!(matches!(error, ParseError::Parse(_)));
+    }
+
+    #[test]
+    fn inline_structs_are_named_after_their_fields() {
+        let source = "struct A { p: struct { x: u8, y: [u8; 2] }, u: union { a: u16, b: u8 }, e: struct {} }";
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("A").unwrap();
+        let fields = &expr.get_type(id).unwrap().fields;
+        let names = fields
+            .iter()
+            .map(|field| field.type_name(&expr))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "struct { x: u8, y: [u8; 2] }",
+                "union { a: u16, b: u8 }",
+                "struct {}"
+            ]
+        );
+        // They are not layouts of their own.
+        assert_eq!(expr.layout_ids.len(), 1);
+        assert_eq!(expr.size_of(id), Some(Size::Fixed(5)));
+
+        let value = expr.read_value(&[1, 2, 3, 4, 5], id).unwrap();
+        let y = value.field("p").and_then(|p| p.field("y"));
+        assert_eq!(y, Some(&Value::Array(vec![Value::U8(2), Value::U8(3)])));
+    }
 }
@@ -0,0 +1,146 @@
+use std::{fmt, ops::Range};
+
+use crate::epxr::Type;
+use crate::expression::Expression;
+
+/// An offset to a value stored elsewhere in the buffer, `ptr<Header>` for a
+/// `u32` offset from the start of the buffer or `ptr<Header, u16>` for a
+/// narrower one. `rel_ptr<...>` counts from the start of the enclosing
+/// struct instead. An offset of 0 is a null pointer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pointer {
+    pub target: Type,
+    /// The integer type the offset is stored as.
+    pub storage: Type,
+    pub base: Base,
+}
+
+/// What an offset counts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Base {
+    /// The start of the buffer.
+    Buffer,
+    /// The start of the struct the offset is declared in.
+    Struct,
+}
+
+/// Where a field that is not laid out in sequence lives, declared as
+/// `header: Header @ 0x40` or `entry: Entry @ self + entry_offset`. The
+/// offset may refer to the fields before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub base: Base,
+    pub offset: Expression,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.base {
+            Base::Buffer => write!(f, "@ {}", self.offset),
+            Base::Struct => write!(f, "@ self + {}", self.offset),
+        }
+    }
+}
+
+/// Byte ranges of a buffer claimed by the values read from or written to
+/// them, used to catch values laid over each other.
+#[derive(Debug, Default)]
+pub(crate) struct Regions(Vec<Region>);
+
+#[derive(Debug)]
+struct Region {
+    range: Range<usize>,
+    path: String,
+    shared: bool,
+}
+
+impl Regions {
+    /// Claims `range` for the value at `path`. Returns what it overlaps, if
+    /// anything. Pointer targets are `shared`, two pointers may point to the
+    /// same value.
+    pub(crate) fn claim(
+        &mut self,
+        range: Range<usize>,
+        path: &str,
+        shared: bool,
+    ) -> Result<(), String> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let overlap = self.0.iter().find(|other| {
+            let same = other.range == range && other.shared && shared;
+            !same && other.range.start < range.end && range.start < other.range.end
+        });
+        if let Some(other) = overlap {
+            return Err(other.path.clone());
+        }
+        self.0.push(Region {
+            range,
+            path: path.to_string(),
+            shared,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::epxr::{Expr, ReadError};
+    use crate::value::Value;
+
+    #[test]
+    fn targets_are_placed_after_the_struct() {
+        let source = "struct H { x: u16 } struct A { h: ptr<H, u8>, y: u8, r: rel_ptr<H, u8> }";
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("A").unwrap();
+        let target = |x| Value::Pointer {
+            offset: None,
+            target: Some(Box::new(Value::Struct {
+                fields: vec![("x".into(), Value::U16(x))],
+            })),
+        };
+        let value = Value::Struct {
+            fields: vec![
+                ("h".into(), target(7)),
+                ("y".into(), Value::U8(1)),
+                ("r".into(), target(9)),
+            ],
+        };
+        // Each target goes after the struct and the targets before it.
+        let bytes = [3, 1, 5, 7, 0, 9, 0];
+        assert_eq!(value.encode_value(&expr, id).unwrap(), bytes);
+        let decoded = expr.read_value(&bytes, id).unwrap();
+        assert!(matches!(
+            decoded.field("h"),
+            Some(Value::Pointer {
+                offset: Some(3),
+                ..
+            })
+        ));
+        assert!(matches!(
+            decoded.field("r"),
+            Some(Value::Pointer {
+                offset: Some(5),
+                ..
+            })
+        ));
+        assert_eq!(decoded.encode_value(&expr, id).unwrap(), bytes);
+
+        // Targets may be shared, but not overlap the struct.
+        assert!(expr.read_value(&[3, 1, 3, 7, 0], id).is_ok());
+        let error = expr.read_value(&[1, 1, 0, 7, 0], id).unwrap_err();
+        assert!(matches!(error, ReadError::Overlap { path, .. } if path == "h"));
+        let error = expr.read_value(&[9, 1, 0], id).unwrap_err();
+        assert!(matches!(error, ReadError::InvalidOffset { offset: 9, .. }));
+    }
+
+    #[test]
+    fn located_fields_take_no_space_in_sequence() {
+        let expr = Expr::parse("struct A { n: u8, x: u16 @ self + n, y: u8 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = expr.read_value(&[3, 5, 0, 2, 1], id).unwrap();
+        assert_eq!(value.field("x"), Some(&Value::U16(0x0102)));
+        assert_eq!(value.field("y"), Some(&Value::U8(5)));
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [3, 5, 0, 2, 1]);
+    }
+}
@@ -0,0 +1,174 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// A layout source file, named by the path it was resolved to.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+/// Finds the source of files referenced by `import` declarations.
+pub trait Resolver {
+    /// Resolves `path`, as written in an import inside the file `from`.
+    fn resolve(&mut self, from: &str, path: &str) -> io::Result<Source>;
+}
+
+/// Resolves imports relative to the importing file on the local filesystem.
+#[derive(Debug, Default, Clone)]
+pub struct FsResolver;
+
+impl Resolver for FsResolver {
+    fn resolve(&mut self, from: &str, path: &str) -> io::Result<Source> {
+        let name = join(from, path);
+        let text = fs::read_to_string(Path::new(&name))?;
+        Ok(Source { name, text })
+    }
+}
+
+/// Resolves imports from a set of sources that were loaded up front,
+/// e.g. fetched over HTTP before parsing.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryResolver {
+    pub files: HashMap<String, String>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, text: impl Into<String>) {
+        self.files.insert(name.into(), text.into());
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.files.contains_key(name)
+    }
+}
+
+impl Resolver for MemoryResolver {
+    fn resolve(&mut self, from: &str, path: &str) -> io::Result<Source> {
+        let name = join(from, path);
+        match self.files.get(&name) {
+            Some(text) => Ok(Source {
+                name,
+                text: text.clone(),
+            }),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{name} not found"),
+            )),
+        }
+    }
+}
+
+/// Joins an import path onto the directory of the importing file, folding
+/// away `.` and `..` components. Paths starting with `/` are kept as is.
+pub fn join(from: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    let absolute = path.starts_with('/') || (!path.is_empty() && from.starts_with('/'));
+    if !path.starts_with('/') {
+        parts.extend(from.split('/').filter(|part| !part.is_empty()));
+        // Drop the importing file's own name.
+        parts.pop();
+    }
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if matches!(parts.last(), None | Some(&"..")) {
+                    parts.push("..");
+                } else {
+                    parts.pop();
+                }
+            }
+            part => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if absolute {
+        format!("/{joined}")
+    } else {
+        joined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epxr::Expr;
+    use crate::parser::ParseError;
+    use crate::value::Value;
+
+    /// `error`, unwrapped from the file it was found in.
+    fn error_in_file(error: ParseError) -> Box<ParseError> {
+        match error {
+            ParseError::InFile { error, .. } => error,
+            error => Box::new(error),
+        }
+    }
+
+    #[test]
+    fn joins_relative_to_the_importing_file() {
+        assert_eq!(join("layouts/main.lay", "common.lay"), "layouts/common.lay");
+        assert_eq!(
+            join("layouts/main.lay", "./net/ip.lay"),
+            "layouts/net/ip.lay"
+        );
+        assert_eq!(join("layouts/main.lay", "../shared.lay"), "shared.lay");
+        assert_eq!(join("main.lay", "../shared.lay"), "../shared.lay");
+        assert_eq!(join("/srv/main.lay", "common.lay"), "/srv/common.lay");
+        assert_eq!(
+            join("layouts/main.lay", "/etc/common.lay"),
+            "/etc/common.lay"
+        );
+    }
+
+    #[test]
+    fn imports_from_memory() {
+        let mut resolver = MemoryResolver::new();
+        resolver.insert(
+            "main.lay",
+            "import \"net/ip.lay\" as ip; struct A { h: ip::Header }",
+        );
+        resolver.insert("net/ip.lay", "struct Header { version: u8 }");
+        let expr = Expr::parse_file("main.lay", &mut resolver).unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = expr.read_value(&[4], id).unwrap();
+        assert_eq!(
+            value.field("h").and_then(|h| h.field("version")),
+            Some(&Value::U8(4))
+        );
+
+        let mut resolver = MemoryResolver::new();
+        resolver.insert("main.lay", "import \"missing.lay\"; struct A { x: u8 }");
+        let error = Expr::parse_file("main.lay", &mut resolver).unwrap_err();
+        assert!(matches!(error, ParseError::InFile { file, .. } if file == "main.lay"));
+        let error = Expr::parse("import \"common.lay\"; struct A { x: u8 }").unwrap_err();
+        assert!(matches!(*error_in_file(error), ParseError::Import { .. }));
+    }
+
+    #[test]
+    fn files_imported_twice_are_loaded_once() {
+        let mut resolver = MemoryResolver::new();
+        resolver.insert(
+            "main.lay",
+            "import \"b.lay\"; import \"c.lay\"; struct A { b: b::B, c: c::C }",
+        );
+        resolver.insert("b.lay", "import \"d.lay\"; struct B { h: d::Header }");
+        resolver.insert("c.lay", "import \"d.lay\"; struct C { h: d::Header }");
+        resolver.insert("d.lay", "struct Header { version: u8 }");
+        let expr = Expr::parse_file("main.lay", &mut resolver).unwrap();
+        let header = |name: &str| expr.get(name).unwrap().fields[0].ty.clone();
+        assert_eq!(header("b::B"), header("c::C"));
+        // Both paths name the same layout.
+        assert_eq!(expr.get_id("b::d::Header"), expr.get_id("c::d::Header"));
+        assert!(expr.get_id("c::d::Header").is_some());
+
+        let mut resolver = MemoryResolver::new();
+        resolver.insert("main.lay", "import \"a.lay\"; struct A { x: u8 }");
+        resolver.insert("a.lay", "import \"main.lay\";");
+        let error = Expr::parse_file("main.lay", &mut resolver).unwrap_err();
+        assert!(matches!(*error_in_file(error), ParseError::ImportCycle(_)));
+    }
+}
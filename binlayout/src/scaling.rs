@@ -0,0 +1,197 @@
+use thiserror::Error;
+
+use crate::epxr::{Expr, Type};
+use crate::expression::Scalar;
+use crate::parser::Literal;
+use crate::value::Value;
+
+/// The engineering value a raw number stands for, `raw * scale + offset`,
+/// declared with `#[scale(0.01)]` and `#[offset(-40)]` on a numeric field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scaling {
+    pub scale: f64,
+    pub offset: f64,
+    /// How engineering values are rounded to integer raw values.
+    pub rounding: Rounding,
+    /// The unit of the engineering values, declared with `#[unit("degC")]`.
+    pub unit: Option<String>,
+}
+
+/// Rounding mode for converting engineering values back to raw integers,
+/// declared with `#[round("floor")]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// Rounds half way cases away from zero.
+    #[default]
+    Nearest,
+    Floor,
+    Ceil,
+    Trunc,
+    /// Rejects values that are not an exact multiple of the scale.
+    Exact,
+}
+
+impl Rounding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "nearest" => Rounding::Nearest,
+            "floor" => Rounding::Floor,
+            "ceil" => Rounding::Ceil,
+            "trunc" => Rounding::Trunc,
+            "exact" => Rounding::Exact,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ScaleError {
+    #[error("{value} does not fit in {ty} after scaling")]
+    Overflow { value: f64, ty: String },
+    #[error("{value} is not a multiple of {scale}")]
+    Inexact { value: f64, scale: f64 },
+}
+
+impl Scaling {
+    /// Presents a raw value in engineering units as a [`Value::Scaled`],
+    /// element wise for arrays. Other values are returned as is.
+    pub fn apply(&self, raw: Value) -> Value {
+        if let Value::Array(elements) = raw {
+            return Value::Array(elements.into_iter().map(|v| self.apply(v)).collect());
+        }
+        let value = match Scalar::of(&raw) {
+            Some(Scalar::Int(v)) => v as f64,
+            Some(Scalar::Float(v)) => v,
+            _ => return raw,
+        };
+        Value::Scaled {
+            raw: Box::new(raw),
+            value: value * self.scale + self.offset,
+            unit: self.unit.clone(),
+        }
+    }
+
+    /// Converts the engineering value `value` back to a raw value of type
+    /// `ty`, rounding it for integer types.
+    pub fn raw(&self, expr: &Expr, ty: &Type, value: f64) -> Result<Value, ScaleError> {
+        let overflow = || ScaleError::Overflow {
+            value,
+            ty: ty.name(expr).into_owned(),
+        };
+        let raw = (value - self.offset) / self.scale;
+        if let Some(stored) = ty.round_float(raw) {
+            // Values too large for the float type would be stored as infinity.
+            if !stored.is_finite() {
+                return Err(overflow());
+            }
+            return Literal::Float(raw).to_value(expr, ty).ok_or_else(overflow);
+        }
+        let rounded = match self.rounding {
+            Rounding::Nearest => raw.round(),
+            Rounding::Floor => raw.floor(),
+            Rounding::Ceil => raw.ceil(),
+            Rounding::Trunc => raw.trunc(),
+            Rounding::Exact => {
+                let rounded = raw.round();
+                // Allow for the error of the division above.
+                if (rounded - raw).abs() > 1e-9 * rounded.abs().max(1.0) {
+                    return Err(ScaleError::Inexact {
+                        value,
+                        scale: self.scale,
+                    });
+                }
+                rounded
+            }
+        };
+        if !(i128::MIN as f64..=i128::MAX as f64).contains(&rounded) {
+            return Err(overflow());
+        }
+        Literal::Int(rounded as i128)
+            .to_value(expr, ty)
+            .ok_or_else(overflow)
+    }
+
+    /// The engineering value `value` of a field of type `ty`, along with the
+    /// raw value it rounds to.
+    pub fn scaled(&self, expr: &Expr, ty: &Type, value: f64) -> Result<Value, ScaleError> {
+        Ok(Value::Scaled {
+            raw: Box::new(self.raw(expr, ty, value)?),
+            value,
+            unit: self.unit.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaling(scale: f64, offset: f64, rounding: Rounding) -> Scaling {
+        Scaling {
+            scale,
+            offset,
+            rounding,
+            unit: None,
+        }
+    }
+
+    #[test]
+    fn applies_scale_then_offset() {
+        let celsius = Scaling {
+            unit: Some("degC".into()),
+            ..scaling(0.5, -40.0, Rounding::Nearest)
+        };
+        let scaled = celsius.apply(Value::U8(100));
+        assert_eq!(scaled.to_string(), "10.000000 degC");
+        let Value::Scaled { raw, value, .. } = scaled else {
+            panic!("integers are scaled");
+        };
+        assert_eq!((*raw, value), (Value::U8(100), 10.0));
+        let expr = Expr::parse("").unwrap();
+        assert_eq!(celsius.raw(&expr, &Type::U8, 10.0).unwrap(), Value::U8(100));
+        assert_eq!(
+            celsius.raw(&expr, &Type::F32, 10.1).unwrap(),
+            Value::F32(100.2)
+        );
+    }
+
+    #[test]
+    fn rounds_to_raw_values() {
+        let expr = Expr::parse("").unwrap();
+        let raw = |rounding, value| scaling(0.1, 0.0, rounding).raw(&expr, &Type::I16, value);
+        assert_eq!(raw(Rounding::Nearest, 0.25).unwrap(), Value::I16(3));
+        assert_eq!(raw(Rounding::Nearest, -0.25).unwrap(), Value::I16(-3));
+        assert_eq!(raw(Rounding::Floor, 0.29).unwrap(), Value::I16(2));
+        assert_eq!(raw(Rounding::Ceil, 0.21).unwrap(), Value::I16(3));
+        assert_eq!(raw(Rounding::Trunc, -0.29).unwrap(), Value::I16(-2));
+        assert_eq!(raw(Rounding::Exact, 0.3).unwrap(), Value::I16(3));
+        assert!(matches!(
+            raw(Rounding::Exact, 0.35),
+            Err(ScaleError::Inexact { .. })
+        ));
+        assert!(matches!(
+            raw(Rounding::Nearest, 3276.8),
+            Err(ScaleError::Overflow { .. })
+        ));
+        assert!(matches!(
+            raw(Rounding::Nearest, f64::INFINITY),
+            Err(ScaleError::Overflow { .. })
+        ));
+
+        // Floats are not rounded, but still have to fit.
+        let raw = |ty, value| scaling(0.1, 0.0, Rounding::Nearest).raw(&expr, ty, value);
+        assert_eq!(raw(&Type::F64, 1e300).unwrap(), Value::F64(1e301));
+        assert!(matches!(
+            raw(&Type::F32, 1e300),
+            Err(ScaleError::Overflow { .. })
+        ));
+        assert!(matches!(
+            raw(&Type::F16, 1e4),
+            Err(ScaleError::Overflow { .. })
+        ));
+        assert!(matches!(
+            raw(&Type::F64, f64::NAN),
+            Err(ScaleError::Overflow { .. })
+        ));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::epxr::Type;
+
+const NANOS_PER_SECOND: i128 = 1_000_000_000;
+const SECONDS_PER_DAY: i128 = 86_400;
+
+/// A timestamp type, `timestamp<u32, unix, s>`: an integer counting `unit`s
+/// since `epoch`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Timestamp {
+    /// The integer type the count is stored as.
+    pub storage: Type,
+    pub epoch: Epoch,
+    pub unit: Unit,
+}
+
+impl Timestamp {
+    /// The count a point in time, in nanoseconds since the Unix epoch, is
+    /// stored as. `None` if it is not a whole number of units.
+    pub fn raw(&self, nanos: i128) -> Option<i128> {
+        let since = nanos - self.epoch.nanos();
+        (since % self.unit.nanos() == 0).then(|| since / self.unit.nanos())
+    }
+}
+
+/// The instant a timestamp counts from. Counts are converted as they are,
+/// without leap seconds, so GPS time is shown on its own scale, ahead of
+/// UTC by the leap seconds since 1980.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Epoch {
+    /// 1970-01-01.
+    Unix,
+    /// 1980-01-06, the start of GPS week 0.
+    Gps,
+    /// 1900-01-01, as in NTP.
+    Ntp,
+}
+
+impl Epoch {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(Epoch::Unix),
+            "gps" => Some(Epoch::Gps),
+            "ntp" => Some(Epoch::Ntp),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Epoch::Unix => "unix",
+            Epoch::Gps => "gps",
+            Epoch::Ntp => "ntp",
+        }
+    }
+
+    /// The epoch in nanoseconds since the Unix epoch.
+    pub fn nanos(self) -> i128 {
+        let seconds = match self {
+            Epoch::Unix => 0,
+            Epoch::Gps => 315_964_800,
+            Epoch::Ntp => -2_208_988_800,
+        };
+        seconds * NANOS_PER_SECOND
+    }
+}
+
+/// What a timestamp counts. `week` is for GPS week numbers, with the
+/// seconds into the week in a field of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unit {
+    Nanos,
+    Micros,
+    Millis,
+    Seconds,
+    Days,
+    Weeks,
+}
+
+impl Unit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ns" => Some(Unit::Nanos),
+            "us" => Some(Unit::Micros),
+            "ms" => Some(Unit::Millis),
+            "s" => Some(Unit::Seconds),
+            "day" => Some(Unit::Days),
+            "week" => Some(Unit::Weeks),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::Nanos => "ns",
+            Unit::Micros => "us",
+            Unit::Millis => "ms",
+            Unit::Seconds => "s",
+            Unit::Days => "day",
+            Unit::Weeks => "week",
+        }
+    }
+
+    pub fn nanos(self) -> i128 {
+        match self {
+            Unit::Nanos => 1,
+            Unit::Micros => 1_000,
+            Unit::Millis => 1_000_000,
+            Unit::Seconds => NANOS_PER_SECOND,
+            Unit::Days => SECONDS_PER_DAY * NANOS_PER_SECOND,
+            Unit::Weeks => 7 * SECONDS_PER_DAY * NANOS_PER_SECOND,
+        }
+    }
+
+    /// The number of fraction digits a second is shown with.
+    pub fn digits(self) -> u32 {
+        match self {
+            Unit::Nanos => 9,
+            Unit::Micros => 6,
+            Unit::Millis => 3,
+            Unit::Seconds | Unit::Days | Unit::Weeks => 0,
+        }
+    }
+}
+
+/// The point in time `raw` `unit`s after `epoch`, in nanoseconds since the
+/// Unix epoch.
+pub fn nanos(raw: i128, epoch: Epoch, unit: Unit) -> i128 {
+    epoch.nanos() + raw * unit.nanos()
+}
+
+/// Formats nanoseconds since the Unix epoch as ISO 8601 in UTC, e.g.
+/// `2024-03-01T12:00:00.250Z`, with at least `digits` fraction digits and
+/// more if the time needs them.
+pub fn format(nanos: i128, digits: u32) -> String {
+    let seconds = nanos.div_euclid(NANOS_PER_SECOND);
+    let fraction = nanos.rem_euclid(NANOS_PER_SECOND);
+    let (year, month, day) = civil(seconds.div_euclid(SECONDS_PER_DAY));
+    let time = seconds.rem_euclid(SECONDS_PER_DAY);
+    // Years past 9999 or before 0 take a sign, as in ISO 8601's expanded form.
+    let year = match year {
+        0..=9999 => format!("{year:04}"),
+        _ => format!("{year:+05}"),
+    };
+    let mut text = format!(
+        "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    );
+    let fraction = format!("{fraction:09}");
+    let needed = fraction.trim_end_matches('0').len();
+    let shown = needed.max(digits.min(9) as usize);
+    if shown > 0 {
+        text.push('.');
+        text.push_str(&fraction[..shown]);
+    }
+    text.push('Z');
+    text
+}
+
+/// Parses an ISO 8601 date and time, e.g. `2024-03-01T12:00:00.25Z`, into
+/// nanoseconds since the Unix epoch. The time, its seconds and fraction and
+/// the UTC offset may be left out, times without an offset are UTC. Years
+/// past 9999 or before 0 take a sign and up to six digits, `+012024`.
+pub fn parse(text: &str) -> Option<i128> {
+    let (date, time) = match text.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let mut parts = date.rsplitn(3, '-');
+    let day = number(parts.next()?, 2)?;
+    let month = number(parts.next()?, 2)?;
+    let year = parts.next()?;
+    let year = match year.strip_prefix(['+', '-']) {
+        Some(digits) if (4..=6).contains(&digits.len()) => {
+            let value = number(digits, digits.len())?;
+            if year.starts_with('-') { -value } else { value }
+        }
+        _ => number(year, 4)?,
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    let mut seconds = days(year, month, day) * SECONDS_PER_DAY;
+    let mut fraction = 0;
+    if let Some(time) = time {
+        let (time, offset) = split_offset(time)?;
+        let mut fields = time.splitn(3, ':');
+        let hour = number(fields.next()?, 2)?;
+        let minute = number(fields.next()?, 2)?;
+        let second = match fields.next() {
+            Some(second) => {
+                let (second, digits) = match second.split_once(['.', ',']) {
+                    Some((second, digits)) => (second, Some(digits)),
+                    None => (second, None),
+                };
+                if let Some(digits) = digits {
+                    if !(1..=9).contains(&digits.len()) {
+                        return None;
+                    }
+                    fraction = number(digits, digits.len())? * 10i128.pow(9 - digits.len() as u32);
+                }
+                number(second, 2)?
+            }
+            None => 0,
+        };
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        seconds += hour * 3600 + minute * 60 + second - offset;
+    }
+    Some(seconds * NANOS_PER_SECOND + fraction)
+}
+
+/// Splits a time into the time of day and its UTC offset in seconds, which
+/// is written `Z`, `+01:00`, `+0100` or `+01`, or left out for UTC.
+fn split_offset(time: &str) -> Option<(&str, i128)> {
+    if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        return Some((time, 0));
+    }
+    let Some(sign) = time.rfind(['+', '-']) else {
+        return Some((time, 0));
+    };
+    let (time, offset) = time.split_at(sign);
+    let (negative, offset) = offset.split_at(1);
+    let (hours, minutes) = match offset.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if offset.len() == 4 => offset.split_at(2),
+        None => (offset, "00"),
+    };
+    let (hours, minutes) = (number(hours, 2)?, number(minutes, 2)?);
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    let offset = hours * 3600 + minutes * 60;
+    Some((time, if negative == "-" { -offset } else { offset }))
+}
+
+/// Parses exactly `len` decimal digits.
+fn number(digits: &str, len: usize) -> Option<i128> {
+    if digits.len() != len || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn is_leap(year: i128) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i128, month: i128) -> i128 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days(year: i128, month: i128, day: i128) -> i128 {
+    // Count from March, so the leap day ends the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date `days` days after 1970-01-01, the inverse of [`days`].
+fn civil(days: i128) -> (i128, i128, i128) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as i128;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dates_and_times() {
+        assert_eq!(parse("1970-01-01"), Some(0));
+        assert_eq!(parse("1970-01-01T00:00:01Z"), Some(NANOS_PER_SECOND));
+        assert_eq!(
+            parse("2024-03-01T12:00:00.25Z"),
+            Some(1_709_294_400_250_000_000)
+        );
+        assert_eq!(
+            parse("2024-03-01T13:00:00+01:00"),
+            parse("2024-03-01T12:00:00Z")
+        );
+        assert_eq!(
+            parse("2024-03-01 12:00:00-0130"),
+            parse("2024-03-01T13:30:00Z")
+        );
+        assert_eq!(parse("1969-12-31T23:59:59.999999999Z"), Some(-1));
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        assert_eq!(parse("2023-02-29"), None);
+        assert_eq!(parse("2024-13-01"), None);
+        assert_eq!(parse("2024-03-01T24:00:00Z"), None);
+        assert_eq!(parse("2024-03-01T12:00:00.1234567890Z"), None);
+        assert_eq!(parse("24-03-01"), None);
+        assert!(parse("2024-02-29").is_some());
+    }
+
+    #[test]
+    fn expanded_years_take_at_most_six_digits() {
+        for text in ["+10000-01-01T00:00:00Z", "-0001-01-01T00:00:00Z"] {
+            assert_eq!(
+                parse(text).map(|nanos| format(nanos, 0)).as_deref(),
+                Some(text)
+            );
+        }
+        assert!(parse("+999999-12-31T00:00:00Z").is_some());
+        assert!(parse("-000001-01-01").is_some());
+        // Longer years would overflow the nanoseconds.
+        assert_eq!(parse("+1000000-01-01"), None);
+        assert_eq!(
+            parse("+99999999999999999999999999999999999999999-01-01"),
+            None
+        );
+    }
+
+    #[test]
+    fn formats_with_as_many_digits_as_needed() {
+        assert_eq!(format(0, 0), "1970-01-01T00:00:00Z");
+        assert_eq!(format(0, 3), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format(250_000_000, 0), "1970-01-01T00:00:00.25Z");
+        assert_eq!(format(-1, 0), "1969-12-31T23:59:59.999999999Z");
+    }
+
+    #[test]
+    fn counts_from_the_epoch() {
+        let gps = Timestamp {
+            storage: Type::U32,
+            epoch: Epoch::Gps,
+            unit: Unit::Weeks,
+        };
+        let week = parse("1980-01-13").unwrap();
+        assert_eq!(gps.raw(week), Some(1));
+        assert_eq!(nanos(1, Epoch::Gps, Unit::Weeks), week);
+        // Times between two units have no raw value.
+        assert_eq!(gps.raw(week + 1), None);
+        assert_eq!(
+            format(nanos(0, Epoch::Ntp, Unit::Seconds), 0),
+            "1900-01-01T00:00:00Z"
+        );
+    }
+}
@@ -10,3 +10,116 @@ pub fn as_hex(array: &[u8]) -> String {
     }
     hex_string
 }
+
+/// Appends a field name to a dotted field path, e.g. `header.sync`.
+pub fn field_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}.{name}")
+    }
+}
+
+/// Parses hex digits, two per byte, optionally prefixed with `0x`. Spaces
+/// between them are ignored, as in a pasted dump.
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text
+        .strip_prefix("0x")
+        .or(text.strip_prefix("0X"))
+        .unwrap_or(text);
+    let digits = text
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<Vec<_>>>()?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect(),
+    )
+}
+
+/// Parses base64, in the standard or the URL-safe alphabet, with or without
+/// padding.
+pub fn from_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let sextets = text
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .map(|b| match b {
+            b'A'..=b'Z' => Some(b - b'A'),
+            b'a'..=b'z' => Some(b - b'a' + 26),
+            b'0'..=b'9' => Some(b - b'0' + 52),
+            b'+' | b'-' => Some(62),
+            b'/' | b'_' => Some(63),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    // A lone trailing character holds fewer than 8 bits.
+    if sextets.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(sextets.len() * 3 / 4);
+    for chunk in sextets.chunks(4) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, sextet)| {
+            bits | (*sextet as u32) << (18 - 6 * i)
+        });
+        bytes.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epxr::{Expr, Type};
+    use crate::value::{EncodeError, Value};
+
+    #[test]
+    fn hex() {
+        assert_eq!(as_hex(&[0x00, 0xAB, 0x1F]), "00AB1F");
+        assert_eq!(from_hex("00ab1F"), Some(vec![0x00, 0xAB, 0x1F]));
+        assert_eq!(from_hex("0x00 AB 1f"), Some(vec![0x00, 0xAB, 0x1F]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("ABC"), None);
+        assert_eq!(from_hex("0g"), None);
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(from_base64("aGVsbG8="), Some(b"hello".to_vec()));
+        assert_eq!(from_base64("aGVsbG8"), Some(b"hello".to_vec()));
+        assert_eq!(from_base64("aGVs bG8h"), Some(b"hello!".to_vec()));
+        assert_eq!(from_base64("+/8="), Some(vec![0xFB, 0xFF]));
+        assert_eq!(from_base64("-_8"), Some(vec![0xFB, 0xFF]));
+        assert_eq!(from_base64("aGVsb"), None);
+        assert_eq!(from_base64("aG*s"), None);
+    }
+
+    #[test]
+    fn bytes_are_read_as_hex_before_base64() {
+        let expr = Expr::parse("struct A { b: bytes[3] }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let bytes = |text| Value::from_text(&expr, &Type::Bytes(Some(3)), text).unwrap();
+        // "abcd" is valid in both.
+        assert_eq!(bytes("abcd"), Value::Bytes(vec![0xAB, 0xCD]));
+        assert_eq!(bytes("base64:abcd"), Value::Bytes(vec![0x69, 0xB7, 0x1D]));
+
+        let value = Value::Struct {
+            fields: vec![("b".into(), bytes("abcd"))],
+        };
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(
+            error,
+            EncodeError::SizeMismatch {
+                expected: 3,
+                found: 2,
+                ..
+            }
+        ));
+    }
+}
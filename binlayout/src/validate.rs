@@ -0,0 +1,283 @@
+use std::fmt;
+
+use crate::epxr::{Expr, LayoutId, Type};
+use crate::expression::{CompareOp, Scalar};
+use crate::utils::field_path;
+use crate::value::Value;
+
+/// A semantic limit declared on a field with an attribute.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    /// `#[range(min, max)]`, both ends inclusive.
+    Range(Scalar, Scalar),
+    /// `#[one_of(a, b, ...)]`.
+    OneOf(Vec<Scalar>),
+}
+
+impl Constraint {
+    fn allows(&self, value: Scalar) -> bool {
+        let compare = |op, other| value.compare(op, other).unwrap_or(false);
+        match self {
+            Constraint::Range(min, max) => {
+                compare(CompareOp::Ge, *min) && compare(CompareOp::Le, *max)
+            }
+            Constraint::OneOf(allowed) => allowed.iter().any(|v| compare(CompareOp::Eq, *v)),
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constraint::Range(min, max) => write!(f, "range({min}, {max})"),
+            Constraint::OneOf(allowed) => {
+                let allowed = allowed.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                write!(f, "one_of({})", allowed.join(", "))
+            }
+        }
+    }
+}
+
+/// A value that breaks a constraint or assertion, by field path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl Expr {
+    /// Checks a decoded or to-be-encoded instance of the layout `id` against
+    /// the declared field constraints and struct assertions. Fields missing
+    /// from `value` are skipped, encoding reports those.
+    pub fn validate(&self, value: &Value, id: LayoutId) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        self.validate_struct(value, id, "", &mut violations);
+        violations
+    }
+
+    fn validate_struct(&self, value: &Value, id: LayoutId, path: &str, out: &mut Vec<Violation>) {
+        let Some(layout) = self.get_type(id) else {
+            return;
+        };
+        for field in &layout.fields {
+            let Some(field_value) = value.field(&field.name) else {
+                continue;
+            };
+            let path = field_path(path, &field.name);
+            self.validate_type(field_value, &field.ty, &field.constraints, &path, out);
+        }
+        for assertion in &layout.asserts {
+            let message = match assertion.check(value) {
+                Ok(true) => continue,
+                Ok(false) => format!("assertion failed: {assertion}"),
+                Err(e) => format!("cannot check {assertion}: {e}"),
+            };
+            out.push(Violation {
+                path: path.to_string(),
+                message,
+            });
+        }
+    }
+
+    /// Constraints on an array field apply to each of its elements.
+    fn validate_type(
+        &self,
+        value: &Value,
+        ty: &Type,
+        constraints: &[Constraint],
+        path: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        match (ty, value) {
+            (Type::Struct(id), _) => self.validate_struct(value, *id, path, out),
+            (Type::Array(element, _), Value::Array(elements)) => {
+                for (i, value) in elements.iter().enumerate() {
+                    let path = format!("{path}[{i}]");
+                    self.validate_type(value, element, constraints, &path, out);
+                }
+            }
+            (
+                Type::Pointer(pointer),
+                Value::Pointer {
+                    target: Some(target),
+                    ..
+                },
+            ) => {
+                self.validate_type(target, &pointer.target, constraints, path, out);
+            }
+            (Type::Enum(_), Value::Enum { raw, name: None }) => out.push(Violation {
+                path: path.to_string(),
+                message: format!("{raw} is not a known {} value", ty.name(self)),
+            }),
+            _ => {
+                let Some(scalar) = Scalar::of(value) else {
+                    return;
+                };
+                for constraint in constraints {
+                    if !constraint.allows(scalar) {
+                        out.push(Violation {
+                            path: path.to_string(),
+                            message: format!("{value} is outside of {constraint}"),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// Lists the floats in an instance of the layout `id` that change when
+    /// encoded, such as 0.1 in an `f16` field. These still encode, the
+    /// messages are warnings for values entered by hand.
+    pub fn precision_loss(&self, value: &Value, id: LayoutId) -> Vec<Violation> {
+        let mut warnings = Vec::new();
+        self.precision_loss_type(value, &Type::Struct(id), "", &mut warnings);
+        warnings
+    }
+
+    fn precision_loss_type(&self, value: &Value, ty: &Type, path: &str, out: &mut Vec<Violation>) {
+        match (ty, value) {
+            (Type::Struct(id), _) => {
+                let Some(layout) = self.get_type(*id) else {
+                    return;
+                };
+                for field in &layout.fields {
+                    if let Some(value) = value.field(&field.name) {
+                        let path = field_path(path, &field.name);
+                        self.precision_loss_type(value, &field.ty, &path, out);
+                    }
+                }
+            }
+            (Type::Array(element, _), Value::Array(elements)) => {
+                for (i, value) in elements.iter().enumerate() {
+                    self.precision_loss_type(value, element, &format!("{path}[{i}]"), out);
+                }
+            }
+            (
+                Type::Pointer(pointer),
+                Value::Pointer {
+                    target: Some(target),
+                    ..
+                },
+            ) => {
+                self.precision_loss_type(target, &pointer.target, path, out);
+            }
+            (_, Value::F16(v) | Value::BF16(v) | Value::F32(v)) => {
+                let Some(stored) = ty.round_float(*v) else {
+                    return;
+                };
+                // NaNs stay NaN, whatever their payload.
+                if stored != *v && !(stored.is_nan() && v.is_nan()) {
+                    out.push(Violation {
+                        path: path.to_string(),
+                        message: format!("{v} is stored as {stored}"),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The violations of the `A` of `source` holding `fields`.
+    fn violations(source: &str, fields: Vec<(&str, Value)>) -> Vec<String> {
+        let expr = Expr::parse(source).unwrap();
+        let id = expr.get_id("A").unwrap();
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        let value = Value::Struct { fields };
+        expr.validate(&value, id)
+            .iter()
+            .map(Violation::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn ranges_sets_and_assertions() {
+        let source = "struct A {
+            #[range(0, 360)] heading: u16,
+            #[one_of(1, 2, 4)] mode: u8,
+            min: u8, max: u8,
+            assert(min <= max)
+        }";
+        let valid = vec![
+            ("heading", Value::U16(360)),
+            ("mode", Value::U8(4)),
+            ("min", Value::U8(1)),
+            ("max", Value::U8(1)),
+        ];
+        assert!(violations(source, valid).is_empty());
+        let invalid = vec![
+            ("heading", Value::U16(361)),
+            ("mode", Value::U8(3)),
+            ("min", Value::U8(2)),
+            ("max", Value::U8(1)),
+        ];
+        assert_eq!(
+            violations(source, invalid),
+            [
+                "heading: 361 is outside of range(0, 360)",
+                "mode: 3 is outside of one_of(1, 2, 4)",
+                "assertion failed: min <= max",
+            ]
+        );
+    }
+
+    #[test]
+    fn constraints_apply_to_each_element() {
+        let source = "struct A { #[range(0, 9)] digits: [u8; 2] }";
+        let digits = Value::Array(vec![Value::U8(1), Value::U8(10)]);
+        assert_eq!(
+            violations(source, vec![("digits", digits)]),
+            ["digits[1]: 10 is outside of range(0, 9)"]
+        );
+    }
+
+    #[test]
+    fn precision_loss() {
+        let expr = Expr::parse("struct A { a: f16, b: bf16, c: f32, d: f64, e: f16 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let value = Value::Struct {
+            fields: vec![
+                ("a".into(), Value::F16(0.1)),
+                ("b".into(), Value::BF16(1.5)),
+                ("c".into(), Value::F32(0.5)),
+                ("d".into(), Value::F64(0.1)),
+                ("e".into(), Value::F16(70000.0)),
+            ],
+        };
+        let warnings = expr.precision_loss(&value, id);
+        let warnings = warnings
+            .iter()
+            .map(Violation::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            [
+                "a: 0.1 is stored as 0.0999755859375",
+                "e: 70000 is stored as inf",
+            ]
+        );
+        let bytes = value.encode_value(&expr, id).unwrap();
+        assert_eq!(bytes[..2], [0x66, 0x2E]);
+        assert_eq!(bytes[2..4], [0xC0, 0x3F]);
+        assert_eq!(bytes[bytes.len() - 2..], [0x00, 0x7C]);
+    }
+}
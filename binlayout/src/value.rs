@@ -1,29 +1,217 @@
+use std::collections::{HashMap, VecDeque};
 use std::io;
 use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::{Deref, DerefMut};
 
 use std::fmt;
 
-use crate::epxr::{Expr, LayoutId, Type};
+use half::{bf16, f16};
+use thiserror::Error;
 
+use crate::address::{Mac, Uuid};
+use crate::bits::BitWriter;
+use crate::epxr::{EnumId, Expr, LayoutId, Size, Struct, Type};
+use crate::expression::{EvalError, Scalar};
+use crate::fixed;
+use crate::length::{Length, LengthUnit};
+use crate::parser::Literal;
+use crate::pointer::{Base, Regions};
+use crate::scaling::{ScaleError, Scaling};
+use crate::time::{self, Epoch, Unit};
+use crate::utils::{self, field_path};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
-    Struct { fields: Vec<(String, Value)> },
+    Struct {
+        fields: Vec<(String, Value)>,
+    },
+    Bool(bool),
     I8(i64),
     I16(i64),
     I32(i64),
     I64(i64),
+    U8(u64),
+    U16(u64),
+    U32(u64),
+    U64(u64),
+    F16(f64),
+    BF16(f64),
     F32(f64),
     F64(f64),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    Mac(Mac),
+    Uuid(Uuid),
+    /// Opaque bytes, carried as they are.
+    Bytes(Vec<u8>),
+    Array(Vec<Value>),
+    /// A value of an enum type, stored as its integer `raw` value. `name` is
+    /// `None` for values missing from the enum's table.
+    Enum {
+        raw: Box<Value>,
+        name: Option<String>,
+    },
+    /// A fixed-point number, `raw / 2^frac_bits`.
+    Fixed {
+        raw: i128,
+        frac_bits: u32,
+    },
+    /// A value of a field with `#[scale]`/`#[offset]`, in engineering units.
+    /// `raw` is the number it is stored as.
+    Scaled {
+        raw: Box<Value>,
+        value: f64,
+        unit: Option<String>,
+    },
+    /// The value a pointer points to, or `None` for a null pointer. `offset`
+    /// is the one it was read from, or `None` to have the encoder place the
+    /// value.
+    Pointer {
+        offset: Option<i64>,
+        target: Option<Box<Value>>,
+    },
+    /// A point in time, `raw` `unit`s after `epoch`.
+    Timestamp {
+        raw: i128,
+        epoch: Epoch,
+        unit: Unit,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum EncodeError {
+    #[error("Unknown layout {0:?}")]
+    UnknownLayout(LayoutId),
+    #[error("Unknown enum {0:?}")]
+    UnknownEnum(EnumId),
+    #[error("{0}: missing value")]
+    MissingField(String),
+    #[error("{path}: expected a value of type {expected}")]
+    TypeMismatch { path: String, expected: String },
+    #[error("{path}: {value} does not fit in {ty}")]
+    OutOfRange {
+        path: String,
+        value: String,
+        ty: String,
+    },
+    #[error("{path}: expected {expected} elements, found {found}")]
+    LengthMismatch {
+        path: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{path}: expected constant {expected}, found {found}")]
+    ConstMismatch {
+        path: String,
+        expected: String,
+        found: String,
+    },
+    #[error("{path}: {error}")]
+    Scale { path: String, error: ScaleError },
+    #[error("{path}: cannot evaluate condition: {error}")]
+    Condition { path: String, error: EvalError },
+    #[error("{path}: present although {condition} does not hold")]
+    UnexpectedField { path: String, condition: String },
+    #[error("{path}: {ty} has no value named {name}")]
+    UnknownName {
+        path: String,
+        name: String,
+        ty: String,
+    },
+    #[error("{path}: cannot evaluate offset: {error}")]
+    Location { path: String, error: EvalError },
+    #[error("{path}: cannot evaluate size: {error}")]
+    Size { path: String, error: EvalError },
+    #[error("{path}: cannot evaluate value: {error}")]
+    Derived { path: String, error: EvalError },
+    #[error("{path}: expected {expected} elements, found {found}")]
+    SizeMismatch {
+        path: String,
+        expected: i128,
+        found: usize,
+    },
+    #[error("{path}: offset {offset} is before the start of the buffer")]
+    InvalidOffset { path: String, offset: i128 },
+    #[error("{path}: overlaps {other}")]
+    Overlap { path: String, other: String },
+    #[error("{path}: expected one member of the union, found {found}")]
+    UnionMembers { path: String, found: usize },
+    #[error("{path}: {ty} has no member {name}")]
+    UnknownMember {
+        path: String,
+        name: String,
+        ty: String,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ValueParseError {
+    #[error("{text:?} is not a valid {ty} value")]
+    Invalid { text: String, ty: String },
+    #[error("{0} values cannot be parsed from text")]
+    NotScalar(String),
+    #[error(transparent)]
+    Scale(#[from] ScaleError),
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Value::Bool(v) => write!(f, "{}", v),
             Value::I8(v) => write!(f, "{}", v),
             Value::I16(v) => write!(f, "{}", v),
             Value::I32(v) => write!(f, "{}", v),
             Value::I64(v) => write!(f, "{}", v),
+            Value::U8(v) => write!(f, "{}", v),
+            Value::U16(v) => write!(f, "{}", v),
+            Value::U32(v) => write!(f, "{}", v),
+            Value::U64(v) => write!(f, "{}", v),
+            Value::F16(v) => write!(f, "{:.6}", v),
+            Value::BF16(v) => write!(f, "{:.6}", v),
             Value::F32(v) => write!(f, "{:.6}", v), // Limits float precision for readability
             Value::F64(v) => write!(f, "{:.6}", v),
+            Value::Ipv4(v) => write!(f, "{}", v),
+            Value::Ipv6(v) => write!(f, "{}", v),
+            Value::Mac(v) => write!(f, "{}", v),
+            Value::Uuid(v) => write!(f, "{}", v),
+            Value::Bytes(v) => write!(f, "{}", utils::as_hex(v)),
+            Value::Enum { raw, name } => match name {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "{}", raw),
+            },
+            Value::Fixed { raw, frac_bits } => write!(f, "{}", fixed::decimal(*raw, *frac_bits)),
+            Value::Scaled {
+                value,
+                unit: Some(unit),
+                ..
+            } => write!(f, "{:.6} {}", value, unit),
+            Value::Scaled { value, .. } => write!(f, "{:.6}", value),
+            Value::Timestamp { raw, epoch, unit } => {
+                write!(
+                    f,
+                    "{}",
+                    time::format(time::nanos(*raw, *epoch, *unit), unit.digits())
+                )
+            }
+            Value::Pointer { target: None, .. } => write!(f, "null"),
+            Value::Pointer {
+                offset,
+                target: Some(target),
+            } => match offset {
+                Some(offset) => write!(f, "@{:#x} {}", offset, target),
+                None => write!(f, "{}", target),
+            },
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
             Value::Struct { fields } => {
                 writeln!(f, "{{")?;
                 for (name, value) in fields {
@@ -41,76 +229,789 @@ impl fmt::Display for Value {
 }
 
 impl Value {
-    pub fn size(&self) -> usize {
+    /// Looks up a field of a struct value by name.
+    pub fn field(&self, name: &str) -> Option<&Value> {
         match self {
-            Value::I8(_) => mem::size_of::<i8>(),
-            Value::I16(_) => mem::size_of::<i16>(),
-            Value::I32(_) => mem::size_of::<i32>(),
-            Value::I64(_) => mem::size_of::<i64>(),
-            Value::F32(_) => mem::size_of::<f32>(),
-            Value::F64(_) => mem::size_of::<f64>(),
-            Value::Struct { fields } => {
-                // Ensure a consistent order by sorting the field names.
-                fields.iter().map(|(_, val)| val.size()).sum()
-            }
+            Value::Struct { fields } => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
         }
     }
 
-    /// Write the byte representation of this value into the provided buffer.
-    /// Returns the number of bytes written.
-    fn write_into(&self, buf: &mut [u8]) -> usize {
-        let mut offset = 0;
+    /// The value as it is stored, with floats rounded to their precision,
+    /// so that it compares equal to the value read back.
+    pub fn rounded(&self) -> Value {
         match self {
-            Value::I8(val) => {
-                let v = *val as i8;
-                let bytes = v.to_le_bytes();
-                buf[..bytes.len()].copy_from_slice(&bytes);
-                offset += bytes.len();
-            }
-            Value::I16(val) => {
-                let v = *val as i16;
-                let bytes = v.to_le_bytes();
-                buf[..bytes.len()].copy_from_slice(&bytes);
-                offset += bytes.len();
-            }
-            Value::I32(val) => {
-                let v = *val as i32;
-                let bytes = v.to_le_bytes();
-                buf[..bytes.len()].copy_from_slice(&bytes);
-                offset += bytes.len();
-            }
-            Value::I64(val) => {
-                let bytes = val.to_le_bytes();
-                buf[..bytes.len()].copy_from_slice(&bytes);
-                offset += bytes.len();
-            }
-            Value::F32(val) => {
+            Value::F16(v) => Value::F16(Type::F16.round_float(*v).unwrap()),
+            Value::BF16(v) => Value::BF16(Type::BF16.round_float(*v).unwrap()),
+            Value::F32(v) => Value::F32(Type::F32.round_float(*v).unwrap()),
+            Value::Array(elements) => Value::Array(elements.iter().map(Value::rounded).collect()),
+            value => value.clone(),
+        }
+    }
+
+    /// Write the byte representation of this value, laid out as `ty`, to `out`.
+    fn write_into(
+        &self,
+        expr: &Expr,
+        ty: &Type,
+        path: &str,
+        out: &mut Output<'_>,
+    ) -> Result<(), EncodeError> {
+        let out_of_range = || EncodeError::OutOfRange {
+            path: path.to_string(),
+            value: self.to_string(),
+            ty: ty.name(expr).into_owned(),
+        };
+        if out.bits.is_some()
+            && let Some(width) = ty.bits()
+        {
+            let raw = self.to_bits(expr, ty, path)?;
+            if let Some(writer) = &mut out.bits {
+                writer.write(&mut out.bytes, width, raw);
+            }
+            return Ok(());
+        }
+        match (ty, self) {
+            (Type::UVarInt, Value::U64(val)) => write_leb128(*val, out),
+            (Type::VarInt, Value::I64(val)) => {
+                let mut val = *val;
+                loop {
+                    let byte = (val & 0x7F) as u8;
+                    val >>= 7;
+                    // Done once the rest is all sign bits, including the
+                    // sign bit of this byte.
+                    if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+                        out.push(byte);
+                        break;
+                    }
+                    out.push(byte | 0x80);
+                }
+            }
+            (Type::ZigZag, Value::I64(val)) => write_leb128(((val << 1) ^ (val >> 63)) as u64, out),
+            (Type::I8, Value::I8(val)) => {
+                let v = i8::try_from(*val).map_err(|_| out_of_range())?;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            (Type::I16, Value::I16(val)) => {
+                let v = i16::try_from(*val).map_err(|_| out_of_range())?;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            (Type::I32, Value::I32(val)) => {
+                let v = i32::try_from(*val).map_err(|_| out_of_range())?;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            (Type::I64, Value::I64(val)) => {
+                out.extend_from_slice(&val.to_le_bytes());
+            }
+            (Type::U8, Value::U8(val)) => {
+                let v = u8::try_from(*val).map_err(|_| out_of_range())?;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            (Type::U16, Value::U16(val)) => {
+                let v = u16::try_from(*val).map_err(|_| out_of_range())?;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            (Type::U32, Value::U32(val)) => {
+                let v = u32::try_from(*val).map_err(|_| out_of_range())?;
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            (Type::U64, Value::U64(val)) => {
+                out.extend_from_slice(&val.to_le_bytes());
+            }
+            // Whole bytes, outside of #[bits] layouts.
+            (Type::Bits { width, .. }, _) => {
+                let raw = self.to_bits(expr, ty, path)?;
+                out.extend_from_slice(&raw.to_le_bytes()[..*width as usize / 8]);
+            }
+            (Type::F16, Value::F16(val)) => {
+                out.extend_from_slice(&f16::from_f64(*val).to_le_bytes());
+            }
+            (Type::BF16, Value::BF16(val)) => {
+                out.extend_from_slice(&bf16::from_f64(*val).to_le_bytes());
+            }
+            (Type::F32, Value::F32(val)) => {
                 let v = *val as f32;
-                let bytes = v.to_le_bytes();
-                buf[..bytes.len()].copy_from_slice(&bytes);
-                offset += bytes.len();
+                out.extend_from_slice(&v.to_le_bytes());
             }
-            Value::F64(val) => {
-                let bytes = val.to_le_bytes();
-                buf[..bytes.len()].copy_from_slice(&bytes);
-                offset += bytes.len();
+            (Type::F64, Value::F64(val)) => {
+                out.extend_from_slice(&val.to_le_bytes());
             }
-            Value::Struct { fields } => {
-                for (_, value) in fields {
-                    let written = value.write_into(&mut buf[offset..]);
-                    offset += written;
+            (Type::Ipv4, Value::Ipv4(val)) => out.extend_from_slice(&val.octets()),
+            (Type::Ipv6, Value::Ipv6(val)) => out.extend_from_slice(&val.octets()),
+            (Type::Mac, Value::Mac(val)) => out.extend_from_slice(&val.0),
+            (Type::Uuid, Value::Uuid(val)) => out.extend_from_slice(&val.0),
+            (Type::Bytes(len), Value::Bytes(bytes)) => {
+                if let Some(len) = len
+                    && bytes.len() != *len
+                {
+                    return Err(EncodeError::SizeMismatch {
+                        path: path.to_string(),
+                        expected: *len as i128,
+                        found: bytes.len(),
+                    });
                 }
+                out.extend_from_slice(bytes);
+            }
+            (Type::Bool(storage), Value::Bool(val)) => {
+                let raw = Literal::Int(*val as i128).to_value(expr, storage);
+                raw.ok_or_else(out_of_range)?
+                    .write_into(expr, storage, path, out)?;
+            }
+            (Type::Fixed(fixed), Value::Fixed { raw, frac_bits }) => {
+                // Rescale values of another format, rounding to nearest.
+                let raw = if *frac_bits == fixed.frac_bits {
+                    Some(*raw)
+                } else {
+                    fixed.raw(fixed::to_f64(*raw, *frac_bits))
+                };
+                let storage = fixed.storage();
+                raw.and_then(|raw| Literal::Int(raw).to_value(expr, &storage))
+                    .ok_or_else(out_of_range)?
+                    .write_into(expr, &storage, path, out)?;
+            }
+            (Type::Fixed(fixed), Value::F32(val) | Value::F64(val)) => {
+                let storage = fixed.storage();
+                fixed
+                    .raw(*val)
+                    .and_then(|raw| Literal::Int(raw).to_value(expr, &storage))
+                    .ok_or_else(out_of_range)?
+                    .write_into(expr, &storage, path, out)?;
+            }
+            (Type::Timestamp(timestamp), Value::Timestamp { raw, epoch, unit }) => {
+                // Convert times read as another timestamp type.
+                let raw = if (*epoch, *unit) == (timestamp.epoch, timestamp.unit) {
+                    Some(*raw)
+                } else {
+                    timestamp.raw(time::nanos(*raw, *epoch, *unit))
+                };
+                raw.and_then(|raw| Literal::Int(raw).to_value(expr, &timestamp.storage))
+                    .ok_or_else(out_of_range)?
+                    .write_into(expr, &timestamp.storage, path, out)?;
+            }
+            (Type::Enum(id), Value::Enum { raw, name }) => {
+                let enumeration = expr.enums.get(id).ok_or(EncodeError::UnknownEnum(*id))?;
+                let Some(name) = name else {
+                    return raw.write_into(expr, &enumeration.storage, path, out);
+                };
+                // The name decides the value, the raw one may be stale.
+                let value = enumeration
+                    .value_of(name)
+                    .and_then(|value| expr.enum_value(*id, value))
+                    .ok_or_else(|| EncodeError::UnknownName {
+                        path: path.to_string(),
+                        name: name.clone(),
+                        ty: ty.name(expr).into_owned(),
+                    })?;
+                let Value::Enum { raw, .. } = value else {
+                    unreachable!()
+                };
+                raw.write_into(expr, &enumeration.storage, path, out)?;
+            }
+            (Type::Struct(id), Value::Struct { .. }) => {
+                let layout = expr.get_type(*id).ok_or(EncodeError::UnknownLayout(*id))?;
+                if layout.union {
+                    return self.write_union(expr, *id, path, out);
+                }
+                let start = out.origin + out.len();
+                let outer_base = mem::replace(&mut out.base, start);
+                // `#[bits]` layouts start on a byte, unless they are part of
+                // another.
+                let outer_bits = out.bits;
+                if let Some(order) = layout.bits {
+                    let position = out.bits.map_or(out.len() * 8, |writer| writer.position);
+                    out.bits = Some(BitWriter { order, position });
+                }
+                // The fields written so far, as conditions and offsets see them.
+                let scoped = layout.fields.iter().any(|field| {
+                    field.condition.is_some()
+                        || field.location.is_some()
+                        || field.size.is_some()
+                        || field.derived.is_some()
+                });
+                let mut scope = Vec::new();
+                // Where each field starts, for the ranges checksums cover.
+                let mut bounds = Vec::with_capacity(layout.fields.len() + 1);
+                let mut checksums = Vec::new();
+                let mut lengths = Vec::new();
+                for (index, field) in layout.fields.iter().enumerate() {
+                    bounds.push(out.len());
+                    let path = field_path(path, &field.name);
+                    // Padding is always written with its fill.
+                    let value = match field.padding {
+                        true => None,
+                        false => self.field(&field.name),
+                    };
+                    if let Some(condition) = &field.condition {
+                        let present = condition.check_fields(&scope).map_err(|error| {
+                            EncodeError::Condition {
+                                path: path.clone(),
+                                error,
+                            }
+                        })?;
+                        match (present, value) {
+                            (false, Some(_)) => {
+                                return Err(EncodeError::UnexpectedField {
+                                    path,
+                                    condition: condition.to_string(),
+                                });
+                            }
+                            (false, None) => continue,
+                            (true, _) => {}
+                        }
+                    }
+                    if let Some(checksum) = &field.checksum {
+                        // Zeroed for now, filled in once the fields it covers
+                        // are written. A given value is ignored.
+                        let position = out.len();
+                        checksums.push((index, position));
+                        out.resize(position + checksum.width as usize / 8, 0);
+                        continue;
+                    }
+                    if let Some(length) = &field.length {
+                        // Likewise filled in at the end, lengths are fixed-size
+                        // integers.
+                        let size = expr.size_of_type(&field.ty).and_then(Size::fixed);
+                        let position = out.len();
+                        lengths.push((index, position));
+                        out.resize(position + size.unwrap_or(0), 0);
+                        // The fields after it may be sized by it, so it is
+                        // measured ahead of them when it can be.
+                        if scoped
+                            && let Some(value) = self.measure_ahead(expr, layout, length, &scope)
+                            && let Some(value) = Literal::Int(value).to_value(expr, &field.ty)
+                        {
+                            scope.push((field.name.clone(), value));
+                        }
+                        continue;
+                    }
+                    if let Some(derived) = &field.derived {
+                        // Computed from the fields before it, a given value is
+                        // ignored.
+                        let value = expr.derive(&field.ty, derived, &scope).map_err(|error| {
+                            EncodeError::Derived {
+                                path: path.clone(),
+                                error,
+                            }
+                        })?;
+                        value.write_into(expr, &field.ty, &path, out)?;
+                        if scoped {
+                            scope.push((field.name.clone(), value));
+                        }
+                        continue;
+                    }
+                    let value = match (&field.constant, value) {
+                        (Some(constant), Some(value)) if value.rounded() != *constant => {
+                            return Err(EncodeError::ConstMismatch {
+                                path,
+                                expected: constant.to_string(),
+                                found: value.to_string(),
+                            });
+                        }
+                        // Constants and defaults are filled in when the value
+                        // leaves them out.
+                        (Some(constant), _) => constant,
+                        (None, Some(value)) => value,
+                        (None, None) => match &field.default {
+                            Some(default) => default,
+                            None => return Err(EncodeError::MissingField(path)),
+                        },
+                    };
+                    let raw = match &field.scaling {
+                        Some(scaling) => &value.unscale(expr, &field.ty, scaling, &path)?,
+                        None => value,
+                    };
+                    // `bytes` and arrays sized by the fields before them.
+                    let sized;
+                    let ty = match &field.size {
+                        Some(size) => {
+                            let size =
+                                size.eval_fields(&scope)
+                                    .map_err(|error| EncodeError::Size {
+                                        path: path.clone(),
+                                        error,
+                                    })?;
+                            let Scalar::Int(size) = size else {
+                                unreachable!("sizes are type checked as integers")
+                            };
+                            let len =
+                                usize::try_from(size).map_err(|_| EncodeError::SizeMismatch {
+                                    path: path.clone(),
+                                    expected: size,
+                                    found: match raw {
+                                        Value::Bytes(bytes) => bytes.len(),
+                                        Value::Array(elements) => elements.len(),
+                                        _ => 0,
+                                    },
+                                })?;
+                            sized = field.ty.sized(len);
+                            &sized
+                        }
+                        None => &field.ty,
+                    };
+                    // Padding declared without a fill is as many zeros.
+                    let zeros;
+                    let raw = match ty {
+                        Type::Bytes(Some(len)) if field.padding && field.constant.is_none() => {
+                            zeros = Value::Bytes(vec![0; *len]);
+                            &zeros
+                        }
+                        _ => raw,
+                    };
+                    match &field.location {
+                        // Written once the struct is, where the offset says.
+                        Some(location) => {
+                            let offset = location.offset.eval_fields(&scope).map_err(|error| {
+                                EncodeError::Location {
+                                    path: path.clone(),
+                                    error,
+                                }
+                            })?;
+                            let Scalar::Int(offset) = offset else {
+                                unreachable!("offsets are type checked as integers")
+                            };
+                            let position = out.position(location.base, offset, &path)?;
+                            out.deferred.push(Deferred {
+                                path: path.clone(),
+                                ty: ty.clone(),
+                                value: raw.clone(),
+                                position: Some(position),
+                                shared: false,
+                            });
+                        }
+                        None => raw.write_into(expr, ty, &path, out)?,
+                    }
+                    if scoped {
+                        scope.push((field.name.clone(), value.clone()));
+                    }
+                }
+                bounds.push(out.len());
+                // Lengths first, checksums may cover them.
+                for (index, position) in lengths {
+                    let field = &layout.fields[index];
+                    let length = field.length.as_ref().unwrap();
+                    let counted = &layout.fields[length.fields.start];
+                    let counted = self.field(&counted.name).or(counted.default.as_ref());
+                    let value = length.measure(&bounds, counted);
+                    let path = field_path(path, &field.name);
+                    let value = Literal::Int(value)
+                        .to_value(expr, &field.ty)
+                        .ok_or_else(|| EncodeError::OutOfRange {
+                            path: path.clone(),
+                            value: value.to_string(),
+                            ty: field.ty.name(expr).into_owned(),
+                        })?;
+                    let mut bytes = Output::new(0, out.placed);
+                    value.write_into(expr, &field.ty, &path, &mut bytes)?;
+                    out[position..position + bytes.len()].copy_from_slice(&bytes);
+                }
+                // Checksums covering other checksums are computed after them.
+                let checksum = |index: usize| layout.fields[index].checksum.as_ref().unwrap();
+                while !checksums.is_empty() {
+                    let ready = checksums
+                        .iter()
+                        .position(|(index, _)| {
+                            let covered = &checksum(*index).fields;
+                            !checksums.iter().any(|(other, _)| covered.contains(other))
+                        })
+                        .expect("checksum cycles are rejected when parsing");
+                    let (index, position) = checksums.remove(ready);
+                    let checksum = checksum(index);
+                    let data = &out[bounds[checksum.fields.start]..bounds[checksum.fields.end]];
+                    let value = checksum.algorithm.compute(checksum.width, data);
+                    let len = checksum.width as usize / 8;
+                    out[position..position + len].copy_from_slice(&value.to_le_bytes()[..len]);
+                }
+                if layout.bits.is_some() {
+                    let position = out.bits.map_or(0, |writer| writer.position);
+                    out.bits = outer_bits.map(|outer| BitWriter { position, ..outer });
+                }
+                out.base = outer_base;
+            }
+            (Type::Pointer(pointer), Value::Pointer { target: None, .. }) => {
+                Literal::Int(0)
+                    .to_value(expr, &pointer.storage)
+                    .expect("zero fits any integer")
+                    .write_into(expr, &pointer.storage, path, out)?;
+            }
+            (
+                Type::Pointer(pointer),
+                Value::Pointer {
+                    offset,
+                    target: Some(target),
+                },
+            ) => {
+                // Targets without an offset go after everything else, at
+                // the position the first pass picked.
+                let position = match offset {
+                    Some(offset) => Some(out.position(pointer.base, *offset as i128, path)?),
+                    None => out.placed.get(path).copied(),
+                };
+                let offset = match (position, pointer.base) {
+                    (None, _) => 0,
+                    (Some(position), Base::Buffer) => position as i128,
+                    (Some(position), Base::Struct) => position as i128 - out.base as i128,
+                };
+                Literal::Int(offset)
+                    .to_value(expr, &pointer.storage)
+                    .ok_or_else(|| EncodeError::OutOfRange {
+                        path: path.to_string(),
+                        value: offset.to_string(),
+                        ty: ty.name(expr).into_owned(),
+                    })?
+                    .write_into(expr, &pointer.storage, path, out)?;
+                out.deferred.push(Deferred {
+                    path: path.to_string(),
+                    ty: pointer.target.clone(),
+                    value: (**target).clone(),
+                    position,
+                    shared: true,
+                });
+            }
+            (Type::Array(element, len), Value::Array(elements)) => {
+                if let Some(len) = len
+                    && elements.len() != *len
+                {
+                    return Err(EncodeError::LengthMismatch {
+                        path: path.to_string(),
+                        expected: *len,
+                        found: elements.len(),
+                    });
+                }
+                for (i, value) in elements.iter().enumerate() {
+                    value.write_into(expr, element, &format!("{path}[{i}]"), out)?;
+                }
+            }
+            _ => {
+                return Err(EncodeError::TypeMismatch {
+                    path: path.to_string(),
+                    expected: ty.name(expr).into_owned(),
+                });
             }
         }
-        offset
+        Ok(())
     }
 
-    /// Format the value into a Vec<u8> with minimal allocations.
-    pub fn encode_value(&self) -> Vec<u8> {
-        let total_size = self.size();
-        let mut buf = vec![0u8; total_size];
-        self.write_into(&mut buf);
-        buf
+    /// The bits of an integer or float of type `ty`, as written to a `#[bits]`
+    /// layout.
+    fn to_bits(&self, expr: &Expr, ty: &Type, path: &str) -> Result<u64, EncodeError> {
+        let mismatch = || EncodeError::TypeMismatch {
+            path: path.to_string(),
+            expected: ty.name(expr).into_owned(),
+        };
+        Ok(match (ty, self) {
+            (Type::F16, Value::F16(v)) => f16::from_f64(*v).to_bits() as u64,
+            (Type::BF16, Value::BF16(v)) => bf16::from_f64(*v).to_bits() as u64,
+            (Type::F32, Value::F32(v)) => (*v as f32).to_bits() as u64,
+            (Type::F64, Value::F64(v)) => v.to_bits(),
+            (_, Value::Enum { .. }) => return Err(mismatch()),
+            _ => {
+                let Some(Scalar::Int(v)) = Scalar::of(self) else {
+                    return Err(mismatch());
+                };
+                // The value has to be of the type's own kind and fit its
+                // width.
+                match Literal::Int(v).to_value(expr, ty) {
+                    Some(value) if value == *self => {}
+                    Some(_) => return Err(mismatch()),
+                    None => {
+                        return Err(EncodeError::OutOfRange {
+                            path: path.to_string(),
+                            value: self.to_string(),
+                            ty: ty.name(expr).into_owned(),
+                        });
+                    }
+                }
+                // Two's complement, cut to the width by the writer.
+                v as u64
+            }
+        })
+    }
+
+    /// What the struct value measures for `length` before the fields it
+    /// covers are written, given the fields written so far. `None` if it
+    /// cannot be told yet, e.g. because a covered field's condition refers
+    /// to fields after the length.
+    fn measure_ahead(
+        &self,
+        expr: &Expr,
+        layout: &Struct,
+        length: &Length,
+        scope: &[(String, Value)],
+    ) -> Option<i128> {
+        let covered = &layout.fields[length.fields.clone()];
+        if length.unit == LengthUnit::Elements {
+            let counted = &covered[0];
+            let counted = self.field(&counted.name).or(counted.default.as_ref());
+            return Some(length.measure(&[], counted));
+        }
+        let placed = HashMap::new();
+        let mut size = 0;
+        for field in covered {
+            let value = match field.padding {
+                true => None,
+                false => self.field(&field.name),
+            };
+            if let Some(condition) = &field.condition
+                && value.is_none()
+                && !condition.check_fields(scope).ok()?
+            {
+                continue;
+            }
+            // Written elsewhere, or of the same size whatever the value.
+            if field.location.is_some() {
+                continue;
+            }
+            if let Some(fixed) = expr.size_of_type(&field.ty).and_then(Size::fixed) {
+                size += fixed;
+                continue;
+            }
+            let value = field
+                .constant
+                .as_ref()
+                .or(value)
+                .or(field.default.as_ref())?;
+            let raw = match &field.scaling {
+                Some(scaling) => value.unscale(expr, &field.ty, scaling, &field.name).ok()?,
+                None => value.clone(),
+            };
+            let mut scratch = Output::new(0, &placed);
+            raw.write_into(expr, &field.ty, &field.name, &mut scratch)
+                .ok()?;
+            size += scratch.len();
+        }
+        Some(size as i128 + length.adjust)
+    }
+
+    /// Writes the one member of a value of the union `id`, padded with zeros
+    /// to the size of the union.
+    fn write_union(
+        &self,
+        expr: &Expr,
+        id: LayoutId,
+        path: &str,
+        out: &mut Output<'_>,
+    ) -> Result<(), EncodeError> {
+        let layout = expr.get_type(id).ok_or(EncodeError::UnknownLayout(id))?;
+        let Value::Struct { fields } = self else {
+            unreachable!("unions are written from struct values")
+        };
+        let [(name, value)] = fields.as_slice() else {
+            return Err(EncodeError::UnionMembers {
+                path: path.to_string(),
+                found: fields.len(),
+            });
+        };
+        let member = layout
+            .fields
+            .iter()
+            .find(|member| member.name == *name)
+            .ok_or_else(|| EncodeError::UnknownMember {
+                path: path.to_string(),
+                name: name.clone(),
+                ty: Type::Struct(id).name(expr).into_owned(),
+            })?;
+        let path = field_path(path, name);
+        if let Some(constant) = &member.constant
+            && value.rounded() != *constant
+        {
+            return Err(EncodeError::ConstMismatch {
+                path,
+                expected: constant.to_string(),
+                found: value.to_string(),
+            });
+        }
+        let start = out.len();
+        let outer_base = mem::replace(&mut out.base, out.origin + start);
+        match &member.scaling {
+            Some(scaling) => value
+                .unscale(expr, &member.ty, scaling, &path)?
+                .write_into(expr, &member.ty, &path, out)?,
+            None => value.write_into(expr, &member.ty, &path, out)?,
+        }
+        let size = expr.size_of(id).and_then(Size::fixed).unwrap_or(0);
+        out.resize(start + size, 0);
+        out.base = outer_base;
+        Ok(())
+    }
+
+    /// Replaces the engineering values in the value of a scaled field with
+    /// the raw values they round to.
+    fn unscale(
+        &self,
+        expr: &Expr,
+        ty: &Type,
+        scaling: &Scaling,
+        path: &str,
+    ) -> Result<Value, EncodeError> {
+        Ok(match (ty, self) {
+            (Type::Array(element, _), Value::Array(elements)) => Value::Array(
+                elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        value.unscale(expr, element, scaling, &format!("{path}[{i}]"))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            (_, Value::Scaled { value, .. }) => {
+                scaling
+                    .raw(expr, ty, *value)
+                    .map_err(|error| EncodeError::Scale {
+                        path: path.to_string(),
+                        error,
+                    })?
+            }
+            // Anything else is taken to be raw already.
+            _ => self.clone(),
+        })
+    }
+
+    /// Encodes the value as an instance of the layout `id`.
+    pub fn encode_value(&self, expr: &Expr, id: LayoutId) -> Result<Vec<u8>, EncodeError> {
+        let (buf, picked) = self.encode_pass(expr, id, &HashMap::new())?;
+        if picked.is_empty() {
+            return Ok(buf);
+        }
+        // Pointers written before their targets were placed hold zeros, and
+        // so do the checksums covering them. Another pass fills them in.
+        let (buf, _) = self.encode_pass(expr, id, &picked)?;
+        Ok(buf)
+    }
+
+    /// Encodes the value and everything it points to, with pointers to the
+    /// targets in `placed` set to their positions. Returns the positions it
+    /// picked for targets missing from `placed`.
+    fn encode_pass(
+        &self,
+        expr: &Expr,
+        id: LayoutId,
+        placed: &HashMap<String, usize>,
+    ) -> Result<(Vec<u8>, HashMap<String, usize>), EncodeError> {
+        let mut out = Output::new(0, placed);
+        out.reserve(expr.size_of(id).map_or(0, Size::min));
+        self.write_into(expr, &Type::Struct(id), "", &mut out)?;
+        let mut regions = Regions::default();
+        let name = Type::Struct(id).name(expr).into_owned();
+        regions
+            .claim(0..out.len(), &name, false)
+            .expect("nothing else is written yet");
+        let Output {
+            mut bytes,
+            deferred,
+            ..
+        } = out;
+        let mut queue = VecDeque::from(deferred);
+        let mut picked = HashMap::new();
+        while let Some(deferred) = queue.pop_front() {
+            let position = deferred.position.unwrap_or_else(|| {
+                picked.insert(deferred.path.clone(), bytes.len());
+                bytes.len()
+            });
+            let mut target = Output::new(position, placed);
+            deferred
+                .value
+                .write_into(expr, &deferred.ty, &deferred.path, &mut target)?;
+            let end = position + target.len();
+            regions
+                .claim(position..end, &deferred.path, deferred.shared)
+                .map_err(|other| EncodeError::Overlap {
+                    path: deferred.path.clone(),
+                    other,
+                })?;
+            if bytes.len() < end {
+                bytes.resize(end, 0);
+            }
+            bytes[position..end].copy_from_slice(&target);
+            queue.extend(target.deferred);
+        }
+        Ok((bytes, picked))
+    }
+
+    /// Parses a primitive or enum value of type `ty` from text, as typed into
+    /// a form or prompt. Integers may be written in hex (`0x1F`), enums by
+    /// name or by number, bools as `true`/`false` or 1/0, fixed-point numbers
+    /// as exact decimals, timestamps in ISO 8601 or as the count they are
+    /// stored as, addresses and UUIDs in their usual notation and bytes in
+    /// hex or base64. Text that is valid as both is read as hex, unless it is
+    /// prefixed with `base64:`.
+    pub fn from_text(expr: &Expr, ty: &Type, text: &str) -> Result<Value, ValueParseError> {
+        let text = text.trim();
+        let invalid = || ValueParseError::Invalid {
+            text: text.to_string(),
+            ty: ty.name(expr).into_owned(),
+        };
+        match ty {
+            Type::Struct(_) | Type::Array(..) | Type::Pointer(_) => {
+                return Err(ValueParseError::NotScalar(ty.name(expr).into_owned()));
+            }
+            Type::Enum(id) => {
+                if let Some(value) = expr.enums.get(id).and_then(|e| e.value_of(text)) {
+                    return expr.enum_value(*id, value).ok_or_else(invalid);
+                }
+            }
+            Type::Bool(_) => {
+                return match text {
+                    "true" | "1" => Ok(Value::Bool(true)),
+                    "false" | "0" => Ok(Value::Bool(false)),
+                    _ => Err(invalid()),
+                };
+            }
+            Type::Ipv4 => return text.parse().map(Value::Ipv4).map_err(|_| invalid()),
+            Type::Ipv6 => return text.parse().map(Value::Ipv6).map_err(|_| invalid()),
+            Type::Mac => return Mac::parse(text).map(Value::Mac).ok_or_else(invalid),
+            Type::Uuid => return Uuid::parse(text).map(Value::Uuid).ok_or_else(invalid),
+            Type::Bytes(_) => {
+                let bytes = match text.strip_prefix("base64:") {
+                    Some(text) => utils::from_base64(text),
+                    None => utils::from_hex(text).or_else(|| utils::from_base64(text)),
+                };
+                return bytes.map(Value::Bytes).ok_or_else(invalid);
+            }
+            Type::Fixed(fixed) => {
+                if let Some(raw) = fixed.parse_decimal(text) {
+                    return Ok(Value::Fixed {
+                        raw,
+                        frac_bits: fixed.frac_bits,
+                    });
+                }
+            }
+            Type::Timestamp(timestamp) => {
+                if let Some(nanos) = time::parse(text) {
+                    return timestamp
+                        .raw(nanos)
+                        .and_then(|raw| Literal::Int(raw).to_value(expr, ty))
+                        .ok_or_else(invalid);
+                }
+            }
+            _ => {}
+        }
+        parse_number(text)
+            .and_then(|literal| literal.to_value(expr, ty))
+            .ok_or_else(invalid)
+    }
+
+    /// Parses an engineering value of a field of type `ty` with `scaling`,
+    /// converting it to the raw value it rounds to.
+    pub fn from_scaled_text(
+        expr: &Expr,
+        ty: &Type,
+        scaling: &Scaling,
+        text: &str,
+    ) -> Result<Value, ValueParseError> {
+        let value = match parse_number(text.trim()) {
+            Some(Literal::Int(v)) => v as f64,
+            Some(Literal::Float(v)) => v,
+            Some(Literal::Bool(_)) | None => {
+                return Err(ValueParseError::Invalid {
+                    text: text.trim().to_string(),
+                    ty: ty.name(expr).into_owned(),
+                });
+            }
+        };
+        Ok(scaling.scaled(expr, ty, value)?)
     }
 
     pub fn prompt_for_value(expr: &Expr, id: LayoutId) -> Option<Value> {
@@ -120,51 +1021,276 @@ impl Value {
     fn prompt_for_value_helper(expr: &Expr, id: LayoutId, prefix: &str) -> Option<Value> {
         let layout = expr.layouts.get(&id)?;
         let mut values = Vec::new();
-        let mut input = String::new();
 
-        for (field_name, field_type) in &layout.fields {
-            let full_field_name = format!("{}{}", prefix, field_name);
-            let val = match field_type {
-                Type::I8 | Type::I16 | Type::I32 | Type::I64 => {
-                    println!("Enter integer value for {}:", full_field_name);
-                    input.clear();
-                    io::stdin().read_line(&mut input).ok()?;
-                    if let Ok(value) = input.trim().parse::<i64>() {
-                        match field_type {
-                            Type::I8 => Value::I8(value),
-                            Type::I16 => Value::I16(value),
-                            Type::I32 => Value::I32(value),
-                            Type::I64 => Value::I64(value),
-                            _ => unreachable!(),
-                        }
-                    } else {
-                        println!("Invalid input. Expected an integer.");
+        let mut fields = layout.fields.iter().collect::<Vec<_>>();
+        if layout.union {
+            // Only one member of a union is entered.
+            let names = fields.iter().map(|field| field.name.as_str());
+            println!("Choose a member of {}:", prefix.trim_end_matches('.'));
+            println!("Options: {}", names.collect::<Vec<_>>().join(", "));
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).ok()?;
+            let Some(member) = fields.iter().find(|field| field.name == input.trim()) else {
+                println!("Invalid input. {} is not a member", input.trim());
+                return None;
+            };
+            fields = vec![*member];
+        }
+        for field in fields {
+            let full_field_name = format!("{}{}", prefix, field.name);
+            if let Some(condition) = &field.condition
+                && !condition.check_fields(&values).ok()?
+            {
+                continue;
+            }
+            // Computed or filled in when encoding.
+            if field.is_computed() || field.padding {
+                continue;
+            }
+            // Sized by the fields before it, if they were entered.
+            let len = field.size.as_ref().map(|size| size.eval_fields(&values));
+            let ty = match len {
+                Some(Ok(Scalar::Int(len))) => field.ty.sized(usize::try_from(len).ok()?),
+                _ => field.ty.clone(),
+            };
+            let val = match &field.constant {
+                Some(constant) => constant.clone(),
+                None => Self::prompt_for_type(expr, &ty, &full_field_name)?,
+            };
+            values.push((field.name.to_string(), val));
+        }
+        Some(Value::Struct { fields: values })
+    }
+
+    fn prompt_for_type(expr: &Expr, ty: &Type, full_field_name: &str) -> Option<Value> {
+        let mut input = String::new();
+        let val = match ty {
+            Type::Struct(inner_id) => {
+                Self::prompt_for_value_helper(expr, *inner_id, &format!("{}.", full_field_name))?
+            }
+            Type::Array(element, len) => {
+                let len = match len {
+                    Some(len) => *len,
+                    None => {
+                        println!("Enter the number of elements of {full_field_name}:");
+                        io::stdin().read_line(&mut input).ok()?;
+                        let Ok(len) = input.trim().parse() else {
+                            println!("Invalid input. {} is not a number", input.trim());
+                            return None;
+                        };
+                        len
+                    }
+                };
+                let elements = (0..len).map(|i| {
+                    Self::prompt_for_type(expr, element, &format!("{full_field_name}[{i}]"))
+                });
+                Value::Array(elements.collect::<Option<_>>()?)
+            }
+            Type::Pointer(pointer) => {
+                println!("Does {full_field_name} point to a value? (y/n)");
+                io::stdin().read_line(&mut input).ok()?;
+                let target = match input.trim() {
+                    "y" => Some(Self::prompt_for_type(
+                        expr,
+                        &pointer.target,
+                        full_field_name,
+                    )?),
+                    "n" => None,
+                    other => {
+                        println!("Invalid input. {other} is not y or n");
                         return None;
                     }
+                };
+                Value::Pointer {
+                    offset: None,
+                    target: target.map(Box::new),
                 }
-                Type::F32 | Type::F64 => {
-                    println!("Enter floating-point value for {}:", full_field_name);
-                    input.clear();
-                    io::stdin().read_line(&mut input).ok()?;
-                    if let Ok(value) = input.trim().parse::<f64>() {
-                        match field_type {
-                            Type::F32 => Value::F32(value),
-                            Type::F64 => Value::F64(value),
-                            _ => unreachable!(),
-                        }
-                    } else {
-                        println!("Invalid input. Expected a floating-point number.");
+            }
+            _ => {
+                println!("Enter {} value for {}:", ty.name(expr), full_field_name);
+                if let Type::Enum(id) = ty {
+                    let names = expr
+                        .enums
+                        .get(id)?
+                        .values
+                        .iter()
+                        .map(|(name, _)| name.as_str());
+                    println!("Options: {}", names.collect::<Vec<_>>().join(", "));
+                }
+                io::stdin().read_line(&mut input).ok()?;
+                match Self::from_text(expr, ty, &input) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        println!("Invalid input. {e}");
                         return None;
                     }
                 }
-                Type::Struct(inner_id) => Self::prompt_for_value_helper(
-                    expr,
-                    *inner_id,
-                    &format!("{}.", full_field_name),
-                )?,
-            };
-            values.push((field_name.to_string(), val));
+            }
+        };
+        Some(val)
+    }
+}
+
+/// Bytes being encoded, along with the values to be written elsewhere in
+/// the buffer once they are.
+struct Output<'a> {
+    bytes: Vec<u8>,
+    /// Where `bytes` goes in the buffer.
+    origin: usize,
+    /// Where the innermost struct being written starts in the buffer.
+    base: usize,
+    deferred: Vec<Deferred>,
+    /// Positions picked for pointer targets by an earlier pass, by path.
+    placed: &'a HashMap<String, usize>,
+    /// Set while writing a `#[bits]` layout, integers and floats are then
+    /// written to it instead of as whole bytes.
+    bits: Option<BitWriter>,
+}
+
+/// A located field or pointer target, written after the bytes around it.
+struct Deferred {
+    path: String,
+    ty: Type,
+    value: Value,
+    /// Where it goes in the buffer, `None` for after everything else.
+    position: Option<usize>,
+    /// Whether other pointers may point to it too.
+    shared: bool,
+}
+
+impl<'a> Output<'a> {
+    fn new(origin: usize, placed: &'a HashMap<String, usize>) -> Self {
+        Output {
+            bytes: Vec::new(),
+            origin,
+            base: origin,
+            deferred: Vec::new(),
+            placed,
+            bits: None,
         }
-        Some(Value::Struct { fields: values })
+    }
+
+    /// Turns an offset from `base` into a position in the buffer.
+    fn position(&self, base: Base, offset: i128, path: &str) -> Result<usize, EncodeError> {
+        let base = match base {
+            Base::Buffer => 0,
+            Base::Struct => self.base,
+        };
+        let position = base as i128 + offset;
+        usize::try_from(position).map_err(|_| EncodeError::InvalidOffset {
+            path: path.to_string(),
+            offset: position,
+        })
+    }
+}
+
+impl Deref for Output<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.bytes
+    }
+}
+
+impl DerefMut for Output<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+}
+
+/// Writes `value` as unsigned LEB128.
+fn write_leb128(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Parses a decimal or `0x` prefixed hex integer, or a float.
+fn parse_number(text: &str) -> Option<Literal> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    // The integer parsers take a sign of their own, which must not follow the first.
+    if digits.starts_with(['+', '-']) {
+        return None;
+    }
+    let int = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(hex) if hex.starts_with(['+', '-']) => return None,
+        Some(hex) => i128::from_str_radix(hex, 16).ok(),
+        None => digits.parse::<i128>().ok(),
+    };
+    match int {
+        Some(v) => Some(Literal::Int(if negative { -v } else { v })),
+        None => text.parse().ok().map(Literal::Float),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_take_at_most_one_sign() {
+        let expr = Expr::parse("struct A { a: u8 }").unwrap();
+        assert_eq!(
+            Value::from_text(&expr, &Type::I8, "-5").unwrap(),
+            Value::I8(-5)
+        );
+        assert_eq!(
+            Value::from_text(&expr, &Type::U8, "+0x1f").unwrap(),
+            Value::U8(0x1f)
+        );
+        assert_eq!(
+            Value::from_text(&expr, &Type::F32, "-1e-3").unwrap(),
+            Value::F32(-1e-3)
+        );
+        for text in ["--5", "-0x-5", "-+5", "+-5", "0x+5"] {
+            for ty in [Type::U8, Type::I8] {
+                let error = Value::from_text(&expr, &ty, text).unwrap_err();
+                assert!(matches!(error, ValueParseError::Invalid { .. }), "{text}");
+            }
+        }
+    }
+
+    #[test]
+    fn unions_are_written_from_one_member() {
+        let expr = Expr::parse("union U { a: u32, b: [u8; 2] } struct A { u: U, t: u8 }").unwrap();
+        let id = expr.get_id("A").unwrap();
+        let union = |members: Vec<(&str, Value)>| Value::Struct {
+            fields: vec![
+                (
+                    "u".into(),
+                    Value::Struct {
+                        fields: members
+                            .into_iter()
+                            .map(|(name, v)| (name.to_string(), v))
+                            .collect(),
+                    },
+                ),
+                ("t".into(), Value::U8(9)),
+            ],
+        };
+        // The union takes the size of its largest member.
+        let value = union(vec![("b", Value::Array(vec![Value::U8(1), Value::U8(2)]))]);
+        assert_eq!(value.encode_value(&expr, id).unwrap(), [1, 2, 0, 0, 9]);
+        // Decoding reads every member.
+        let decoded = expr.read_value(&[1, 2, 0, 0, 9], id).unwrap();
+        assert_eq!(
+            decoded.field("u").and_then(|u| u.field("a")),
+            Some(&Value::U32(0x0201))
+        );
+
+        let value = union(vec![
+            ("a", Value::U32(1)),
+            ("b", Value::Array(vec![Value::U8(1); 2])),
+        ]);
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(error, EncodeError::UnionMembers { found: 2, .. }));
+        let value = union(vec![("c", Value::U8(1))]);
+        let error = value.encode_value(&expr, id).unwrap_err();
+        assert!(matches!(error, EncodeError::UnknownMember { name, .. } if name == "c"));
     }
 }
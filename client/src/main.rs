@@ -1,9 +1,13 @@
 use anyhow::Result;
 use binlayout::epxr::*;
+use binlayout::parser::{Item, struct_parser};
+use binlayout::resolver::{self, MemoryResolver};
+use binlayout::scaling::Scaling;
+use binlayout::validate::Violation;
 use binlayout::value::Value;
 use leptos::task::spawn_local;
 use leptos::{ev, prelude::*};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 fn main() {
     mount_to_body(|| {
@@ -36,40 +40,104 @@ fn main() {
 }
 
 async fn load_layout() -> Result<StoredValue<Expr>, leptos::error::Error> {
-    let response = gloo::net::http::Request::get("/layout.lay").send().await?;
-    let text = response.text().await?;
-    let parsed: Expr = Expr::parse(&text)?;
+    let mut resolver = fetch_layout_files("/layout.lay").await?;
+    let parsed: Expr = Expr::parse_file("/layout.lay", &mut resolver)?;
     Ok(StoredValue::new(parsed))
 }
 
+/// Fetches `entry` and every file it transitively imports, so the layout can
+/// then be parsed synchronously from memory.
+async fn fetch_layout_files(entry: &str) -> Result<MemoryResolver, leptos::error::Error> {
+    let mut resolver = MemoryResolver::new();
+    let mut pending = vec![entry.to_string()];
+    while let Some(path) = pending.pop() {
+        if resolver.contains(&path) {
+            continue;
+        }
+        let response = gloo::net::http::Request::get(&path).send().await?;
+        if !response.ok() {
+            // Leave the file out, parsing reports the missing import.
+            continue;
+        }
+        let text = response.text().await?;
+        // Files that fail to parse are reported by `Expr::parse_file` later.
+        if let Ok(items) = struct_parser::items(&text) {
+            for item in items {
+                if let Item::Import { path: import, .. } = item {
+                    pending.push(resolver::join(&path, &import));
+                }
+            }
+        }
+        resolver.insert(path, text);
+    }
+    Ok(resolver)
+}
+
 #[component]
 fn App(expr: StoredValue<Expr>) -> impl IntoView {
     let (selected_layout, set_selected_layout) = signal::<Option<String>>(None);
     let (form_data, set_form_data) = signal(HashMap::new());
     let (encoded_buffer, set_encoded_buffer) = signal::<Option<String>>(None); // Store encoded buffer
+    let (encoded_json, set_encoded_json) = signal::<Option<String>>(None);
+    let (precision_warnings, set_precision_warnings) = signal(Vec::<Violation>::new());
+
+    // Constraint violations of the current form contents, shown next to the inputs
+    let violations = Memo::new(move |_| {
+        let Some(layout_name) = selected_layout.get() else {
+            return Vec::new();
+        };
+        let expr = expr.read_value();
+        let Some(layout_id) = expr.get_id(&layout_name) else {
+            return Vec::new();
+        };
+        let layout = expr.get_type(layout_id).unwrap();
+        build_value_from_form(&form_data.get(), layout, &expr, "")
+            .map(|value| expr.validate(&value, layout_id))
+            .unwrap_or_default()
+    });
+    provide_context(violations);
+    provide_context(form_data);
 
     let on_submit = move |ev: ev::SubmitEvent| {
         ev.prevent_default();
 
+        let violation_count = violations.get_untracked().len();
+        if violation_count > 0 {
+            set_encoded_buffer.set(Some(format!(
+                "Fix {violation_count} constraint violation(s) before submitting"
+            )));
+            return;
+        }
+
         let Some(layout_name) = selected_layout.get() else {
             return;
         };
         let expr = expr.read_value();
-        let Some(layout) = expr.get_id(&layout_name).and_then(|id| expr.get_type(id)) else {
+        let Some(layout_id) = expr.get_id(&layout_name) else {
+            return;
+        };
+        let Some(layout) = expr.get_type(layout_id) else {
             return;
         };
 
-        match build_value_from_form(&form_data.get(), layout, &expr, "") {
-            Ok(value) => {
-                let encoded = value
-                    .encode_value()
+        let encoded = build_value_from_form(&form_data.get(), layout, &expr, "")
+            .and_then(|value| Ok((value.encode_value(&expr, layout_id)?, value)));
+        match encoded {
+            Ok((encoded, value)) => {
+                let encoded = encoded
                     .iter()
                     .map(|b| format!("{:02X} ", b))
                     .collect::<String>();
 
                 set_encoded_buffer.set(Some(encoded));
+                set_encoded_json.set(Some(format!("{:#}", value.to_json())));
+                set_precision_warnings.set(expr.precision_loss(&value, layout_id));
+            }
+            Err(e) => {
+                set_encoded_buffer.set(Some(e.to_string()));
+                set_encoded_json.set(None);
+                set_precision_warnings.set(Vec::new());
             }
-            Err(e) => set_encoded_buffer.set(Some(e.to_string())),
         }
     };
 
@@ -83,12 +151,20 @@ fn App(expr: StoredValue<Expr>) -> impl IntoView {
                     set_selected_layout.set(Some(event_target_value(&ev)))
                 }>
                     <option value="">"Select a Layout"</option>
-                    {expr
-                        .read_value()
-                        .layout_ids
-                        .keys()
-                        .map(|key| {
-                            view! { <option value=key.clone()>{key.clone()}</option> }
+                    {layouts_by_namespace(&expr.read_value())
+                        .into_iter()
+                        .map(|(namespace, names)| {
+                            let options = names
+                                .into_iter()
+                                .map(|(key, name)| {
+                                    view! { <option value=key>{name}</option> }
+                                })
+                                .collect::<Vec<_>>();
+                            if namespace.is_empty() {
+                                options.into_any()
+                            } else {
+                                view! { <optgroup label=namespace>{options}</optgroup> }.into_any()
+                            }
                         })
                         .collect::<Vec<_>>()}
                 </select>
@@ -98,14 +174,28 @@ fn App(expr: StoredValue<Expr>) -> impl IntoView {
                         .get()
                         .map(|layout_name| {
                             let layout_id = expr.read_value().get_id(&layout_name).unwrap();
+                            let initial = expr.read_value().default_value(layout_id).unwrap();
+                            let size = expr.read_value().size_of(layout_id);
+                            // `#[bits]` layouts may end part way into a byte.
+                            let packed = expr.read_value().get_type(layout_id).unwrap().bits.is_some();
+                            let bits = expr.read_value().bit_size_of(layout_id).filter(|_| packed);
+                            let size = size.map(|size| match bits {
+                                Some(Size::Fixed(bits)) => format!("Size: {size} ({bits} bits)"),
+                                Some(Size::Variable { min }) => {
+                                    format!("Size: {size} (at least {min} bits)")
+                                }
+                                None => format!("Size: {size}"),
+                            });
 
                             view! {
+                                <p>{size}</p>
                                 <form on:submit=on_submit>
                                     <StructBuilder
                                         struct_layout=layout_id
                                         expr=expr
                                         form_data=set_form_data
                                         prefix=StoredValue::new("".to_string())
+                                        initial=initial
                                     />
                                     <button type="submit">"Submit"</button>
                                 </form>
@@ -126,11 +216,56 @@ fn App(expr: StoredValue<Expr>) -> impl IntoView {
                             }
                         })
                 }}
+
+                // Floats that were rounded to fit their type
+                {move || {
+                    precision_warnings
+                        .get()
+                        .into_iter()
+                        .map(|warning| view! { <div class="warning">{warning.to_string()}</div> })
+                        .collect::<Vec<_>>()
+                }}
+
+                {move || {
+                    encoded_json
+                        .get()
+                        .map(|json| {
+                            view! {
+                                <div>
+                                    <h2>"JSON:"</h2>
+                                    <pre>{json}</pre>
+                                </div>
+                            }
+                        })
+                }}
             }
         }}
     }
 }
 
+/// Groups the qualified layout names by namespace, as
+/// `(namespace, [(qualified name, short name)])`, both sorted by name.
+fn layouts_by_namespace(expr: &Expr) -> BTreeMap<String, Vec<(String, String)>> {
+    let mut groups = BTreeMap::<String, Vec<(String, String)>>::new();
+    for key in expr.layout_ids.keys() {
+        // Generic instances such as `Vec3<nav::Meters>` are grouped by the
+        // struct's own namespace, not their arguments'.
+        let base = key.split('<').next().unwrap_or(key);
+        let namespace = base
+            .rsplit_once("::")
+            .map_or("", |(namespace, _)| namespace);
+        let name = key[namespace.len()..].trim_start_matches("::");
+        groups
+            .entry(namespace.to_string())
+            .or_default()
+            .push((key.clone(), name.to_string()));
+    }
+    for names in groups.values_mut() {
+        names.sort();
+    }
+    groups
+}
+
 // Recursive Struct Builder Component
 #[component]
 fn StructBuilder(
@@ -138,74 +273,634 @@ fn StructBuilder(
     expr: StoredValue<Expr>,
     form_data: WriteSignal<HashMap<String, ReadSignal<String>>>,
     prefix: StoredValue<String>,
+    initial: Value,
 ) -> impl IntoView {
+    if expr.read_value().get_type(struct_layout).unwrap().union {
+        return view! {
+            <UnionEditor union_layout=struct_layout expr=expr form_data=form_data prefix=prefix initial=initial />
+        }
+        .into_any();
+    }
+    // The value the form holds, built once for all of the conditional fields
+    // to look up whether they are present. It is left as is while some input
+    // doesn't parse.
+    let form = use_context::<ReadSignal<HashMap<String, ReadSignal<String>>>>().unwrap();
+    let built = Memo::new(move |prev: Option<&Option<Value>>| {
+        let expr = expr.read_value();
+        let layout = expr.get_type(struct_layout).unwrap();
+        match build_value_from_form(&form.get(), layout, &expr, &prefix.read_value()) {
+            Ok(value) => Some(value),
+            Err(_) => prev.cloned().flatten(),
+        }
+    });
     let views = move || {
         expr.read_value()
             .get_type(struct_layout)
             .unwrap()
             .fields
             .iter()
-            .map(|(name, ty)| {
+            // Padding is filled in by the encoder, there's nothing to edit.
+            .filter(|field| !field.padding)
+            .map(|field| {
                 let name = StoredValue::new(if !prefix.read_value().is_empty() {
-                    format!("{}.{name}", prefix.read_value())
+                    format!("{}.{}", prefix.read_value(), field.name)
                 } else {
-                    name.clone()
+                    field.name.clone()
                 });
+                let label = match &field.alias {
+                    Some(alias) => format!("{} ({alias})", name.get_value()),
+                    None => name.get_value(),
+                };
 
-                match ty {
-                    Type::Struct(inner_id) => view! {
-                        <fieldset>
-                            <legend>{name.get_value()}</legend>
-
-                            <StructBuilder
-                                struct_layout=*inner_id
-                                expr=expr
-                                form_data=form_data
-                                prefix=name
-                            />
-                        </fieldset>
+                let input = if let Some(constant) = &field.constant {
+                    view! {
+                        <div>
+                            <label>{label}</label>
+                            <input type="text" readonly value=constant.to_string() />
+                        </div>
                     }
-                    .into_any(),
-
-                    Type::I8 | Type::I16 | Type::I32 | Type::I64 => {
-                        let (value, set_value) = signal("0".to_string());
-                        form_data.write().insert(name.get_value(), value);
-
-                        view! {
-                            <div>
-                                <label>{name.get_value()}</label>
-                                <input
-                                    type="number"
-                                    step="1"
-                                    on:input=move |ev| set_value.set(event_target_value(&ev))
-                                />
-                            </div>
-                        }
-                        .into_any()
+                    .into_any()
+                } else if field.is_computed() {
+                    let layout = expr.read_value();
+                    let fields = &layout.get_type(struct_layout).unwrap().fields;
+                    let derived = match (&field.checksum, &field.length, &field.derived) {
+                        (Some(checksum), _, _) => checksum.describe(fields),
+                        (_, Some(length), _) => length.describe(fields),
+                        (_, _, Some(derived)) => derived.to_string(),
+                        (None, None, None) => unreachable!(),
+                    };
+                    view! {
+                        <div>
+                            <label>{label}</label>
+                            <input type="text" readonly value=derived />
+                            <span>"computed when encoding"</span>
+                        </div>
                     }
+                    .into_any()
+                } else {
+                    let initial = initial.field(&field.name).cloned();
+                    let initial = initial
+                        .or_else(|| expr.read_value().default_for_type(&field.ty))
+                        .unwrap();
 
-                    Type::F32 | Type::F64 => {
-                        let (value, set_value) = signal("0.0".to_string());
-                        form_data.write().insert(name.get_value(), value);
-
-                        view! {
-                            <div>
-                                <label>{name.get_value()}</label>
-                                <input
-                                    type="number"
-                                    step="any"
-                                    on:input=move |ev| set_value.set(event_target_value(&ev))
-                                />
-                            </div>
-                        }
-                        .into_any()
+                    view! {
+                        <FieldInput
+                            ty=field.ty.clone()
+                            label=label
+                            expr=expr
+                            form_data=form_data
+                            name=name
+                            initial=initial
+                            scaling=field.scaling.clone()
+                            unit=field.unit.clone()
+                        />
                     }
+                    .into_any()
+                };
+
+                if field.condition.is_none() {
+                    return input;
+                }
+                // Conditional fields are shown while the form has them.
+                let field_name = field.name.clone();
+                let visible = move || {
+                    built.with(|value| {
+                        value
+                            .as_ref()
+                            .is_none_or(|value| value.field(&field_name).is_some())
+                    })
+                };
+                view! {
+                    <div style:display=move || if visible() { "block" } else { "none" }>
+                        {input}
+                    </div>
                 }
+                .into_any()
             })
             .collect::<Vec<_>>()
     };
 
-    view! { <div>{views}</div> }
+    view! {
+        <div>
+            {views} <Violations path=prefix.get_value() />
+        </div>
+    }
+    .into_any()
+}
+
+// Tabs for the members of a union, only the open one is encoded. The open
+// tab is stored in the form under the union's own name.
+#[component]
+fn UnionEditor(
+    union_layout: LayoutId,
+    expr: StoredValue<Expr>,
+    form_data: WriteSignal<HashMap<String, ReadSignal<String>>>,
+    prefix: StoredValue<String>,
+    initial: Value,
+) -> impl IntoView {
+    let members = expr
+        .read_value()
+        .get_type(union_layout)
+        .unwrap()
+        .fields
+        .clone();
+    // A value being edited again has a single member, a decoded one all.
+    let open = match &initial {
+        Value::Struct { fields } if fields.len() == 1 => fields[0].0.clone(),
+        _ => members[0].name.clone(),
+    };
+    let (active, set_active) = signal(open);
+    form_data.write().insert(prefix.get_value(), active);
+
+    let tabs = members
+        .iter()
+        .map(|member| {
+            let name = member.name.clone();
+            let selected = name.clone();
+            view! {
+                <button
+                    type="button"
+                    disabled=move || active.read() == selected
+                    on:click=move |_| set_active.set(name.clone())
+                >
+                    {member.name.clone()}
+                </button>
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let panels = members
+        .into_iter()
+        .map(|member| {
+            let name = StoredValue::new(if !prefix.read_value().is_empty() {
+                format!("{}.{}", prefix.read_value(), member.name)
+            } else {
+                member.name.clone()
+            });
+            let input = match &member.constant {
+                Some(constant) => view! {
+                    <div>
+                        <label>{name.get_value()}</label>
+                        <input type="text" readonly value=constant.to_string() />
+                    </div>
+                }
+                .into_any(),
+                None => {
+                    let initial = initial.field(&member.name).cloned();
+                    let initial = initial
+                        .or_else(|| expr.read_value().default_for_type(&member.ty))
+                        .unwrap();
+                    view! {
+                        <FieldInput
+                            ty=member.ty.clone()
+                            label=name.get_value()
+                            expr=expr
+                            form_data=form_data
+                            name=name
+                            initial=initial
+                            scaling=member.scaling.clone()
+                            unit=member.unit.clone()
+                        />
+                    }
+                    .into_any()
+                }
+            };
+            let shown = member.name;
+            view! {
+                <div style:display=move || if active.read() == shown { "block" } else { "none" }>
+                    {input}
+                </div>
+            }
+        })
+        .collect::<Vec<_>>();
+
+    view! {
+        <div>
+            <div>{tabs}</div>
+            {panels}
+            <Violations path=prefix.get_value() />
+        </div>
+    }
+}
+
+// Messages for the constraint violations at exactly `path`
+#[component]
+fn Violations(path: String) -> impl IntoView {
+    let violations = use_context::<Memo<Vec<Violation>>>().unwrap();
+    move || {
+        violations
+            .read()
+            .iter()
+            .filter(|violation| violation.path == path)
+            .map(|violation| view! { <div class="violation">{violation.message.clone()}</div> })
+            .collect::<Vec<_>>()
+    }
+}
+
+// Input for a single value of type `ty`, stored in the form under `name`.
+// Scaled fields are entered in engineering units, shown next to the input.
+#[component]
+fn FieldInput(
+    ty: Type,
+    label: String,
+    expr: StoredValue<Expr>,
+    form_data: WriteSignal<HashMap<String, ReadSignal<String>>>,
+    name: StoredValue<String>,
+    initial: Value,
+    scaling: Option<Scaling>,
+    unit: Option<String>,
+) -> impl IntoView {
+    // The input holds the number alone, the unit is shown next to it.
+    let initial = match initial {
+        Value::Scaled { value, .. } => Value::F64(value),
+        initial => initial,
+    };
+    match ty {
+        Type::Struct(inner_id) => view! {
+            <fieldset>
+                <legend>{label}</legend>
+
+                <StructBuilder
+                    struct_layout=inner_id
+                    expr=expr
+                    form_data=form_data
+                    prefix=name
+                    initial=initial
+                />
+            </fieldset>
+        }
+        .into_any(),
+
+        Type::Array(element, len) => {
+            let Value::Array(initial) = initial else {
+                unreachable!("default values match their type")
+            };
+            let entered = initial.len();
+            let element = StoredValue::new(*element);
+            let initial = StoredValue::new(initial);
+            let scaling = StoredValue::new(scaling);
+            let unit = StoredValue::new(unit);
+            let input = move |i: usize| {
+                let name = format!("{}[{i}]", name.get_value());
+                let initial = initial.read_value().get(i).cloned();
+                let initial = initial
+                    .or_else(|| expr.read_value().default_for_type(&element.read_value()))
+                    .unwrap();
+                view! {
+                    <FieldInput
+                        ty=element.get_value()
+                        label=name.clone()
+                        expr=expr
+                        form_data=form_data
+                        name=StoredValue::new(name)
+                        initial=initial
+                        scaling=scaling.get_value()
+                        unit=unit.get_value()
+                    />
+                }
+            };
+
+            let Some(len) = len else {
+                // Sized by another field, it takes as many elements as the
+                // form says, stored under `name#len`.
+                let (count, set_count) = signal(entered.to_string());
+                form_data
+                    .write()
+                    .insert(format!("{}#len", name.get_value()), count);
+                let elements = move || {
+                    (0..count.read().parse().unwrap_or(0))
+                        .map(input)
+                        .collect::<Vec<_>>()
+                };
+                return view! {
+                    <fieldset>
+                        <legend>{label}</legend>
+                        <div>
+                            <label>"elements"</label>
+                            <input
+                                type="number"
+                                step="1"
+                                min="0"
+                                value=count.get_untracked()
+                                on:input=move |ev| set_count.set(event_target_value(&ev))
+                            />
+                        </div>
+                        {elements}
+                    </fieldset>
+                }
+                .into_any();
+            };
+            let elements = (0..len).map(input).collect::<Vec<_>>();
+
+            view! {
+                <fieldset>
+                    <legend>{label}</legend>
+                    {elements}
+                </fieldset>
+            }
+            .into_any()
+        }
+
+        // The target is edited in place, the encoder picks where it goes.
+        // Whether it is null is stored under `name#null`.
+        Type::Pointer(pointer) => {
+            let Value::Pointer { target, .. } = initial else {
+                unreachable!("default values match their type")
+            };
+            let (null, set_null) = signal(target.is_none().to_string());
+            form_data
+                .write()
+                .insert(format!("{}#null", name.get_value()), null);
+            let target = target
+                .map(|target| *target)
+                .or_else(|| expr.read_value().default_for_type(&pointer.target))
+                .unwrap();
+            let pointer = StoredValue::new(*pointer);
+            let target = StoredValue::new(target);
+            let label = StoredValue::new(label);
+            let scaling = StoredValue::new(scaling);
+            let unit = StoredValue::new(unit);
+            let input = move || {
+                (null.get() == "false").then(|| {
+                    view! {
+                        <FieldInput
+                            ty=pointer.read_value().target.clone()
+                            label=format!("{} (placed automatically)", label.read_value())
+                            expr=expr
+                            form_data=form_data
+                            name=name
+                            initial=target.get_value()
+                            scaling=scaling.get_value()
+                            unit=unit.get_value()
+                        />
+                    }
+                })
+            };
+            view! {
+                <div>
+                    <label>{format!("{} is null", label.get_value())}</label>
+                    <input
+                        type="checkbox"
+                        checked=null.get_untracked() == "true"
+                        on:change=move |ev| set_null.set(event_target_checked(&ev).to_string())
+                    />
+                </div>
+                {input}
+            }
+            .into_any()
+        }
+
+        Type::Bool(_) => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    <input
+                        type="checkbox"
+                        checked=initial == Value::Bool(true)
+                        on:change=move |ev| set_value.set(event_target_checked(&ev).to_string())
+                    />
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+
+        Type::Enum(id) => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            let mut names = expr.read_value().enums[&id]
+                .values
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            // Keep values missing from the table selectable, they're flagged.
+            if let Value::Enum { raw, name: None } = &initial {
+                names.push(raw.to_string());
+            }
+            let options = names
+                .into_iter()
+                .map(|option| {
+                    let selected = option == initial.to_string();
+                    let text = option.clone();
+                    view! { <option value=option selected=selected>{text}</option> }
+                })
+                .collect::<Vec<_>>();
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    <select on:change=move |ev| set_value.set(event_target_value(&ev))>
+                        {options}
+                    </select>
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+
+        Type::I8
+        | Type::I16
+        | Type::I32
+        | Type::I64
+        | Type::VarInt
+        | Type::ZigZag
+        | Type::Bits { signed: true, .. } => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    <input
+                        type="number"
+                        step=if scaling.is_some() { "any" } else { "1" }
+                        value=initial.to_string()
+                        on:input=move |ev| set_value.set(event_target_value(&ev))
+                    />
+                    {unit}
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+
+        Type::U8
+        | Type::U16
+        | Type::U32
+        | Type::U64
+        | Type::UVarInt
+        | Type::Bits { signed: false, .. } => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    <input
+                        type="number"
+                        step=if scaling.is_some() { "any" } else { "1" }
+                        min=scaling.is_none().then_some("0")
+                        value=initial.to_string()
+                        on:input=move |ev| set_value.set(event_target_value(&ev))
+                    />
+                    {unit}
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+
+        Type::Fixed(fixed) => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    <input
+                        type="number"
+                        step=fixed.resolution()
+                        value=initial.to_string()
+                        on:input=move |ev| set_value.set(event_target_value(&ev))
+                    />
+                    <span>{format!("resolution {}", fixed.resolution())}</span>
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+
+        // Picked in UTC. Times finer than milliseconds, which the picker
+        // cannot hold, are typed in ISO 8601 instead.
+        Type::Timestamp(timestamp) => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            let input = match timestamp.unit.digits() {
+                digits @ ..=3 => {
+                    // The picker takes the time without the `Z`.
+                    let initial = initial.to_string().trim_end_matches('Z').to_string();
+                    view! {
+                        <input
+                            type="datetime-local"
+                            step=if digits == 0 { "1" } else { "0.001" }
+                            value=initial
+                            on:input=move |ev| set_value.set(event_target_value(&ev))
+                        />
+                    }
+                    .into_any()
+                }
+                _ => view! {
+                    <input
+                        type="text"
+                        value=initial.to_string()
+                        on:input=move |ev| set_value.set(event_target_value(&ev))
+                    />
+                }
+                .into_any(),
+            };
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    {input}
+                    <span>"UTC"</span>
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+
+        // Checked as typed, so a malformed address shows before submitting.
+        ty @ (Type::Ipv4 | Type::Ipv6 | Type::Mac | Type::Uuid) => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            let placeholder = match ty {
+                Type::Ipv4 => "192.168.0.1",
+                Type::Ipv6 => "2001:db8::1",
+                Type::Mac => "00:1a:2b:3c:4d:5e",
+                _ => "123e4567-e89b-12d3-a456-426614174000",
+            };
+            let error = move || {
+                Value::from_text(&expr.read_value(), &ty, &value.read())
+                    .err()
+                    .map(|error| view! { <div class="violation">{error.to_string()}</div> })
+            };
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    <input
+                        type="text"
+                        placeholder=placeholder
+                        value=initial.to_string()
+                        on:input=move |ev| set_value.set(event_target_value(&ev))
+                    />
+                    {error}
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+
+        // Hex or base64, checked against the length as typed.
+        Type::Bytes(len) => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            let status = move || match Value::from_text(&expr.read_value(), &ty, &value.read()) {
+                Ok(Value::Bytes(bytes)) if len.is_some_and(|len| bytes.len() != len) => {
+                    let message = format!("expected {} bytes, found {}", len.unwrap(), bytes.len());
+                    view! { <div class="violation">{message}</div> }.into_any()
+                }
+                Ok(Value::Bytes(bytes)) => {
+                    view! { <span>{format!("{} bytes", bytes.len())}</span> }.into_any()
+                }
+                Ok(_) => ().into_any(),
+                Err(error) => view! { <div class="violation">{error.to_string()}</div> }.into_any(),
+            };
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    <input
+                        type="text"
+                        placeholder="hex or base64"
+                        value=initial.to_string()
+                        on:input=move |ev| set_value.set(event_target_value(&ev))
+                    />
+                    {status}
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+
+        Type::F16 | Type::BF16 | Type::F32 | Type::F64 => {
+            let (value, set_value) = signal(initial.to_string());
+            form_data.write().insert(name.get_value(), value);
+
+            view! {
+                <div>
+                    <label>{label}</label>
+                    <input
+                        type="number"
+                        step="any"
+                        value=initial.to_string()
+                        on:input=move |ev| set_value.set(event_target_value(&ev))
+                    />
+                    {unit}
+                    <Violations path=name.get_value() />
+                </div>
+            }
+            .into_any()
+        }
+    }
 }
 
 // Convert form data into a nested Value struct
@@ -216,28 +911,98 @@ fn build_value_from_form(
     prefix: &str,
 ) -> Result<Value, Error> {
     let mut fields = Vec::new();
+    // Only the open member of a union is encoded.
+    let active = layout.union.then(|| form_data[prefix].read().clone());
 
-    for (name, ty) in &layout.fields {
+    for field in &layout.fields {
+        if active.as_ref().is_some_and(|active| *active != field.name) {
+            continue;
+        }
         let name = if !prefix.is_empty() {
-            format!("{prefix}.{name}")
+            format!("{prefix}.{}", field.name)
         } else {
-            name.clone()
+            field.name.clone()
         };
-        let data = form_data.get(&name);
-        let parsed_value = match ty {
-            Type::I8 => Value::I8(data.unwrap().read().parse()?),
-            Type::I16 => Value::I16(data.unwrap().read().parse()?),
-            Type::I32 => Value::I32(data.unwrap().read().parse()?),
-            Type::I64 => Value::I64(data.unwrap().read().parse()?),
-            Type::F32 => Value::F32(data.unwrap().read().parse()?),
-            Type::F64 => Value::F64(data.unwrap().read().parse()?),
-            Type::Struct(inner_name) => {
-                let inner_layout = expr.layouts.get(inner_name).unwrap();
-                build_value_from_form(form_data, inner_layout, expr, &name)?
+        if let Some(condition) = &field.condition
+            && !condition.check_fields(&fields)?
+        {
+            continue;
+        }
+        // Computed or filled in by the encoder, conditions never refer to them.
+        if field.is_computed() || field.padding {
+            continue;
+        }
+        let parsed_value = match &field.constant {
+            Some(constant) => constant.clone(),
+            None => {
+                let scaling = field.scaling.as_ref();
+                build_field_value(form_data, &field.ty, scaling, expr, &name)?
             }
         };
-        fields.push((name.clone(), parsed_value));
+        fields.push((field.name.clone(), parsed_value));
     }
 
     Ok(Value::Struct { fields })
 }
+
+fn build_field_value(
+    form_data: &HashMap<String, ReadSignal<String>>,
+    ty: &Type,
+    scaling: Option<&Scaling>,
+    expr: &Expr,
+    name: &str,
+) -> Result<Value, Error> {
+    Ok(match ty {
+        Type::Struct(inner_name) => {
+            let inner_layout = expr.layouts.get(inner_name).unwrap();
+            build_value_from_form(form_data, inner_layout, expr, name)?
+        }
+        Type::Array(element, len) => {
+            let len = match len {
+                Some(len) => *len,
+                None => form_data
+                    .get(&format!("{name}#len"))
+                    .unwrap()
+                    .read()
+                    .parse()?,
+            };
+            Value::Array(
+                (0..len)
+                    .map(|i| {
+                        let name = format!("{name}[{i}]");
+                        build_field_value(form_data, element, scaling, expr, &name)
+                    })
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        Type::Pointer(pointer) => {
+            let null: bool = form_data
+                .get(&format!("{name}#null"))
+                .unwrap()
+                .read()
+                .parse()?;
+            let target = if null {
+                None
+            } else {
+                Some(build_field_value(
+                    form_data,
+                    &pointer.target,
+                    scaling,
+                    expr,
+                    name,
+                )?)
+            };
+            Value::Pointer {
+                offset: None,
+                target: target.map(Box::new),
+            }
+        }
+        _ => {
+            let text = form_data.get(name).unwrap().read();
+            match scaling {
+                Some(scaling) => Value::from_scaled_text(expr, ty, scaling, &text)?,
+                None => Value::from_text(expr, ty, &text)?,
+            }
+        }
+    })
+}